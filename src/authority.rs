@@ -1,24 +1,215 @@
 use clacc::{
     Accumulator,
+    Witness,
     sha3::Shake128 as Map,
 };
 use gmp::mpz::Mpz;
-use rand::RngCore;
+use serde::{Serialize, Deserialize};
+use std::{
+    collections::HashSet,
+    fs,
+    path::PathBuf,
+    sync::{Arc, RwLock},
+};
 use tokio::sync::Mutex;
 use crate::{
-    permission::Permission,
-    request::{UpdateRequest, UpdateResponse, ActionRequest},
+    checkpoint::{Checkpoint, PublicKey, SignedCheckpoint, Signer},
+    condition::Attributes,
+    constant::CHECKPOINT_HISTORY,
+    delegation::{self, DELEGATE},
+    error::Error,
+    keystore::{KeyStore, SoftwareKeyStore},
+    permission::{Nonce, Permission},
+    request::{
+        ActionRequest,
+        BatchUpdateResponse,
+        DeleteRequest,
+        DeleteResponse,
+        RoleRequest,
+        UpdateRequest,
+        UpdateResponse,
+        ValuesResponse,
+        WitnessedActions,
+        WitnessedDelegation,
+    },
+    role::{Registry, Role},
+    store::{self, Log},
+    subject::ProofChecker,
+    util::now_millis,
+    verifier,
 };
 
-/// An Authority that controls the private key of an accumulator and is able
-/// to add and delete Permissions.
-pub struct Authority {
+/// The accumulation values of the Authority's Accumulators at the time of
+/// the latest `update` or `sync`.
+#[derive(Serialize, Deserialize)]
+struct Snapshot {
+
+    /// The sequence number of the last log entry reflected in the snapshot.
+    seq: u64,
+
+    /// The number of update windows that have been synchronized.
+    #[serde(default)]
+    window: u64,
 
     /// The Accumulator's public key.
     key: Mpz,
 
-    /// The Accumulator used to verify Permissions.
-    verifying: Accumulator<Mpz, Map>,
+    /// The value of the verifying Accumulator.
+    verifying: Mpz,
+
+    /// The value of the updating Accumulator.
+    updating: Mpz,
+
+    /// The value of the staging Accumulator.
+    staging: Mpz,
+
+    /// The Permissions deleted since they were last verifiable.
+    #[serde(default)]
+    deleted: Deleted,
+}
+
+/// The Permissions deleted from the staging Accumulator that the verifying
+/// Accumulator still contains.
+///
+/// Deletions are verified against the verifying Accumulator, since that is
+/// what the Worker's Witnesses are valid for. A Permission stays verifiable
+/// there until the deletion has been synchronized, so it is remembered here
+/// to keep it from being deleted from the staging Accumulator twice.
+#[derive(Serialize, Deserialize, Clone, Default)]
+struct Deleted {
+
+    /// The Permissions deleted since the latest `update`.
+    staged: HashSet<(Nonce, usize)>,
+
+    /// The Permissions deleted before the latest `update`, which are
+    /// forgotten at the next `sync`.
+    updating: HashSet<(Nonce, usize)>,
+}
+
+impl Deleted {
+
+    /// Return whether a Permission has been deleted.
+    fn contains(&self, perm: &Permission) -> bool {
+        let key = (perm.nonce, perm.version);
+        self.staged.contains(&key) || self.updating.contains(&key)
+    }
+
+    /// Remember that a Permission has been deleted.
+    fn insert(&mut self, perm: &Permission) {
+        self.staged.insert((perm.nonce, perm.version));
+    }
+
+    /// Return the deletions remembered after an `update`.
+    fn updated(&self) -> Self {
+        Deleted {
+            staged: HashSet::new(),
+            updating: self.staged.union(&self.updating).copied().collect(),
+        }
+    }
+
+    /// Return the deletions remembered after a `sync`.
+    fn synced(&self) -> Self {
+        Deleted {
+            staged: self.staged.clone(),
+            updating: HashSet::new(),
+        }
+    }
+}
+
+/// An operation on the staging Accumulator.
+#[derive(Serialize, Deserialize)]
+enum Operation {
+
+    /// A Permission was added.
+    Add(Permission),
+
+    /// A Permission was replaced by a new version.
+    Update(Permission, Witness<Mpz>, Permission),
+
+    /// A Permission was deleted.
+    Del(Permission, Witness<Mpz>),
+}
+
+/// An Operation recorded in the write-ahead log.
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    seq: u64,
+    op: Operation,
+}
+
+/// Durable storage for an Authority's state.
+///
+/// The staging Accumulator is mutated by every added, updated and deleted
+/// Permission, so those Operations are appended to a write-ahead log before
+/// they are applied. The values of all three Accumulators are snapshotted
+/// whenever they are copied during `update` and `sync`, after which the log
+/// is cleared. The Checkpoints issued at the same time are kept in a
+/// separate log.
+struct Storage {
+    dir: PathBuf,
+    log: Log<Entry>,
+    checkpoints: Log<SignedCheckpoint>,
+
+    /// The number of Checkpoints in the Checkpoint log.
+    recorded: usize,
+}
+
+impl Storage {
+
+    /// Durably record an Operation with a sequence number.
+    fn append(&mut self, seq: u64, op: Operation) -> Result<(), Error> {
+        self.log.append(&Entry { seq, op })
+    }
+
+    /// Durably record the accumulation values and clear the log.
+    fn snapshot(
+        &mut self,
+        seq: u64,
+        window: u64,
+        verifying: &Accumulator<Mpz, Map>,
+        updating: &Accumulator<Mpz, Map>,
+        staging: &Accumulator<Mpz, Map>,
+        deleted: &Deleted,
+    ) -> Result<(), Error> {
+        store::save(&self.dir.join("snapshot.json"), &Snapshot {
+            seq,
+            window,
+            key: verifying.get_public_key(),
+            verifying: verifying.get_value(),
+            updating: updating.get_value(),
+            staging: staging.get_value(),
+            deleted: deleted.clone(),
+        })?;
+        // Entries up to `seq` are skipped on replay, so a crash before the
+        // log is cleared is harmless.
+        self.log.clear()
+    }
+
+    /// Durably record a Checkpoint, given the history that is kept.
+    ///
+    /// The log is rewritten with just the history once it has grown to
+    /// twice its length.
+    fn record(
+        &mut self,
+        checkpoint: &SignedCheckpoint,
+        history: &[SignedCheckpoint],
+    ) -> Result<(), Error> {
+        if self.recorded >= 2 * CHECKPOINT_HISTORY {
+            self.checkpoints.clear()?;
+            for checkpoint in history {
+                self.checkpoints.append(checkpoint)?;
+            }
+            self.recorded = history.len();
+        }
+        self.checkpoints.append(checkpoint)?;
+        self.recorded += 1;
+        Ok(())
+    }
+}
+
+/// The Accumulators of an Authority that Permissions are added to and
+/// deleted from, along with their durable storage.
+struct State {
 
     /// The Accumulator containing Permissions whose Witnesses are currently
     /// being updated by the Worker.
@@ -28,106 +219,658 @@ pub struct Authority {
     /// Permissions.
     staging: Accumulator<Mpz, Map>,
 
-    /// Mutex locked while the Authority is operating on its Accumulators.
-    guard: Mutex<()>,
+    /// The number of Permission additions, updates and deletions made so
+    /// far.
+    seq: u64,
+
+    /// The number of update windows that have been synchronized.
+    window: u64,
+
+    /// The Permissions deleted that are still verifiable.
+    deleted: Deleted,
+
+    /// The most recent Checkpoints issued at `update` and `sync`, oldest
+    /// first.
+    checkpoints: Vec<SignedCheckpoint>,
+
+    /// The store performing operations with the Accumulator's private key.
+    keys: Box<dyn KeyStore>,
+
+    /// The Accumulator being prepared by a key rotation, if one is in
+    /// progress.
+    next: Option<Next>,
+
+    /// The current definitions of the roles that Permissions may carry.
+    roles: Registry,
+
+    /// The durable storage, if the Authority was opened from a directory.
+    storage: Option<Storage>,
+}
+
+/// An Accumulator with a new private key that the Authority is rotating to.
+struct Next {
+
+    /// The store holding the new private key, which is not committed until
+    /// the rotation is.
+    keys: Box<dyn KeyStore>,
+
+    /// The Accumulator containing every current Permission.
+    acc: Accumulator<Mpz, Map>,
+
+    /// The number of Permission changes made when the rotation began.
+    seq: u64,
+}
+
+/// An Authority that controls the private key of an accumulator and is able
+/// to add and delete Permissions.
+///
+/// All methods take `&self`, so an Authority may be shared between tasks
+/// with an `Arc`.
+pub struct Authority {
+
+    /// The key Checkpoints are signed with.
+    signer: Signer,
+
+    /// The Accumulator used to verify Permissions.
+    ///
+    /// Actions only read the verifying Accumulator, so they clone the
+    /// reference under a read lock and verify against it without holding
+    /// any lock. `sync` swaps in a new Accumulator rather than mutating it.
+    verifying: RwLock<Arc<Accumulator<Mpz, Map>>>,
+
+    /// The remaining Accumulators, locked while the Authority is operating on
+    /// them.
+    state: Mutex<State>,
+
+    /// Checks the proofs of subjects requesting actions.
+    proofs: ProofChecker,
+}
+
+impl Default for Authority {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Authority {
 
     /// Create a new Authority.
     pub fn new() -> Self {
-        // Generate an accumulator. In a real world scenario, the
-        // Accumulator's private key would be generated and sharded as part of
-        // a key ceremony. Security officers entrusted with the shards would
-        // then submit their part of the key to the Authority server in order
-        // to reconstitute in a secure hardware environment. This is out-of-
-        // scope for the purposes of this demonstration, so an Accumulator is
-        // instead initialized from a random private key.
-        let mut rng = rand::thread_rng();
-        let (acc, _, _) = Accumulator::<Mpz, Map>::with_random_key(
-            |bytes| rng.fill_bytes(bytes),
-            None,
-        );
+        // Generate a random private key. A deployment that shards the
+        // private key generates it with `ceremony::generate` instead and
+        // opens the Authority with `open_with_key` once the shares have been
+        // combined.
+        Self::with_key_store(Box::new(SoftwareKeyStore::generate()))
+    }
+
+    /// Create a new Authority whose private key operations are performed by
+    /// a KeyStore.
+    pub fn with_key_store(keys: Box<dyn KeyStore>) -> Self {
+        let acc = Accumulator::<Mpz, Map>::with_public_key(keys.public_key());
+        let signer = Signer::generate();
         // Allocate the Authority using the public key and three copies of the
         // Accumulator for each phase of the update process.
-        Authority {
-            key: acc.get_public_key().clone(),
-            verifying: acc.clone(),
+        let mut state = State {
             updating: acc.clone(),
             staging: acc.clone(),
-            guard: Mutex::new(()),
+            seq: 0,
+            window: 0,
+            deleted: Deleted::default(),
+            checkpoints: Vec::new(),
+            keys,
+            next: None,
+            roles: Registry::new(),
+            storage: None,
+        };
+        // Issue the first Checkpoint.
+        let checkpoint = Self::sign(&signer, &acc, &state);
+        state.checkpoints.push(checkpoint);
+        Authority {
+            proofs: ProofChecker::new(&signer.public_key()),
+            signer,
+            verifying: RwLock::new(Arc::new(acc)),
+            state: Mutex::new(state),
         }
     }
 
+    /// Open an Authority whose state is persisted in a directory.
+    ///
+    /// The first time a directory is opened, a random private key and
+    /// signing key are generated and written to it. Subsequent opens restore
+    /// the keys, the latest snapshot of the Accumulators and replay the
+    /// Operations logged since, so that Witnesses held by the Worker remain
+    /// valid across restarts.
+    ///
+    /// The private key is encrypted with `secret`, a hex encoded 256 bit
+    /// key, as by `SoftwareKeyStore::open_encrypted`.
+    pub fn open(dir: &str, secret: &str) -> Result<Self, Error> {
+        let path = PathBuf::from(dir);
+        if fs::create_dir_all(&path).is_err() {
+            return Err(Error::Storage(
+                "could not create data directory".to_owned(),
+            ));
+        }
+        // Restore the private key, generating it if this is the first time
+        // the directory has been opened. The key file is only accessible to
+        // the owner of the process.
+        let keys = SoftwareKeyStore::open_encrypted(
+            &path.join("key.enc"),
+            secret,
+        )?;
+        Self::open_with_key_store(dir, Box::new(keys))
+    }
+
+    /// Open an Authority whose state is persisted in a directory, using a
+    /// private key that is not stored in it.
+    ///
+    /// This is how a sealed Authority is opened once its private key has
+    /// been reconstructed from the shares created by a key ceremony. Only
+    /// the public key is written to the directory, and it must match the
+    /// private key on subsequent opens.
+    pub fn open_with_key(dir: &str, p: Mpz, q: Mpz) -> Result<Self, Error> {
+        Self::open_with_key_store(dir, Box::new(SoftwareKeyStore::new(p, q)))
+    }
+
+    /// Open an Authority whose state is persisted in a directory, with its
+    /// private key operations performed by a KeyStore.
+    ///
+    /// The KeyStore's public key is written to the directory, and must match
+    /// on subsequent opens.
+    pub fn open_with_key_store(
+        dir: &str,
+        keys: Box<dyn KeyStore>,
+    ) -> Result<Self, Error> {
+        let dir = PathBuf::from(dir);
+        if fs::create_dir_all(&dir).is_err() {
+            return Err(Error::Storage(
+                "could not create data directory".to_owned(),
+            ));
+        }
+        // Restore the signing key, generating it if this is the first time
+        // the directory has been opened.
+        let signer_path = dir.join("signing_key.json");
+        let signer = match store::load::<String>(&signer_path)? {
+            Some(secret) => Signer::from_secret(&secret)?,
+            None => {
+                let signer = Signer::generate();
+                store::save_private(&signer_path, &signer.secret())?;
+                signer
+            },
+        };
+        let key = keys.public_key();
+        let acc = Accumulator::<Mpz, Map>::with_public_key(key.clone());
+        // Remember the public key so that the directory cannot be opened
+        // with another private key.
+        let public_path = dir.join("public_key.json");
+        match store::load::<Mpz>(&public_path)? {
+            Some(public) if public != key => {
+                return Err(Error::Storage(
+                    "private key does not match public key".to_owned(),
+                ));
+            },
+            Some(_) => {},
+            None => store::save(&public_path, &key)?,
+        }
+        let mut verifying = acc.clone();
+        let mut state = State {
+            updating: acc.clone(),
+            staging: acc,
+            seq: 0,
+            window: 0,
+            deleted: Deleted::default(),
+            checkpoints: Vec::new(),
+            keys,
+            next: None,
+            roles: Registry::open(&dir.join("roles.json"))?,
+            storage: None,
+        };
+        // Restore the accumulation values from the latest snapshot.
+        let snapshot: Option<Snapshot> = store::load(
+            &dir.join("snapshot.json"),
+        )?;
+        if let Some(snapshot) = snapshot {
+            if snapshot.key != key {
+                return Err(Error::Storage(
+                    "snapshot does not match private key".to_owned(),
+                ));
+            }
+            verifying.set_value(snapshot.verifying);
+            state.updating.set_value(snapshot.updating);
+            state.staging.set_value(snapshot.staging);
+            state.seq = snapshot.seq;
+            state.window = snapshot.window;
+            state.deleted = snapshot.deleted;
+        }
+        // Replay the Operations that were logged after the snapshot.
+        let (log, entries) = Log::<Entry>::open(&dir.join("wal.jsonl"))?;
+        for entry in entries {
+            if entry.seq <= state.seq {
+                continue;
+            }
+            match entry.op {
+                Operation::Add(perm) => {
+                    state.staging.add(perm);
+                },
+                // The deletions were verified before they were logged.
+                Operation::Update(perm, _, update) => {
+                    let Ok(mut staging) = Self::remove(&state, &perm) else {
+                        return Err(Error::Storage(
+                            "could not replay log".to_owned(),
+                        ));
+                    };
+                    staging.add(update);
+                    state.staging = staging;
+                    state.deleted.insert(&perm);
+                },
+                Operation::Del(perm, _) => {
+                    let Ok(staging) = Self::remove(&state, &perm) else {
+                        return Err(Error::Storage(
+                            "could not replay log".to_owned(),
+                        ));
+                    };
+                    state.staging = staging;
+                    state.deleted.insert(&perm);
+                },
+            }
+            state.seq = entry.seq;
+        }
+        // Restore the Checkpoint history.
+        let (checkpoints, mut history) = Log::<SignedCheckpoint>::open(
+            &dir.join("checkpoints.jsonl"),
+        )?;
+        let recorded = history.len();
+        let keep = recorded.saturating_sub(CHECKPOINT_HISTORY);
+        state.checkpoints = history.split_off(keep);
+        state.storage = Some(Storage { dir, log, checkpoints, recorded });
+        // Issue the first Checkpoint if this is the first time the
+        // directory has been opened.
+        if state.checkpoints.is_empty() {
+            let checkpoint = Self::sign(&signer, &verifying, &state);
+            Self::record(&mut state, checkpoint)?;
+        }
+        Ok(Authority {
+            proofs: ProofChecker::new(&signer.public_key()),
+            signer,
+            verifying: RwLock::new(Arc::new(verifying)),
+            state: Mutex::new(state),
+        })
+    }
+
     /// Return the Accumulator's public key.
-    pub fn get_key(&self) -> &Mpz {
-        &self.key
+    pub fn get_key(&self) -> Mpz {
+        self.verifying().get_public_key()
+    }
+
+    /// Return the public key Checkpoints are signed with.
+    pub fn get_signing_key(&self) -> PublicKey {
+        self.signer.public_key()
+    }
+
+    /// Return the current accumulation values.
+    pub async fn values(&self) -> ValuesResponse {
+        let state = self.state.lock().await;
+        ValuesResponse {
+            verifying: self.verifying().get_value(),
+            updating: state.updating.get_value(),
+            staging: state.staging.get_value(),
+        }
+    }
+
+    /// Return the Checkpoint issued at the latest `update` or `sync`.
+    pub async fn checkpoint(&self) -> SignedCheckpoint {
+        let state = self.state.lock().await;
+        state.checkpoints.last().unwrap().clone()
+    }
+
+    /// Return the most recent Checkpoints issued at `update` and `sync`,
+    /// oldest first.
+    pub async fn checkpoints(&self) -> Vec<SignedCheckpoint> {
+        self.state.lock().await.checkpoints.clone()
+    }
+
+    /// Return the current definitions of the roles.
+    pub async fn roles(&self) -> Vec<Role> {
+        self.state.lock().await.roles.list()
+    }
+
+    /// Define a role, or redefine it with the next version.
+    ///
+    /// Permissions carrying an earlier version of the role are not changed,
+    /// but may only be updated to carry the new version.
+    pub async fn define_role(&self, req: RoleRequest) -> Result<Role, Error> {
+        self.state.lock().await.roles.define(req)
+    }
+
+    /// Internal helper to get the current verifying Accumulator.
+    fn verifying(&self) -> Arc<Accumulator<Mpz, Map>> {
+        Arc::clone(&self.verifying.read().unwrap())
+    }
+
+    /// Internal helper to sign a Checkpoint of the current state.
+    fn sign(
+        signer: &Signer,
+        verifying: &Accumulator<Mpz, Map>,
+        state: &State,
+    ) -> SignedCheckpoint {
+        signer.sign(Checkpoint {
+            window: state.window,
+            seq: state.seq,
+            key: verifying.get_public_key(),
+            verifying: verifying.get_value(),
+            staging: state.staging.get_value(),
+            timestamp: now_millis(),
+        })
+    }
+
+    /// Internal helper to return a copy of the staging Accumulator with a
+    /// Permission deleted.
+    ///
+    /// The Permission's membership must be verified against the verifying
+    /// Accumulator by a Witness, which is what the Worker's Witnesses are
+    /// valid for. A Witness for the staging Accumulator would be invalidated
+    /// by every change made since the window began.
+    fn del(
+        state: &State,
+        verifying: &Accumulator<Mpz, Map>,
+        perm: &Permission,
+        witness: &Witness<Mpz>,
+    ) -> Result<Accumulator<Mpz, Map>, Error> {
+        verifier::verify_membership(verifying, perm, witness)?;
+        Self::remove(state, perm)
+    }
+
+    /// Internal helper to return a copy of the staging Accumulator with a
+    /// Permission deleted by the private key held by the KeyStore.
+    ///
+    /// The private key deletes any element, so the Permission must be known
+    /// to be in the staging Accumulator. It is if it is verifiable and has
+    /// not been deleted since.
+    fn remove(
+        state: &State,
+        perm: &Permission,
+    ) -> Result<Accumulator<Mpz, Map>, Error> {
+        if state.deleted.contains(perm) {
+            return Err(Error::WitnessVerification);
+        }
+        let mut staging = state.staging.clone();
+        let value = state.keys.del(staging.get_value(), perm.clone())?;
+        staging.set_value(value);
+        Ok(staging)
+    }
+
+    /// Internal helper to add a Checkpoint to the history.
+    fn record(
+        state: &mut State,
+        checkpoint: SignedCheckpoint,
+    ) -> Result<(), Error> {
+        if let Some(storage) = &mut state.storage {
+            storage.record(&checkpoint, &state.checkpoints)?;
+        }
+        state.checkpoints.push(checkpoint);
+        if state.checkpoints.len() > CHECKPOINT_HISTORY {
+            state.checkpoints.remove(0);
+        }
+        Ok(())
     }
 
     /// Add a Permission.
     pub async fn add_permission(
-        &mut self,
-        mut perm: Permission,
-    ) -> Permission {
+        &self,
+        perm: Permission,
+    ) -> Result<Permission, Error> {
+        // Lock the Mutex.
+        let mut state = self.state.lock().await;
+        Self::stage_add(&mut state, perm)
+    }
+
+    /// Add a batch of Permissions in a single pass, returning the outcome
+    /// of each in order.
+    ///
+    /// The lock is only taken once, so a batch costs little more than its
+    /// additions to the staging Accumulator. A Permission that cannot be
+    /// added does not prevent the others from being added.
+    pub async fn add_permissions(
+        &self,
+        perms: Vec<Permission>,
+    ) -> Vec<Result<Permission, Error>> {
         // Lock the Mutex.
-        let _guard = self.guard.lock().await;
+        let mut state = self.state.lock().await;
+        perms.into_iter()
+            .map(|perm| Self::stage_add(&mut state, perm))
+            .collect()
+    }
+
+    /// Internal helper to add a Permission to the staging Accumulator.
+    fn stage_add(
+        state: &mut State,
+        mut perm: Permission,
+    ) -> Result<Permission, Error> {
+        // Ensure the Permission can become valid.
+        perm.validate()?;
+        // Ensure the Permission carries the current definitions of its
+        // roles.
+        state.roles.check(&perm)?;
         // Assign a random Nonce that prevents other Permissions from
         // overwriting this Permission in the future.
         perm.nonce = rand::random::<u64>().into();
+        // Log the addition.
+        let seq = state.seq + 1;
+        if let Some(storage) = &mut state.storage {
+            storage.append(seq, Operation::Add(perm.clone()))?;
+        }
         // Add the Permission to the staging Accumulator.
-        self.staging.add(perm.clone());
+        state.staging.add(perm.clone());
+        state.seq = seq;
         // Return the Permission with the new Nonce.
-        perm
+        Ok(perm)
+    }
+
+    /// Add a child Permission delegated from a Permission that is part of
+    /// the Accumulation.
+    ///
+    /// The parent must be verified by its Witness and be within its validity
+    /// period, and, if it is bound to a subject, the delegation must be
+    /// requested by it. The child is derived by `delegation::derive`.
+    pub async fn delegate(
+        &self,
+        req: WitnessedDelegation,
+    ) -> Result<Permission, Error> {
+        let WitnessedDelegation { req, witness } = req;
+        // Verify the parent against the verifying Accumulator.
+        let verifying = self.verifying();
+        verifier::verify_membership(&verifying, &req.perm, &witness)?;
+        req.perm.check_validity(now_millis())?;
+        let child = delegation::derive(&req.perm, &req)?;
+        // Check the proof last, like `action` does.
+        self.proofs.check(
+            &req.perm,
+            DELEGATE,
+            &Attributes::new(),
+            req.proof.as_ref(),
+        )?;
+        // Add the child like any other Permission.
+        self.add_permission(child).await
     }
 
     /// Update an existing Permission.
     pub async fn update_permission(
-        &mut self,
+        &self,
         req: UpdateRequest,
-    ) -> Result<UpdateResponse, &'static str> {
+    ) -> Result<UpdateResponse, Error> {
+        // Lock the Mutex.
+        let mut state = self.state.lock().await;
+        let verifying = self.verifying();
+        Self::stage_update(&mut state, &verifying, &req)?;
+        // Return the latest accumulation value.
+        Ok(UpdateResponse {
+            req,
+            checkpoint: Self::sign(&self.signer, &verifying, &state),
+        })
+    }
+
+    /// Update a batch of existing Permissions in a single pass.
+    ///
+    /// The response carries the outcome of each update in order, the
+    /// updates that were applied and a single Checkpoint of the accumulation
+    /// value after all of them. An update that cannot be applied does not
+    /// prevent the others from being applied.
+    pub async fn update_permissions(
+        &self,
+        reqs: Vec<UpdateRequest>,
+    ) -> BatchUpdateResponse {
+        // Lock the Mutex.
+        let mut state = self.state.lock().await;
+        let verifying = self.verifying();
+        let mut results = Vec::with_capacity(reqs.len());
+        let mut applied = Vec::new();
+        for req in reqs {
+            let res = Self::stage_update(&mut state, &verifying, &req);
+            let res = res.map(|_| req.update.clone());
+            if res.is_ok() {
+                applied.push(req);
+            }
+            results.push(res.into());
+        }
+        // Return the latest accumulation value.
+        BatchUpdateResponse {
+            results,
+            reqs: applied,
+            checkpoint: Self::sign(&self.signer, &verifying, &state),
+        }
+    }
+
+    /// Internal helper to replace a Permission in the staging Accumulator
+    /// with its new version.
+    fn stage_update(
+        state: &mut State,
+        verifying: &Accumulator<Mpz, Map>,
+        req: &UpdateRequest,
+    ) -> Result<(), Error> {
         // Ensure the new Permission's Nonce matches the old Permission's
         // Nonce.
         if req.update.nonce != req.perm.nonce {
-            return Err("nonce mismatch");
+            return Err(Error::NonceMismatch);
         }
         // Ensure the new Permission's version is greater than the old
         // Permission's version.
         if req.update.version <= req.perm.version {
-            return Err("new version must be greater than old version");
+            return Err(Error::VersionRegression);
         }
+        // Ensure the new Permission can become valid.
+        req.update.validate()?;
+        // Ensure the new Permission carries the current definitions of its
+        // roles.
+        state.roles.check(&req.update)?;
+        // Delete the old Permission from a copy of the staging Accumulator.
+        let mut staging = Self::del(state, verifying, &req.perm, &req.witness)?;
+        // Add the new Permission to the copy.
+        staging.add(req.update.clone());
+        // Log the update and commit the copy.
+        let seq = state.seq + 1;
+        if let Some(storage) = &mut state.storage {
+            storage.append(seq, Operation::Update(
+                req.perm.clone(),
+                req.witness.clone(),
+                req.update.clone(),
+            ))?;
+        }
+        state.staging = staging;
+        state.deleted.insert(&req.perm);
+        state.seq = seq;
+        Ok(())
+    }
+
+    /// Delete an existing Permission.
+    pub async fn delete_permission(
+        &self,
+        req: DeleteRequest,
+    ) -> Result<DeleteResponse, Error> {
         // Lock the Mutex.
-        let _guard = self.guard.lock().await;
-        // Delete the old Permission from the staging Accumulator.
-        match self.staging.del(req.perm.clone(), req.witness.clone()) {
-            Err(_) => return Err("could not delete permission"),
-            _ => (),
-        };
-        // Add the new Permission to the staging Accumulator.
-        self.staging.add(req.update.clone());
+        let mut state = self.state.lock().await;
+        let verifying = self.verifying();
+        // Delete the Permission from a copy of the staging Accumulator.
+        let staging = Self::del(&state, &verifying, &req.perm, &req.witness)?;
+        // Log the deletion and commit the copy.
+        let seq = state.seq + 1;
+        if let Some(storage) = &mut state.storage {
+            storage.append(seq, Operation::Del(
+                req.perm.clone(),
+                req.witness.clone(),
+            ))?;
+        }
+        state.staging = staging;
+        state.deleted.insert(&req.perm);
+        state.seq = seq;
         // Return the latest accumulation value.
-        Ok(UpdateResponse {
-            req: req,
-            value: self.staging.get_value().clone(),
+        Ok(DeleteResponse {
+            req,
+            checkpoint: Self::sign(&self.signer, &verifying, &state),
         })
     }
 
-    /// Perform an action if a given Permission is part of the Accumulation.
+    /// Perform an action if a given Permission is part of the Accumulation,
+    /// the request's attributes meet its conditions and, if it is bound to
+    /// a subject, the action is requested by it.
     pub async fn action(
         &self,
         req: ActionRequest,
-    ) -> Result<(), &'static str> {
-        // Lock the Mutex.
-        let _guard = self.guard.lock().await;
-        // Verify the Permission is part of the verifying Accumulator.
-        match self.verifying.verify(req.perm.clone(), req.witness.clone()) {
-            Err(_) => return Err("could not verify permission"),
-            _ => (),
-        };
-        // Ensure the requested action is in the actions list.
-        match req.perm.actions.iter().find(|&action| action == &req.action) {
-            Some(_) => Ok(()),
-            None => Err("permission not granted to perform action"),
+    ) -> Result<(), Error> {
+        // Verify the Permission against the verifying Accumulator. Other
+        // actions may be verified concurrently.
+        let verifying = self.verifying();
+        verifier::verify(
+            &verifying,
+            &req.perm,
+            &req.witness,
+            &req.action,
+            &req.attributes,
+        )?;
+        // Check the proof last, so that it is only recorded as seen once
+        // the action is otherwise allowed.
+        self.proofs.check(
+            &req.perm,
+            &req.action,
+            &req.attributes,
+            req.proof.as_ref(),
+        )
+    }
+
+    /// Decide every action of a list for every Permission of a list.
+    ///
+    /// Each Permission's Witness is verified once, after which each action
+    /// is checked and its proof verified like with `action`. The decisions
+    /// are returned by Permission and then by action, so a Permission that
+    /// fails verification or an action that is refused does not affect the
+    /// other decisions.
+    pub async fn actions(
+        &self,
+        req: WitnessedActions,
+    ) -> Result<Vec<Vec<Result<(), Error>>>, Error> {
+        let WitnessedActions { req, witnesses } = req;
+        if witnesses.len() != req.perms.len() {
+            return Err(Error::BadRequest);
         }
+        // Verify the Permissions against the verifying Accumulator. Other
+        // actions may be verified concurrently.
+        let verifying = self.verifying();
+        let rows = req.perms.iter().zip(witnesses).enumerate();
+        Ok(rows.map(|(i, (perm, witness))| {
+            let member = match witness {
+                Some(witness) => {
+                    verifier::verify_membership(&verifying, perm, &witness)
+                },
+                None => Err(Error::UnknownPermission),
+            };
+            req.actions.iter().enumerate().map(|(j, action)| {
+                member.clone()?;
+                verifier::check(perm, action, &req.attributes)?;
+                // Check the proof last, like `action` does.
+                let proof = req.proofs.get(i)
+                    .and_then(|row| row.get(j))
+                    .and_then(Option::as_ref);
+                self.proofs.check(perm, action, &req.attributes, proof)
+            }).collect()
+        }).collect())
     }
 
     /// Copy the current staging Accumulator to the updating Accumulator.
@@ -135,9 +878,25 @@ impl Authority {
     /// This should be called when the Worker begins updating Witnesses so
     /// that the updating Accumulator captures all Permission additions and
     /// deletions made during the update window.
-    pub async fn update(&mut self) {
-        let _guard = self.guard.lock().await;
-        self.updating = self.staging.clone();
+    pub async fn update(&self) -> Result<(), Error> {
+        let mut guard = self.state.lock().await;
+        let state = &mut *guard;
+        let verifying = self.verifying();
+        let deleted = state.deleted.updated();
+        if let Some(storage) = &mut state.storage {
+            storage.snapshot(
+                state.seq,
+                state.window,
+                &verifying,
+                &state.staging,
+                &state.staging,
+                &deleted,
+            )?;
+        }
+        state.updating = state.staging.clone();
+        state.deleted = deleted;
+        let checkpoint = Self::sign(&self.signer, &verifying, state);
+        Self::record(state, checkpoint)
     }
 
     /// Copy the current updating Accumulator to the verifying Accumulator.
@@ -146,8 +905,313 @@ impl Authority {
     /// Witnesses so that the verifying Accumulator reflects all additions
     /// and deletions that have been captured during the previous update
     /// window.
-    pub async fn sync(&mut self) {
-        let _guard = self.guard.lock().await;
-        self.verifying = self.updating.clone();
+    pub async fn sync(&self) -> Result<(), Error> {
+        let mut guard = self.state.lock().await;
+        let state = &mut *guard;
+        let window = state.window + 1;
+        let deleted = state.deleted.synced();
+        if let Some(storage) = &mut state.storage {
+            storage.snapshot(
+                state.seq,
+                window,
+                &state.updating,
+                &state.updating,
+                &state.staging,
+                &deleted,
+            )?;
+        }
+        let verifying = Arc::new(state.updating.clone());
+        *self.verifying.write().unwrap() = Arc::clone(&verifying);
+        state.window = window;
+        state.deleted = deleted;
+        let checkpoint = Self::sign(&self.signer, &verifying, state);
+        Self::record(state, checkpoint)
+    }
+
+    /// Begin rotating to a new private key.
+    ///
+    /// A new private key is generated by the KeyStore and every current
+    /// Permission is added to a new Accumulator. The returned Checkpoint
+    /// carries the new public key and accumulation value, against which the
+    /// Worker recomputes the Witnesses. The old key stays in use until
+    /// `commit_rotation` is called.
+    ///
+    /// Every change must have been synchronized, and the given Permissions
+    /// must be exactly the Permissions in the accumulation. This is checked
+    /// by accumulating them under the current key, so that the new key
+    /// cannot be made to carry a Permission that was never added or has
+    /// been deleted.
+    pub async fn begin_rotation(
+        &self,
+        perms: Vec<Permission>,
+    ) -> Result<SignedCheckpoint, Error> {
+        let mut guard = self.state.lock().await;
+        let state = &mut *guard;
+        let verifying = self.verifying();
+        if state.staging.get_value() != verifying.get_value()
+            || state.updating.get_value() != verifying.get_value() {
+            return Err(Error::Rotation(
+                "changes have not been synchronized".to_owned(),
+            ));
+        }
+        // Ensure the Permissions rebuild the verifying accumulation value.
+        let mut current = Accumulator::<Mpz, Map>::with_public_key(
+            verifying.get_public_key(),
+        );
+        for perm in perms.iter() {
+            current.add(perm.clone());
+        }
+        if current.get_value() != verifying.get_value() {
+            return Err(Error::Rotation(
+                "permissions do not match the accumulation".to_owned(),
+            ));
+        }
+        // Add every Permission to an Accumulator with the new key.
+        let keys = state.keys.generate()?;
+        let mut acc = Accumulator::<Mpz, Map>::with_public_key(
+            keys.public_key(),
+        );
+        for perm in perms {
+            acc.add(perm);
+        }
+        let checkpoint = self.signer.sign(Checkpoint {
+            window: state.window,
+            seq: state.seq,
+            key: acc.get_public_key(),
+            verifying: acc.get_value(),
+            staging: acc.get_value(),
+            timestamp: now_millis(),
+        });
+        state.next = Some(Next { keys, acc, seq: state.seq });
+        Ok(checkpoint)
+    }
+
+    /// Switch over to the private key of the rotation in progress.
+    ///
+    /// The new key is persisted and all three Accumulators are replaced
+    /// with the one prepared by `begin_rotation`. This counts as the close
+    /// of an update window, so verifiers accept the new key.
+    pub async fn commit_rotation(&self) -> Result<(), Error> {
+        let mut guard = self.state.lock().await;
+        let state = &mut *guard;
+        let next = match state.next.take() {
+            Some(next) => next,
+            None => {
+                return Err(Error::Rotation(
+                    "no rotation in progress".to_owned(),
+                ));
+            },
+        };
+        if next.seq != state.seq {
+            return Err(Error::Rotation(
+                "permissions changed during the rotation".to_owned(),
+            ));
+        }
+        let window = state.window + 1;
+        next.keys.commit()?;
+        if let Some(storage) = &mut state.storage {
+            store::save(
+                &storage.dir.join("public_key.json"),
+                &next.acc.get_public_key(),
+            )?;
+            let acc = &next.acc;
+            let deleted = Deleted::default();
+            storage.snapshot(state.seq, window, acc, acc, acc, &deleted)?;
+        }
+        let verifying = Arc::new(next.acc.clone());
+        *self.verifying.write().unwrap() = Arc::clone(&verifying);
+        state.updating = next.acc.clone();
+        state.staging = next.acc;
+        state.keys = next.keys;
+        state.window = window;
+        state.deleted = Deleted::default();
+        let checkpoint = Self::sign(&self.signer, &verifying, state);
+        Self::record(state, checkpoint)
+    }
+
+    /// Abandon the rotation in progress, if any.
+    pub async fn abort_rotation(&self) {
+        self.state.lock().await.next = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str =
+        "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+
+    /// Return a fresh temporary data directory.
+    fn temp_dir() -> String {
+        std::env::temp_dir()
+            .join(format!(
+                "compauth-authority-{}-{}",
+                std::process::id(),
+                rand::random::<u64>(),
+            ))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    fn perm(action: &str) -> Permission {
+        serde_json::from_value(serde_json::json!({
+            "nonce": 0,
+            "actions": [action],
+            "version": 0,
+        })).unwrap()
+    }
+
+    #[tokio::test]
+    async fn open_restores_snapshot_and_log() {
+        let dir = temp_dir();
+        let auth = Authority::open(&dir, SECRET).unwrap();
+        // The first addition is captured by the snapshot taken by `update`
+        // and `sync`, the second is only in the log.
+        auth.add_permission(perm("tick")).await.unwrap();
+        auth.update().await.unwrap();
+        auth.sync().await.unwrap();
+        auth.add_permission(perm("tock")).await.unwrap();
+        let key = auth.get_key();
+        let values = auth.values().await;
+        let checkpoint = auth.checkpoint().await;
+        drop(auth);
+        let auth = Authority::open(&dir, SECRET).unwrap();
+        assert_eq!(auth.get_key(), key);
+        let restored = auth.values().await;
+        assert_eq!(restored.verifying, values.verifying);
+        assert_eq!(restored.updating, values.updating);
+        assert_eq!(restored.staging, values.staging);
+        assert_eq!(
+            serde_json::to_value(auth.checkpoint().await).unwrap(),
+            serde_json::to_value(checkpoint).unwrap(),
+        );
+    }
+
+    /// Return a Witness for a Permission that is valid for the accumulation
+    /// of a list of Permissions.
+    fn witness(
+        auth: &Authority,
+        perms: &[Permission],
+        perm: &Permission,
+    ) -> Witness<Mpz> {
+        let mut acc = Accumulator::<Mpz, Map>::with_public_key(
+            auth.get_key(),
+        );
+        for other in perms.iter().filter(|other| *other != perm) {
+            acc.add(other.clone());
+        }
+        acc.add(perm.clone())
+    }
+
+    /// Return the next version of a Permission.
+    fn next(perm: &Permission) -> Permission {
+        Permission {
+            version: perm.version + 1,
+            ..perm.clone()
+        }
+    }
+
+    #[tokio::test]
+    async fn deletions_are_verified_against_verifying() {
+        let dir = temp_dir();
+        let auth = Authority::open(&dir, SECRET).unwrap();
+        let mut perms = Vec::new();
+        for action in ["tick", "tock", "tack"] {
+            perms.push(auth.add_permission(perm(action)).await.unwrap());
+        }
+        auth.update().await.unwrap();
+        auth.sync().await.unwrap();
+        let (tick, tock, tack) = (&perms[0], &perms[1], &perms[2]);
+        // An addition earlier in the window does not invalidate the
+        // Witnesses of the deletions.
+        let added = auth.add_permission(perm("tuck")).await.unwrap();
+        let res = auth.update_permissions(vec![
+            UpdateRequest {
+                perm: tick.clone(),
+                witness: witness(&auth, &perms, tick),
+                update: next(tick),
+            },
+            UpdateRequest {
+                perm: tack.clone(),
+                witness: witness(&auth, &perms, tack),
+                update: next(tack),
+            },
+        ]).await;
+        assert!(res.results.iter().all(|res| res.error.is_none()));
+        let delete = DeleteRequest {
+            perm: tock.clone(),
+            witness: witness(&auth, &perms, tock),
+        };
+        auth.delete_permission(delete.clone()).await.unwrap();
+        // A Permission cannot be deleted twice while it is verifiable.
+        let res = auth.delete_permission(delete.clone()).await;
+        assert!(matches!(res, Err(Error::WitnessVerification)));
+        let req = UpdateRequest {
+            perm: tick.clone(),
+            witness: witness(&auth, &perms, tick),
+            update: next(&next(tick)),
+        };
+        let res = auth.update_permission(req).await;
+        assert!(matches!(res, Err(Error::WitnessVerification)));
+        // The staging Accumulator holds exactly the remaining Permissions,
+        // also once the log has been replayed.
+        let expected = [added, next(tick), next(tack)];
+        let mut acc = Accumulator::<Mpz, Map>::with_public_key(
+            auth.get_key(),
+        );
+        for perm in expected {
+            acc.add(perm);
+        }
+        assert_eq!(auth.values().await.staging, acc.get_value());
+        drop(auth);
+        let auth = Authority::open(&dir, SECRET).unwrap();
+        assert_eq!(auth.values().await.staging, acc.get_value());
+        // The deletion is still refused after a restart and an `update`,
+        // until it has been synchronized.
+        auth.update().await.unwrap();
+        drop(auth);
+        let auth = Authority::open(&dir, SECRET).unwrap();
+        let res = auth.delete_permission(delete).await;
+        assert!(matches!(res, Err(Error::WitnessVerification)));
+        auth.sync().await.unwrap();
+        assert_eq!(auth.values().await.verifying, acc.get_value());
+    }
+
+    #[tokio::test]
+    async fn begin_rotation_requires_accumulated_permissions() {
+        let auth = Authority::open(&temp_dir(), SECRET).unwrap();
+        let tick = auth.add_permission(perm("tick")).await.unwrap();
+        let tock = auth.add_permission(perm("tock")).await.unwrap();
+        auth.update().await.unwrap();
+        auth.sync().await.unwrap();
+        // A missing, duplicated or forged Permission is refused.
+        let forged = Permission {
+            actions: vec!["admin".to_owned()],
+            ..tick.clone()
+        };
+        for perms in [
+            vec![tick.clone()],
+            vec![tick.clone(), tock.clone(), tock.clone()],
+            vec![tick.clone(), tock.clone(), forged],
+        ] {
+            let res = auth.begin_rotation(perms).await;
+            assert!(matches!(res, Err(Error::Rotation(_))));
+        }
+        // The Permissions in the accumulation are accepted in any order.
+        let checkpoint = auth.begin_rotation(vec![tock, tick]).await.unwrap();
+        assert!(checkpoint.checkpoint.key != auth.get_key());
+        auth.abort_rotation().await;
+    }
+
+    #[tokio::test]
+    async fn open_keeps_private_key_encrypted() {
+        let dir = temp_dir();
+        drop(Authority::open(&dir, SECRET).unwrap());
+        let path = PathBuf::from(&dir);
+        assert!(!path.join("key.json").exists());
+        assert!(path.join("key.enc").exists());
+        let other = "ff".repeat(32);
+        assert!(Authority::open(&dir, &other).is_err());
     }
 }
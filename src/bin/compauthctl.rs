@@ -0,0 +1,669 @@
+use clacc::Witness;
+use compauth::{
+    ceremony::{self, SealStatus, Share},
+    checkpoint::SignedCheckpoint,
+    client::Client,
+    condition::{Attributes, Condition},
+    config::Config,
+    delegation::DELEGATE,
+    error::Error,
+    peer::Credentials,
+    permission::Permission,
+    request::{
+        AuthorizeRequest,
+        CreateRequest,
+        DelegateRequest,
+        ValuesResponse,
+    },
+    role::Role,
+    subject::{Proof, SubjectKey},
+    tenant,
+    synchronizer::WindowStatus,
+    util::{from_bytes, from_json, Client as HttpClient},
+};
+use gmp::mpz::Mpz;
+use hyper::{Body, Method, Response, body::to_bytes};
+use serde::Serialize;
+
+const USAGE: &str = "\
+Usage: compauthctl [options] <command> [args]
+
+Commands:
+    create [options] <action>...    Create a permission
+    update <permission> <action>... Replace the actions of a permission
+    delegate <parent> <action>...   Delegate actions to a child permission
+    revoke <permission>             Revoke a permission
+    get <nonce>                     Print the current version of a permission
+    role <name> <action>...         Define or redefine a role
+    roles                           Print the roles
+    subject                         Generate a subject key
+    authorize <permission> <action> Test whether an action is allowed
+    witness <nonce>                 Fetch a permission's witness
+    key                             Print the Authority's public keys
+    checkpoint                      Print the published checkpoint
+    checkpoints                     Print the recent checkpoints
+    values                          Print the accumulation values
+    window                          Print the state of the update window
+    window close                    Close the current update window
+    rotate                          Rotate the Accumulator's key
+    ceremony <threshold> <shares>   Generate a private key split into shares
+                                    for the Authority's data directory
+    unseal <share>                  Submit a share to the sealed Authority
+    seal                            Print the progress of unsealing
+
+Permissions and shares are given as JSON, as returned by create, update and
+ceremony. Actions with a permission bound to a subject are authorized, and
+the permission delegated, with the subject's secret key, taken from
+COMPAUTH_SUBJECT_SECRET. Request
+attributes for a permission's conditions follow the action of authorize as
+<name>=<value>.
+
+Create options:
+    --not-before <millis>           Only allow the actions from then on
+    --expires-at <millis>           Only allow the actions until then
+    --role <name>                   Grant a role, may be repeated
+    --subject <key>                 Bind to a subject's public key
+    --condition <json>              Add a condition, may be repeated
+
+Delegate options:
+    --expires-at <millis>           Only allow the actions until then
+    --subject <key>                 Bind to another subject's public key
+
+Output:
+    --output <format>               Print json or table (default: table)
+
+Tenants:
+    --tenant <id>                   Manage a tenant rather than the default
+                                    one
+
+";
+
+/// How results are printed.
+#[derive(PartialEq)]
+enum Output {
+    Json,
+    Table,
+}
+
+/// Print a result as JSON or as a table with the given rows.
+fn print<T: Serialize>(output: &Output, value: &T, rows: Vec<[String; 2]>) {
+    if output == &Output::Json {
+        println!("{}", serde_json::to_string_pretty(value).unwrap());
+        return;
+    }
+    let width = rows.iter().map(|[name, _]| name.len()).max().unwrap_or(0);
+    for [name, value] in rows {
+        println!("{:width$}  {}", name, value, width = width);
+    }
+}
+
+/// Rows describing a Permission.
+fn perm_rows(perm: &Permission) -> Vec<[String; 2]> {
+    vec![
+        ["nonce".to_owned(), perm.nonce.to_string()],
+        ["version".to_owned(), perm.version.to_string()],
+        ["actions".to_owned(), perm.actions.join(", ")],
+        ["not_before".to_owned(), format_millis(perm.not_before)],
+        ["expires_at".to_owned(), format_millis(perm.expires_at)],
+        [
+            "roles".to_owned(),
+            perm.roles.iter()
+                .map(|role| format!("{}@{}", role.name, role.version))
+                .collect::<Vec<_>>()
+                .join(", "),
+        ],
+        [
+            "subject".to_owned(),
+            perm.subject.clone().unwrap_or_else(|| "-".to_owned()),
+        ],
+        [
+            "conditions".to_owned(),
+            perm.conditions.iter()
+                .map(format_condition)
+                .collect::<Vec<_>>()
+                .join(", "),
+        ],
+        [
+            "parent".to_owned(),
+            match &perm.parent {
+                Some(parent) => format!("{}@{}", parent.nonce, parent.version),
+                None => "-".to_owned(),
+            },
+        ],
+    ]
+}
+
+/// Format a condition, e.g. "transfer: amount at_most 1000".
+fn format_condition(condition: &Condition) -> String {
+    let op = serde_json::to_value(condition.op).unwrap();
+    format!(
+        "{}: {} {} {}",
+        condition.action,
+        condition.attribute,
+        op.as_str().unwrap_or_default(),
+        condition.values.join("|"),
+    )
+}
+
+/// Rows describing a Role.
+fn role_rows(role: &Role) -> Vec<[String; 2]> {
+    vec![
+        ["name".to_owned(), role.name.clone()],
+        ["version".to_owned(), role.version.to_string()],
+        ["actions".to_owned(), role.actions.join(", ")],
+    ]
+}
+
+/// Rows describing a Checkpoint.
+fn checkpoint_rows(signed: &SignedCheckpoint) -> Vec<[String; 2]> {
+    let checkpoint = &signed.checkpoint;
+    vec![
+        ["window".to_owned(), checkpoint.window.to_string()],
+        ["seq".to_owned(), checkpoint.seq.to_string()],
+        ["verifying".to_owned(), checkpoint.verifying.to_str_radix(16)],
+        ["staging".to_owned(), checkpoint.staging.to_str_radix(16)],
+        ["timestamp".to_owned(), checkpoint.timestamp.to_string()],
+        ["signature".to_owned(), signed.signature.clone()],
+    ]
+}
+
+/// Rows describing the progress of unsealing.
+fn seal_rows(status: &SealStatus) -> Vec<[String; 2]> {
+    vec![
+        ["sealed".to_owned(), status.sealed.to_string()],
+        [
+            "threshold".to_owned(),
+            match status.threshold {
+                Some(threshold) => threshold.to_string(),
+                None => "-".to_owned(),
+            },
+        ],
+        ["submitted".to_owned(), status.submitted.to_string()],
+    ]
+}
+
+/// Format a timestamp in milliseconds since the Unix epoch.
+fn format_millis(millis: Option<u64>) -> String {
+    match millis {
+        Some(millis) => millis.to_string(),
+        None => "-".to_owned(),
+    }
+}
+
+/// Parse a Permission given on the command line.
+fn parse_perm(arg: &str) -> Result<Permission, Error> {
+    match serde_json::from_str(arg) {
+        Ok(perm) => Ok(perm),
+        Err(_) => Err(Error::BadRequest),
+    }
+}
+
+/// Parse a share given on the command line.
+fn parse_share(arg: &str) -> Result<Share, Error> {
+    match serde_json::from_str(arg) {
+        Ok(share) => Ok(share),
+        Err(_) => Err(Error::BadRequest),
+    }
+}
+
+/// Parse the arguments of the create command.
+fn parse_create(args: &[&str]) -> Option<CreateRequest> {
+    let mut req = CreateRequest::from(Vec::new());
+    let mut args = args.iter();
+    while let Some(&arg) = args.next() {
+        match arg {
+            "--not-before" => {
+                req.not_before = Some(args.next()?.parse().ok()?);
+            },
+            "--expires-at" => {
+                req.expires_at = Some(args.next()?.parse().ok()?);
+            },
+            "--role" => {
+                req.roles.push(args.next()?.to_string());
+            },
+            "--subject" => {
+                req.subject = Some(args.next()?.to_string());
+            },
+            "--condition" => {
+                req.conditions.push(serde_json::from_str(args.next()?).ok()?);
+            },
+            action => req.actions.push(action.to_owned()),
+        }
+    }
+    match req.actions.is_empty() && req.roles.is_empty() {
+        true => None,
+        false => Some(req),
+    }
+}
+
+/// Parse the arguments of the delegate command.
+fn parse_delegate(args: &[&str]) -> Option<DelegateRequest> {
+    let mut args = args.iter();
+    let mut req = DelegateRequest {
+        perm: parse_perm(args.next()?).ok()?,
+        actions: Vec::new(),
+        expires_at: None,
+        subject: None,
+        proof: None,
+    };
+    while let Some(&arg) = args.next() {
+        match arg {
+            "--expires-at" => {
+                req.expires_at = Some(args.next()?.parse().ok()?);
+            },
+            "--subject" => {
+                req.subject = Some(args.next()?.to_string());
+            },
+            action => req.actions.push(action.to_owned()),
+        }
+    }
+    match req.actions.is_empty() {
+        true => None,
+        false => Some(req),
+    }
+}
+
+/// Sign a proof for an action with the subject key taken from
+/// COMPAUTH_SUBJECT_SECRET, if it is set.
+async fn subject_proof(
+    client: &Client,
+    perm: &Permission,
+    action: &str,
+    attributes: &Attributes,
+) -> Result<Option<Proof>, Error> {
+    match std::env::var("COMPAUTH_SUBJECT_SECRET") {
+        Ok(secret) => {
+            let key = SubjectKey::from_secret(&secret)?;
+            let audience = client.audience().await?;
+            Ok(Some(key.prove(&audience, perm, action, attributes)))
+        },
+        Err(_) => Ok(None),
+    }
+}
+
+/// Parse request attributes given as name=value.
+fn parse_attributes(args: &[&str]) -> Option<Attributes> {
+    args.iter()
+        .map(|arg| {
+            let (name, value) = arg.split_once('=')?;
+            Some((name.to_owned(), value.to_owned()))
+        })
+        .collect()
+}
+
+/// Send the requests of a client of the Authority or the Worker to a
+/// tenant's instance, and sign them with the service's secret if it is
+/// configured.
+fn peer_client(
+    client: HttpClient,
+    prefix: &str,
+    secret: &str,
+) -> Result<HttpClient, Error> {
+    let client = client.with_prefix(prefix);
+    match secret {
+        "" => Ok(client),
+        secret => Ok(client.with_credentials(Credentials::new(secret)?)),
+    }
+}
+
+/// Read a velocypack response body.
+async fn read<T>(resp: Response<Body>) -> Result<T, Error>
+where T: for<'a> serde::Deserialize<'a> {
+    let bytes = to_bytes(resp.into_body()).await;
+    match from_bytes(&bytes) {
+        Some(res) => Ok(res),
+        None => Err(Error::Decode),
+    }
+}
+
+/// Read a JSON response body.
+async fn read_json<T>(resp: Response<Body>) -> Result<T, Error>
+where T: for<'a> serde::Deserialize<'a> {
+    let bytes = to_bytes(resp.into_body()).await;
+    match from_json(&bytes) {
+        Some(res) => Ok(res),
+        None => Err(Error::Decode),
+    }
+}
+
+/// Run a command.
+///
+/// Returns `Ok(false)` if the arguments do not describe a command.
+async fn run(
+    config: &Config,
+    output: &Output,
+    tenant: Option<&str>,
+    args: &[String],
+) -> Result<bool, Error> {
+    let mut client = match config.synchronizer_client_tls()? {
+        Some(tls) => Client::with_tls(&config.synchronizer_addr, &tls),
+        None => Client::new(&config.synchronizer_addr),
+    };
+    if let Some(tenant) = tenant {
+        client = client.with_tenant(tenant);
+    }
+    let prefix = tenant.map(tenant::prefix).unwrap_or_default();
+    match args.iter().map(|arg| arg.as_str()).collect::<Vec<_>>()[..] {
+        ["create", ref args @ ..] => {
+            let req = match parse_create(args) {
+                Some(req) => req,
+                None => {
+                    return Ok(false);
+                },
+            };
+            let perm = client.create_permission_with(&req).await?;
+            print(output, &perm, perm_rows(&perm));
+        },
+        ["update", perm, ref actions @ ..] if !actions.is_empty() => {
+            let perm = parse_perm(perm)?;
+            let actions: Vec<String> = actions.iter()
+                .map(|&action| action.to_owned())
+                .collect();
+            let perm = client.update_permission(&perm, &actions).await?;
+            print(output, &perm, perm_rows(&perm));
+        },
+        ["delegate", ref args @ ..] => {
+            let mut req = match parse_delegate(args) {
+                Some(req) => req,
+                None => {
+                    return Ok(false);
+                },
+            };
+            let attributes = Attributes::new();
+            req.proof = subject_proof(
+                &client,
+                &req.perm,
+                DELEGATE,
+                &attributes,
+            ).await?;
+            let perm = client.delegate(&req).await?;
+            print(output, &perm, perm_rows(&perm));
+        },
+        ["revoke", perm] => {
+            client.revoke(&parse_perm(perm)?).await?;
+        },
+        ["get", nonce] => {
+            let nonce = match nonce.parse::<u64>() {
+                Ok(nonce) => nonce.into(),
+                Err(_) => {
+                    return Ok(false);
+                },
+            };
+            let perm = client.permission(nonce).await?;
+            print(output, &perm, perm_rows(&perm));
+        },
+        ["role", name, ref actions @ ..] if !actions.is_empty() => {
+            let actions: Vec<String> = actions.iter()
+                .map(|&action| action.to_owned())
+                .collect();
+            // Defining a role is authenticated with the Authority's secret.
+            let credentials = Credentials::new(&config.authority_secret)?;
+            let role = client.clone()
+                .with_credentials(credentials)
+                .define_role(name, &actions)
+                .await?;
+            print(output, &role, role_rows(&role));
+        },
+        ["roles"] => {
+            let roles = client.roles().await?;
+            let rows = roles.iter()
+                .map(|role| [
+                    format!("{}@{}", role.name, role.version),
+                    role.actions.join(", "),
+                ])
+                .collect();
+            print(output, &roles, rows);
+        },
+        ["subject"] => {
+            let key = SubjectKey::generate();
+            let keys = serde_json::json!({
+                "subject": key.subject(),
+                "secret": key.secret(),
+            });
+            print(output, &keys, vec![
+                ["subject".to_owned(), key.subject()],
+                ["secret".to_owned(), key.secret()],
+            ]);
+        },
+        ["authorize", perm, action, ref attributes @ ..] => {
+            let perm = parse_perm(perm)?;
+            let attributes = match parse_attributes(attributes) {
+                Some(attributes) => attributes,
+                None => {
+                    return Ok(false);
+                },
+            };
+            client.authorize_with(AuthorizeRequest {
+                proof: subject_proof(
+                    &client,
+                    &perm,
+                    action,
+                    &attributes,
+                ).await?,
+                perm,
+                action: action.to_owned(),
+                attributes,
+            }).await?;
+            print(output, &"granted", vec![
+                ["result".to_owned(), "granted".to_owned()],
+            ]);
+        },
+        ["witness", nonce] => {
+            let worker = peer_client(
+                config.worker_client()?,
+                &prefix,
+                &config.worker_secret,
+            )?;
+            let path = format!("/witness/{}", nonce);
+            let witness: Witness<Mpz> = read(worker.get(&path).await?).await?;
+            print(output, &witness, vec![
+                ["u".to_owned(), witness.u.to_str_radix(16)],
+                ["nonce".to_owned(), witness.nonce.to_str_radix(16)],
+            ]);
+        },
+        ["key"] => {
+            let auth = peer_client(
+                config.authority_client()?,
+                &prefix,
+                &config.authority_secret,
+            )?;
+            let key: Mpz = read(auth.get("/key").await?).await?;
+            let signing_key: String = read(
+                auth.get("/signing-key").await?,
+            ).await?;
+            let keys = serde_json::json!({
+                "key": key,
+                "signing_key": signing_key,
+            });
+            print(output, &keys, vec![
+                ["key".to_owned(), key.to_str_radix(16)],
+                ["signing_key".to_owned(), signing_key],
+            ]);
+        },
+        ["checkpoint"] => {
+            let sync = config.synchronizer_client()?.with_prefix(&prefix);
+            let checkpoint: SignedCheckpoint = read_json(
+                sync.get("/checkpoint").await?,
+            ).await?;
+            print(output, &checkpoint, checkpoint_rows(&checkpoint));
+        },
+        ["checkpoints"] => {
+            let sync = config.synchronizer_client()?.with_prefix(&prefix);
+            let checkpoints: Vec<SignedCheckpoint> = read_json(
+                sync.get("/checkpoints").await?,
+            ).await?;
+            let rows = checkpoints.iter()
+                .map(|signed| {
+                    let checkpoint = &signed.checkpoint;
+                    [
+                        format!("{}/{}", checkpoint.window, checkpoint.seq),
+                        checkpoint.verifying.to_str_radix(16),
+                    ]
+                })
+                .collect();
+            print(output, &checkpoints, rows);
+        },
+        ["values"] => {
+            let auth = peer_client(
+                config.authority_client()?,
+                &prefix,
+                &config.authority_secret,
+            )?;
+            let values: ValuesResponse = read(
+                auth.get("/values").await?,
+            ).await?;
+            print(output, &values, vec![
+                ["verifying".to_owned(), values.verifying.to_str_radix(16)],
+                ["updating".to_owned(), values.updating.to_str_radix(16)],
+                ["staging".to_owned(), values.staging.to_str_radix(16)],
+            ]);
+        },
+        ["window", ref rest @ ..] if rest.is_empty() || rest == ["close"] => {
+            // Closing the window is authenticated with the Authority's
+            // secret.
+            let sync = peer_client(
+                config.synchronizer_client()?,
+                &prefix,
+                &config.authority_secret,
+            )?;
+            let resp = match rest {
+                [] => sync.get("/window").await?,
+                _ => sync.send_json(Method::POST, "/window", ()).await?,
+            };
+            let status: WindowStatus = read_json(resp).await?;
+            print(output, &status, vec![
+                ["window_millis".to_owned(), status.window_millis.to_string()],
+                ["closing".to_owned(), status.closing.to_string()],
+                ["closed".to_owned(), status.closed.to_string()],
+                [
+                    "last_closed_at".to_owned(),
+                    format_millis(status.last_closed_at),
+                ],
+                [
+                    "next_close_at".to_owned(),
+                    format_millis(status.next_close_at),
+                ],
+                [
+                    "last_error".to_owned(),
+                    status.last_error.clone().unwrap_or_else(|| "-".to_owned()),
+                ],
+            ]);
+        },
+        ["rotate"] => {
+            // Rotating the key is authenticated with the Authority's secret.
+            let sync = peer_client(
+                config.synchronizer_client()?,
+                &prefix,
+                &config.authority_secret,
+            )?;
+            let checkpoint: SignedCheckpoint = read_json(
+                sync.send_json(Method::POST, "/rotate", ()).await?,
+            ).await?;
+            print(output, &checkpoint, checkpoint_rows(&checkpoint));
+        },
+        ["ceremony", threshold, shares] => {
+            let parsed = (threshold.parse(), shares.parse());
+            let (threshold, shares) = match parsed {
+                (Ok(threshold), Ok(shares)) => (threshold, shares),
+                _ => {
+                    return Ok(false);
+                },
+            };
+            let shares = ceremony::generate(threshold, shares)?;
+            // Record the key and threshold the sealed Authority accepts
+            // shares for.
+            let dir = match tenant {
+                Some(tenant) => config.for_tenant(tenant).authority_data_dir,
+                None => config.authority_data_dir.clone(),
+            };
+            ceremony::record(&dir, &shares[0])?;
+            let rows = shares.iter()
+                .map(|share| [
+                    share.index.to_string(),
+                    serde_json::to_string(share).unwrap(),
+                ])
+                .collect();
+            print(output, &shares, rows);
+        },
+        ["unseal", share] => {
+            let auth = peer_client(
+                config.authority_client()?,
+                &prefix,
+                &config.authority_secret,
+            )?;
+            let status: SealStatus = read(
+                auth.post("/unseal", parse_share(share)?).await?,
+            ).await?;
+            print(output, &status, seal_rows(&status));
+        },
+        ["seal"] => {
+            let auth = peer_client(
+                config.authority_client()?,
+                &prefix,
+                &config.authority_secret,
+            )?;
+            let status: SealStatus = read(auth.get("/seal").await?).await?;
+            print(output, &status, seal_rows(&status));
+        },
+        _ => {
+            return Ok(false);
+        },
+    }
+    Ok(true)
+}
+
+#[tokio::main]
+async fn main() {
+    // Options precede the command. Settings are handed to Config, which
+    // takes a value with every one of them but --help and --print-config.
+    let mut settings = Vec::new();
+    let mut output = Output::Table;
+    let mut tenant = None;
+    let mut args = std::env::args().skip(1).peekable();
+    while let Some(arg) = args.next_if(|arg| arg.starts_with("--")) {
+        let takes_value = !arg.contains('=')
+            && arg != "--help"
+            && arg != "--print-config";
+        let value = match takes_value {
+            true => args.next(),
+            false => None,
+        };
+        if arg == "--output" || arg.starts_with("--output=") {
+            let format = match arg.split_once('=') {
+                Some((_, format)) => Some(format.to_owned()),
+                None => value,
+            };
+            output = match format.as_deref() {
+                Some("json") => Output::Json,
+                Some("table") => Output::Table,
+                _ => {
+                    eprintln!("--output must be json or table\n\n{}", USAGE);
+                    std::process::exit(2);
+                },
+            };
+            continue;
+        }
+        if arg == "--tenant" || arg.starts_with("--tenant=") {
+            tenant = match arg.split_once('=') {
+                Some((_, tenant)) => Some(tenant.to_owned()),
+                None => value,
+            };
+            continue;
+        }
+        settings.push(arg);
+        settings.extend(value);
+    }
+    let config = Config::load_from(&settings, USAGE, &[]);
+    let args: Vec<String> = args.collect();
+    match run(&config, &output, tenant.as_deref(), &args).await {
+        Ok(true) => {},
+        Ok(false) => {
+            eprint!("{}", USAGE);
+            std::process::exit(2);
+        },
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        },
+    }
+}
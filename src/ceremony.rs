@@ -0,0 +1,443 @@
+use clacc::{Accumulator, sha3::Shake128 as Map};
+use gmp::mpz::Mpz;
+use rand::RngCore;
+use serde::{Serialize, Deserialize};
+use std::{
+    fs,
+    path::PathBuf,
+    sync::{Arc, RwLock},
+};
+use tokio::sync::Mutex;
+use crate::{
+    authority::Authority,
+    error::Error,
+    store,
+};
+
+/// A share of the Accumulator's private key.
+///
+/// The factors of the Accumulator's modulus are split with Shamir's secret
+/// sharing over a prime field, so that any `threshold` of the shares
+/// reconstruct them and fewer reveal nothing about them. Each share is
+/// entrusted to a different security officer.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Share {
+
+    /// The Accumulator's public key.
+    pub key: Mpz,
+
+    /// The number of shares needed to reconstruct the private key.
+    pub threshold: usize,
+
+    /// The point the share was evaluated at, starting from 1.
+    pub index: u64,
+
+    /// The share of the first factor.
+    pub p: Mpz,
+
+    /// The share of the second factor.
+    pub q: Mpz,
+}
+
+/// Generate a private key and split it into shares.
+///
+/// This is the key ceremony: the factors exist only for the duration of
+/// the call, and the Authority is later unsealed by submitting `threshold`
+/// of the returned shares to it.
+pub fn generate(threshold: usize, shares: usize) -> Result<Vec<Share>, Error> {
+    let mut rng = rand::thread_rng();
+    let (_, p, q) = Accumulator::<Mpz, Map>::with_random_key(
+        |bytes| rng.fill_bytes(bytes),
+        None,
+    );
+    split(&p, &q, threshold, shares)
+}
+
+/// Split a private key into shares.
+pub fn split(
+    p: &Mpz,
+    q: &Mpz,
+    threshold: usize,
+    shares: usize,
+) -> Result<Vec<Share>, Error> {
+    if threshold == 0 || threshold > shares {
+        return Err(Error::InvalidShare);
+    }
+    let key = p * q;
+    let field = field(&key);
+    // Pick random polynomials whose constant terms are the factors.
+    let p_coeffs = polynomial(p, threshold, &field);
+    let q_coeffs = polynomial(q, threshold, &field);
+    Ok((1..=shares as u64).map(|index| Share {
+        key: key.clone(),
+        threshold,
+        index,
+        p: evaluate(&p_coeffs, index, &field),
+        q: evaluate(&q_coeffs, index, &field),
+    }).collect())
+}
+
+/// Record the public key and threshold of a ceremony's shares in the data
+/// directory of the Authority they unseal.
+///
+/// A sealed Authority only accepts shares for the recorded key and
+/// threshold, so that the first share submitted to it cannot choose them.
+/// A directory that already has a public key is left untouched.
+pub fn record(dir: &str, share: &Share) -> Result<(), Error> {
+    let dir = PathBuf::from(dir);
+    if fs::create_dir_all(&dir).is_err() {
+        return Err(Error::Storage(
+            "could not create data directory".to_owned(),
+        ));
+    }
+    let public_path = dir.join("public_key.json");
+    if store::load::<Mpz>(&public_path)?.is_some() {
+        return Err(Error::Storage(
+            "data directory already has a public key".to_owned(),
+        ));
+    }
+    store::save(&dir.join("threshold.json"), &share.threshold)?;
+    store::save(&public_path, &share.key)
+}
+
+/// Reconstruct a private key from shares.
+///
+/// The shares must be for the same public key, have distinct indices and
+/// number at least their threshold. Since shares carry no proof of their
+/// own, the reconstructed factors are checked against the public key.
+pub fn combine(shares: &[Share]) -> Result<(Mpz, Mpz), Error> {
+    let first = match shares.first() {
+        Some(share) => share,
+        None => {
+            return Err(Error::InvalidShare);
+        },
+    };
+    if shares.len() < first.threshold {
+        return Err(Error::InvalidShare);
+    }
+    for (i, share) in shares.iter().enumerate() {
+        if share.key != first.key
+            || share.threshold != first.threshold
+            || share.index == 0
+            || shares[..i].iter().any(|other| other.index == share.index) {
+            return Err(Error::InvalidShare);
+        }
+    }
+    let field = field(&first.key);
+    let shares = &shares[..first.threshold];
+    let p = interpolate(shares, |share| &share.p, &field)?;
+    let q = interpolate(shares, |share| &share.q, &field)?;
+    if &p * &q != first.key || p == Mpz::one() || q == Mpz::one() {
+        return Err(Error::InvalidShare);
+    }
+    Ok((p, q))
+}
+
+/// Internal helper to choose the prime field shares are computed in.
+///
+/// The prime is derived from the public key so that shares need not carry
+/// it. It is larger than the modulus and therefore than either factor.
+fn field(key: &Mpz) -> Mpz {
+    (Mpz::one() << key.bit_length()).nextprime()
+}
+
+/// Internal helper to create a random polynomial with a given constant term.
+fn polynomial(secret: &Mpz, threshold: usize, field: &Mpz) -> Vec<Mpz> {
+    let mut rng = rand::thread_rng();
+    let mut bytes = vec![0u8; field.bit_length().div_ceil(8) + 16];
+    let mut coeffs = vec![secret.clone()];
+    for _ in 1..threshold {
+        rng.fill_bytes(&mut bytes);
+        coeffs.push(Mpz::from(&bytes[..]).modulus(field));
+    }
+    coeffs
+}
+
+/// Internal helper to evaluate a polynomial at a point.
+fn evaluate(coeffs: &[Mpz], x: u64, field: &Mpz) -> Mpz {
+    let x = Mpz::from(x);
+    coeffs.iter().rev().fold(Mpz::zero(), |acc, coeff| {
+        (acc * &x + coeff).modulus(field)
+    })
+}
+
+/// Internal helper to interpolate the constant term of the polynomial that
+/// a set of shares lie on.
+fn interpolate<F>(shares: &[Share], y: F, field: &Mpz) -> Result<Mpz, Error>
+where F: Fn(&Share) -> &Mpz {
+    let mut secret = Mpz::zero();
+    for share in shares {
+        let xi = Mpz::from(share.index);
+        let mut num = Mpz::one();
+        let mut den = Mpz::one();
+        for other in shares {
+            if other.index == share.index {
+                continue;
+            }
+            let xj = Mpz::from(other.index);
+            num = (num * &xj).modulus(field);
+            den = (den * (&xj - &xi)).modulus(field);
+        }
+        let inv = match den.invert(field) {
+            Some(inv) => inv,
+            None => {
+                return Err(Error::InvalidShare);
+            },
+        };
+        secret = (secret + y(share) * &num * &inv).modulus(field);
+    }
+    Ok(secret)
+}
+
+/// The progress of unsealing an Authority.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SealStatus {
+
+    /// Whether the Authority is still sealed.
+    pub sealed: bool,
+
+    /// The number of shares needed to unseal the Authority, once the first
+    /// share has been submitted.
+    pub threshold: Option<usize>,
+
+    /// The number of shares submitted so far.
+    pub submitted: usize,
+}
+
+/// An Authority that may be sealed.
+///
+/// A sealed Authority holds no private key and refuses every request until
+/// enough security officers have submitted their shares to `unseal`. Once
+/// the private key has been reconstructed, the Authority is opened from its
+/// data directory as if by `Authority::open_with_key`.
+pub struct Vault {
+    dir: PathBuf,
+
+    /// The shares submitted so far.
+    shares: Mutex<Vec<Share>>,
+
+    /// The Authority, once it has been unsealed.
+    authority: RwLock<Option<Arc<Authority>>>,
+}
+
+impl Vault {
+
+    /// Create a sealed Vault for the Authority persisted in a directory.
+    pub fn sealed(dir: &str) -> Self {
+        Vault {
+            dir: PathBuf::from(dir),
+            shares: Mutex::new(Vec::new()),
+            authority: RwLock::new(None),
+        }
+    }
+
+    /// Create a Vault holding an Authority that is already unsealed.
+    pub fn unsealed(dir: &str, authority: Authority) -> Self {
+        Vault {
+            dir: PathBuf::from(dir),
+            shares: Mutex::new(Vec::new()),
+            authority: RwLock::new(Some(Arc::new(authority))),
+        }
+    }
+
+    /// Return the Authority, or `Error::Sealed` if it is still sealed.
+    pub fn authority(&self) -> Result<Arc<Authority>, Error> {
+        match &*self.authority.read().unwrap() {
+            Some(authority) => Ok(Arc::clone(authority)),
+            None => Err(Error::Sealed),
+        }
+    }
+
+    /// Return the progress of unsealing the Authority.
+    pub async fn status(&self) -> SealStatus {
+        let shares = self.shares.lock().await;
+        SealStatus {
+            sealed: self.authority.read().unwrap().is_none(),
+            threshold: shares.first().map(|share| share.threshold),
+            submitted: shares.len(),
+        }
+    }
+
+    /// Submit a share of the private key.
+    ///
+    /// Shares must be for the public key and threshold that `record` wrote
+    /// to the data directory, and are refused until it has. Once the
+    /// threshold is reached, the private key is reconstructed and the
+    /// Authority is opened. If the shares do not reconstruct the private
+    /// key, they are all discarded and the officers must start over.
+    pub async fn unseal(&self, share: Share) -> Result<SealStatus, Error> {
+        let mut shares = self.shares.lock().await;
+        if self.authority.read().unwrap().is_some() {
+            return Ok(SealStatus {
+                sealed: false,
+                threshold: None,
+                submitted: 0,
+            });
+        }
+        // Ensure the share is for the key and threshold recorded by the key
+        // ceremony, and is not one submitted before. Without a record, the
+        // first share would decide which key the Authority is unsealed with.
+        let sealed: Option<Mpz> = store::load(
+            &self.dir.join("public_key.json"),
+        )?;
+        let threshold: Option<usize> = store::load(
+            &self.dir.join("threshold.json"),
+        )?;
+        let (key, threshold) = match (sealed, threshold) {
+            (Some(key), Some(threshold)) => (key, threshold),
+            _ => {
+                return Err(Error::Storage(
+                    "no key ceremony is recorded in the data directory"
+                        .to_owned(),
+                ));
+            },
+        };
+        if share.key != key || share.threshold != threshold {
+            return Err(Error::InvalidShare);
+        }
+        if shares.iter().any(|other| other.index == share.index) {
+            return Err(Error::InvalidShare);
+        }
+        shares.push(share);
+        if shares.len() < threshold {
+            return Ok(SealStatus {
+                sealed: true,
+                threshold: Some(threshold),
+                submitted: shares.len(),
+            });
+        }
+        // Reconstruct the private key and open the Authority with it.
+        let submitted = std::mem::take(&mut *shares);
+        let (p, q) = combine(&submitted)?;
+        let dir = self.dir.to_string_lossy();
+        let authority = Authority::open_with_key(&dir, p, q)?;
+        *self.authority.write().unwrap() = Some(Arc::new(authority));
+        Ok(SealStatus {
+            sealed: false,
+            threshold: Some(threshold),
+            submitted: submitted.len(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Return a small private key, which is enough to exercise the sharing.
+    fn key() -> (Mpz, Mpz) {
+        let p = Mpz::from(1u64 << 40).nextprime();
+        let q = Mpz::from(1u64 << 41).nextprime();
+        (p, q)
+    }
+
+    /// Return a fresh temporary data directory.
+    fn temp_dir() -> String {
+        std::env::temp_dir()
+            .join(format!(
+                "compauth-ceremony-{}-{}",
+                std::process::id(),
+                rand::random::<u64>(),
+            ))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    #[test]
+    fn combine_reconstructs_key() {
+        let (p, q) = key();
+        let shares = split(&p, &q, 3, 5).unwrap();
+        // Any threshold of the shares, in any order, reconstruct the key.
+        for picked in [[0, 1, 2], [4, 2, 0], [1, 3, 4]] {
+            let subset: Vec<Share> = picked.iter()
+                .map(|&i| shares[i].clone())
+                .collect();
+            assert!(combine(&subset).unwrap() == (p.clone(), q.clone()));
+        }
+        // So do more shares than the threshold.
+        assert!(combine(&shares).unwrap() == (p, q));
+    }
+
+    #[test]
+    fn split_rejects_invalid_threshold() {
+        let (p, q) = key();
+        assert!(matches!(split(&p, &q, 0, 3), Err(Error::InvalidShare)));
+        assert!(matches!(split(&p, &q, 4, 3), Err(Error::InvalidShare)));
+    }
+
+    #[test]
+    fn combine_rejects_too_few_shares() {
+        let (p, q) = key();
+        let shares = split(&p, &q, 3, 5).unwrap();
+        assert!(matches!(combine(&shares[..2]), Err(Error::InvalidShare)));
+        assert!(matches!(combine(&[]), Err(Error::InvalidShare)));
+    }
+
+    #[test]
+    fn combine_rejects_duplicate_index() {
+        let (p, q) = key();
+        let shares = split(&p, &q, 3, 5).unwrap();
+        let duplicated = [
+            shares[0].clone(),
+            shares[1].clone(),
+            shares[1].clone(),
+        ];
+        assert!(matches!(combine(&duplicated), Err(Error::InvalidShare)));
+    }
+
+    #[test]
+    fn combine_rejects_mismatched_key() {
+        let (p, q) = key();
+        let shares = split(&p, &q, 2, 3).unwrap();
+        let other = split(&p, &q.clone().nextprime(), 2, 3).unwrap();
+        let mixed = [shares[0].clone(), other[1].clone()];
+        assert!(matches!(combine(&mixed), Err(Error::InvalidShare)));
+        // A share that claims the right key but lies on another polynomial
+        // does not reconstruct the factors.
+        let forged = Share { key: shares[0].key.clone(), ..other[1].clone() };
+        let mixed = [shares[0].clone(), forged];
+        assert!(matches!(combine(&mixed), Err(Error::InvalidShare)));
+    }
+
+    #[tokio::test]
+    async fn unseal_requires_recorded_ceremony() {
+        let (p, q) = key();
+        let shares = split(&p, &q, 2, 3).unwrap();
+        let vault = Vault::sealed(&temp_dir());
+        assert!(matches!(
+            vault.unseal(shares[0].clone()).await,
+            Err(Error::Storage(_)),
+        ));
+        assert_eq!(vault.status().await.submitted, 0);
+    }
+
+    #[tokio::test]
+    async fn unseal_rejects_unrecorded_key_and_threshold() {
+        let (p, q) = key();
+        let shares = split(&p, &q, 2, 3).unwrap();
+        let dir = temp_dir();
+        record(&dir, &shares[0]).unwrap();
+        let vault = Vault::sealed(&dir);
+        // Shares of another key, or with another threshold, are refused.
+        let other = split(&p, &q.clone().nextprime(), 2, 3).unwrap();
+        assert!(matches!(
+            vault.unseal(other[0].clone()).await,
+            Err(Error::InvalidShare),
+        ));
+        let lowered = Share { threshold: 1, ..shares[0].clone() };
+        assert!(matches!(
+            vault.unseal(lowered).await,
+            Err(Error::InvalidShare),
+        ));
+        // A share of the recorded key is accepted once.
+        let status = vault.unseal(shares[0].clone()).await.unwrap();
+        assert!(status.sealed);
+        assert_eq!(status.submitted, 1);
+        assert!(matches!(
+            vault.unseal(shares[0].clone()).await,
+            Err(Error::InvalidShare),
+        ));
+        // A second ceremony cannot replace the recorded key.
+        assert!(matches!(record(&dir, &other[0]), Err(Error::Storage(_))));
+    }
+}
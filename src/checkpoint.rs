@@ -0,0 +1,160 @@
+use ed25519_dalek::{Signer as _, Signature, SigningKey, VerifyingKey};
+use gmp::mpz::Mpz;
+use serde::{Serialize, Deserialize};
+use crate::error::Error;
+
+/// The public key checkpoints are verified with.
+pub type PublicKey = VerifyingKey;
+
+/// A statement by the Authority of its accumulation values.
+///
+/// The Authority issues a checkpoint at every `update` and `sync`, and with
+/// every response to an updated or deleted Permission, so that the Worker
+/// and verifiers only accept values the Authority has vouched for.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Checkpoint {
+
+    /// The number of update windows that have been synchronized.
+    pub window: u64,
+
+    /// The number of Permission additions, updates and deletions made so
+    /// far. Recipients reject checkpoints that do not advance it so that old
+    /// ones cannot be replayed.
+    pub seq: u64,
+
+    /// The Accumulator's public key.
+    pub key: Mpz,
+
+    /// The accumulation value Witnesses are verified against.
+    pub verifying: Mpz,
+
+    /// The accumulation value including every change made so far.
+    pub staging: Mpz,
+
+    /// When the checkpoint was issued, in milliseconds since the Unix
+    /// epoch.
+    pub timestamp: u64,
+}
+
+impl Checkpoint {
+
+    /// The bytes covered by the signature.
+    fn to_bytes(&self) -> Vec<u8> {
+        velocypack::to_bytes(self).unwrap()
+    }
+}
+
+/// A Checkpoint signed by the Authority.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SignedCheckpoint {
+
+    /// The Checkpoint.
+    pub checkpoint: Checkpoint,
+
+    /// The hex encoded Ed25519 signature of the Checkpoint.
+    pub signature: String,
+}
+
+impl SignedCheckpoint {
+
+    /// Return the Checkpoint if it was signed by the given key.
+    pub fn verify(&self, key: &PublicKey) -> Result<&Checkpoint, Error> {
+        let bytes = match hex::decode(&self.signature) {
+            Ok(bytes) => bytes,
+            Err(_) => {
+                return Err(Error::InvalidCheckpoint);
+            },
+        };
+        let signature = match Signature::from_slice(&bytes) {
+            Ok(signature) => signature,
+            Err(_) => {
+                return Err(Error::InvalidCheckpoint);
+            },
+        };
+        let msg = self.checkpoint.to_bytes();
+        match key.verify_strict(&msg, &signature) {
+            Ok(_) => Ok(&self.checkpoint),
+            Err(_) => Err(Error::InvalidCheckpoint),
+        }
+    }
+}
+
+/// Signs Checkpoints on behalf of the Authority.
+pub struct Signer {
+    key: SigningKey,
+}
+
+impl Signer {
+
+    /// Generate a new random signing key.
+    pub fn generate() -> Self {
+        Signer {
+            key: SigningKey::generate(&mut rand::rngs::OsRng),
+        }
+    }
+
+    /// Restore a signing key from its hex encoded secret.
+    pub fn from_secret(secret: &str) -> Result<Self, Error> {
+        let bytes: [u8; 32] = match hex::decode(secret) {
+            Ok(bytes) => match bytes.try_into() {
+                Ok(bytes) => bytes,
+                Err(_) => {
+                    return Err(Error::Storage(
+                        "signing key has the wrong length".to_owned(),
+                    ));
+                },
+            },
+            Err(_) => {
+                return Err(Error::Storage(
+                    "signing key is not hex encoded".to_owned(),
+                ));
+            },
+        };
+        Ok(Signer {
+            key: SigningKey::from_bytes(&bytes),
+        })
+    }
+
+    /// Return the hex encoded secret.
+    pub fn secret(&self) -> String {
+        hex::encode(self.key.to_bytes())
+    }
+
+    /// Return the public key.
+    pub fn public_key(&self) -> PublicKey {
+        self.key.verifying_key()
+    }
+
+    /// Sign a Checkpoint.
+    pub fn sign(&self, checkpoint: Checkpoint) -> SignedCheckpoint {
+        let signature = self.key.sign(&checkpoint.to_bytes());
+        SignedCheckpoint {
+            checkpoint,
+            signature: hex::encode(signature.to_bytes()),
+        }
+    }
+}
+
+/// Parse a hex encoded public key.
+pub fn parse_public_key(key: &str) -> Result<PublicKey, Error> {
+    let bytes: [u8; 32] = match hex::decode(key) {
+        Ok(bytes) => match bytes.try_into() {
+            Ok(bytes) => bytes,
+            Err(_) => {
+                return Err(Error::BadRequest);
+            },
+        },
+        Err(_) => {
+            return Err(Error::BadRequest);
+        },
+    };
+    match PublicKey::from_bytes(&bytes) {
+        Ok(key) => Ok(key),
+        Err(_) => Err(Error::BadRequest),
+    }
+}
+
+/// Hex encode a public key.
+pub fn format_public_key(key: &PublicKey) -> String {
+    hex::encode(key.to_bytes())
+}
@@ -0,0 +1,375 @@
+use clacc::Witness;
+use gmp::mpz::Mpz;
+use hyper::{Body, Method, Response, body::to_bytes};
+use crate::{
+    condition::Attributes,
+    error::Error,
+    peer::Credentials,
+    permission::{Action, Nonce, Permission},
+    request::{
+        ActionsRequest,
+        AuthorizeRequest,
+        BatchResult,
+        CreateRequest,
+        Decision,
+        DelegateRequest,
+        RoleRequest,
+        UpdateActionsRequest,
+    },
+    role::Role,
+    subject::SubjectKey,
+    tenant,
+    tls::ClientTls,
+    util::{from_json, Client as HttpClient},
+};
+
+/// A client of the Synchronizer's public API.
+///
+/// This is the supported way for applications to manage Permissions and
+/// authorize actions. Errors raised by the Synchronizer, the Authority or
+/// the Worker are returned as the `Error` they were raised with, so that,
+/// for example, a denied action can be told apart from an unreachable
+/// service.
+///
+/// Clients are cheap to clone and may be shared between tasks.
+#[derive(Clone)]
+pub struct Client {
+    client: HttpClient,
+}
+
+impl Client {
+
+    /// Create a client of the Synchronizer listening on an address such as
+    /// "127.0.0.1:3000".
+    pub fn new(addr: &str) -> Self {
+        Client {
+            client: HttpClient::new(addr),
+        }
+    }
+
+    /// Create a client of a Synchronizer that serves TLS.
+    pub fn with_tls(addr: &str, tls: &ClientTls) -> Self {
+        Client {
+            client: HttpClient::with_tls(addr, tls),
+        }
+    }
+
+    /// Send every request to a tenant rather than to the default tenant.
+    ///
+    /// Permissions of one tenant are unknown to every other tenant, so a
+    /// client must use the tenant its Permissions were created in.
+    pub fn with_tenant(self, tenant: &str) -> Self {
+        Client {
+            client: self.client.with_prefix(&tenant::prefix(tenant)),
+        }
+    }
+
+    /// Sign requests with the Authority's secret, as operators must to
+    /// define roles.
+    pub fn with_credentials(self, credentials: Credentials) -> Self {
+        Client {
+            client: self.client.with_credentials(credentials),
+        }
+    }
+
+    /// Create a Permission that allows a set of actions.
+    ///
+    /// The returned Permission carries the Nonce assigned by the Authority
+    /// and is what the application should store on behalf of its owner. It
+    /// may be used once the current update window has closed.
+    pub async fn create_permission(
+        &self,
+        actions: &[Action],
+    ) -> Result<Permission, Error> {
+        self.create_permission_with(&actions.to_vec().into()).await
+    }
+
+    /// Create a Permission like `create_permission`, but with a validity
+    /// period, roles or a subject.
+    ///
+    /// The Permission only allows its actions from `not_before` and until
+    /// `expires_at`, and is revoked at the first update window after it
+    /// expires. A Permission bound to a `subject` is only authorized with
+    /// `authorize_as` and the subject's key.
+    pub async fn create_permission_with(
+        &self,
+        req: &CreateRequest,
+    ) -> Result<Permission, Error> {
+        let resp = self.client.send_json(
+            Method::POST,
+            "/permission",
+            req,
+        ).await?;
+        let bytes = to_bytes(resp.into_body()).await;
+        match from_json(&bytes) {
+            Some(perm) => Ok(perm),
+            None => Err(Error::Decode),
+        }
+    }
+
+    /// Create a batch of Permissions in a single request, e.g. when
+    /// importing them from another system.
+    ///
+    /// The outcome of each request is returned in order, so a request that
+    /// is rejected does not prevent the others from creating a Permission.
+    pub async fn create_permissions(
+        &self,
+        reqs: &[CreateRequest],
+    ) -> Result<Vec<Result<Permission, Error>>, Error> {
+        let resp = self.client.send_json(
+            Method::POST,
+            "/permissions",
+            reqs,
+        ).await?;
+        Self::batch_results(resp).await
+    }
+
+    /// Replace the actions of a Permission.
+    ///
+    /// The returned Permission is the next version and supersedes the one
+    /// given once the current update window has closed. It keeps the
+    /// validity period, roles and conditions of the one given.
+    pub async fn update_permission(
+        &self,
+        perm: &Permission,
+        actions: &[Action],
+    ) -> Result<Permission, Error> {
+        let req = UpdateActionsRequest {
+            perm: perm.clone(),
+            actions: actions.to_vec(),
+            roles: None,
+            conditions: None,
+        };
+        let resp = self.client.send_json(
+            Method::PUT,
+            "/permission",
+            req,
+        ).await?;
+        let bytes = to_bytes(resp.into_body()).await;
+        match from_json(&bytes) {
+            Some(perm) => Ok(perm),
+            None => Err(Error::Decode),
+        }
+    }
+
+    /// Update a batch of Permissions in a single request.
+    ///
+    /// Unlike `update_permission`, each request may also replace the roles
+    /// or conditions of its Permission. A Permission may only appear once in
+    /// a batch. The outcome of each request is returned in order.
+    pub async fn update_permissions(
+        &self,
+        reqs: &[UpdateActionsRequest],
+    ) -> Result<Vec<Result<Permission, Error>>, Error> {
+        let resp = self.client.send_json(
+            Method::PUT,
+            "/permissions",
+            reqs,
+        ).await?;
+        Self::batch_results(resp).await
+    }
+
+    /// Internal helper to decode the outcomes of a batch.
+    async fn batch_results(
+        resp: Response<Body>,
+    ) -> Result<Vec<Result<Permission, Error>>, Error> {
+        let bytes = to_bytes(resp.into_body()).await;
+        match from_json::<Vec<BatchResult>, _>(&bytes) {
+            Some(results) => Ok(results.into_iter()
+                .map(BatchResult::into_result)
+                .collect()),
+            None => Err(Error::Decode),
+        }
+    }
+
+    /// Delegate a subset of a Permission's actions to a new child
+    /// Permission, e.g. to hand a narrower capability to a background job.
+    ///
+    /// The child never allows more than the Permission it is delegated from.
+    /// It is revoked along with its parent, and narrowed at the next update
+    /// window whenever the parent is. A parent bound to a subject can only
+    /// be delegated with a proof for `delegation::DELEGATE` signed by the
+    /// subject.
+    pub async fn delegate(
+        &self,
+        req: &DelegateRequest,
+    ) -> Result<Permission, Error> {
+        let resp = self.client.send_json(
+            Method::POST,
+            "/delegate",
+            req,
+        ).await?;
+        let bytes = to_bytes(resp.into_body()).await;
+        match from_json(&bytes) {
+            Some(perm) => Ok(perm),
+            None => Err(Error::Decode),
+        }
+    }
+
+    /// Get the current version of a Permission by its Nonce.
+    ///
+    /// Permissions are updated when one of their roles is redefined, so
+    /// owners of Permissions with roles should look up the current version
+    /// once per update window.
+    pub async fn permission(&self, nonce: Nonce) -> Result<Permission, Error> {
+        let path = format!("/permission/{}", nonce);
+        let resp = self.client.get(&path).await?;
+        let bytes = to_bytes(resp.into_body()).await;
+        match from_json(&bytes) {
+            Some(perm) => Ok(perm),
+            None => Err(Error::Decode),
+        }
+    }
+
+    /// List the current definitions of the roles.
+    pub async fn roles(&self) -> Result<Vec<Role>, Error> {
+        let resp = self.client.get("/roles").await?;
+        let bytes = to_bytes(resp.into_body()).await;
+        match from_json(&bytes) {
+            Some(roles) => Ok(roles),
+            None => Err(Error::Decode),
+        }
+    }
+
+    /// Define a role, or redefine it with new actions.
+    ///
+    /// Permissions granted the role are updated to the new definition in
+    /// the next update window. The client must sign its requests with the
+    /// Authority's secret; see `with_credentials`.
+    pub async fn define_role(
+        &self,
+        name: &str,
+        actions: &[Action],
+    ) -> Result<Role, Error> {
+        let req = RoleRequest {
+            name: name.to_owned(),
+            actions: actions.to_vec(),
+        };
+        let resp = self.client.send_json(Method::PUT, "/role", req).await?;
+        let bytes = to_bytes(resp.into_body()).await;
+        match from_json(&bytes) {
+            Some(role) => Ok(role),
+            None => Err(Error::Decode),
+        }
+    }
+
+    /// Revoke a Permission.
+    ///
+    /// The Permission stops authorizing actions once the current update
+    /// window has closed.
+    pub async fn revoke(&self, perm: &Permission) -> Result<(), Error> {
+        self.client.send_json(Method::DELETE, "/permission", perm).await?;
+        Ok(())
+    }
+
+    /// Authorize an action.
+    ///
+    /// Returns `Ok(())` if the Permission is current and allows the action.
+    /// Otherwise, `Error::NotGranted` is returned for a Permission that does
+    /// not include the action, `Error::NotYetValid` or `Error::Expired` for
+    /// one outside of its validity period, and `Error::WitnessVerification`
+    /// or `Error::UnknownPermission` for one that is outdated, revoked or not
+    /// yet in effect.
+    pub async fn authorize(
+        &self,
+        perm: &Permission,
+        action: &str,
+    ) -> Result<(), Error> {
+        self.authorize_with(AuthorizeRequest {
+            perm: perm.clone(),
+            action: action.to_owned(),
+            attributes: Attributes::new(),
+            proof: None,
+        }).await
+    }
+
+    /// Authorize an action like `authorize`, but with a Permission bound to
+    /// the subject whose key is given.
+    ///
+    /// A fresh proof is signed with the key for every action, and
+    /// `Error::SubjectProof` is returned if the Permission is bound to a
+    /// different subject.
+    pub async fn authorize_as(
+        &self,
+        perm: &Permission,
+        action: &str,
+        key: &SubjectKey,
+    ) -> Result<(), Error> {
+        let attributes = Attributes::new();
+        let audience = self.audience().await?;
+        self.authorize_with(AuthorizeRequest {
+            proof: Some(key.prove(&audience, perm, action, &attributes)),
+            perm: perm.clone(),
+            action: action.to_owned(),
+            attributes,
+        }).await
+    }
+
+    /// Get the audience that proofs of subjects are signed for with
+    /// `SubjectKey::prove`.
+    ///
+    /// This is the hex encoded key the Authority of the client's tenant
+    /// signs Checkpoints with, so a proof is only accepted by the tenant it
+    /// was made for.
+    pub async fn audience(&self) -> Result<String, Error> {
+        let resp = self.client.get("/signing-key").await?;
+        let bytes = to_bytes(resp.into_body()).await;
+        match from_json(&bytes) {
+            Some(key) => Ok(key),
+            None => Err(Error::Decode),
+        }
+    }
+
+    /// Authorize an action like `authorize`, but with request attributes
+    /// for the Permission's conditions and, optionally, a proof of its
+    /// subject.
+    ///
+    /// `Error::ConditionFailed` is returned for a request that does not meet
+    /// a condition on the action.
+    pub async fn authorize_with(
+        &self,
+        req: AuthorizeRequest,
+    ) -> Result<(), Error> {
+        self.client.send_json(Method::POST, "/action", req).await?;
+        Ok(())
+    }
+
+    /// Authorize every action of a list for every Permission of a list in
+    /// a single request, e.g. to decide which controls of a page to show.
+    ///
+    /// The decisions are returned by Permission and then by action, each as
+    /// `authorize_with` would return it. Permissions bound to a subject need
+    /// a proof for each of their actions in `proofs`.
+    pub async fn authorize_all(
+        &self,
+        req: &ActionsRequest,
+    ) -> Result<Vec<Vec<Result<(), Error>>>, Error> {
+        let resp = self.client.send_json(Method::POST, "/actions", req).await?;
+        let bytes = to_bytes(resp.into_body()).await;
+        match from_json::<Vec<Vec<Decision>>, _>(&bytes) {
+            Some(rows) => Ok(rows.into_iter()
+                .map(|row| row.into_iter().map(Decision::into_result).collect())
+                .collect()),
+            None => Err(Error::Decode),
+        }
+    }
+
+    /// Get the current Witness of a Permission.
+    ///
+    /// The owner of a Permission presents it along with the Witness to
+    /// services that verify actions offline with a `verifier::Verifier`.
+    /// Witnesses change every update window, so a fresh one should be
+    /// fetched at least once per window.
+    pub async fn witness(
+        &self,
+        perm: &Permission,
+    ) -> Result<Witness<Mpz>, Error> {
+        let path = format!("/witness/{}", perm.nonce);
+        let resp = self.client.get(&path).await?;
+        let bytes = to_bytes(resp.into_body()).await;
+        match from_json(&bytes) {
+            Some(witness) => Ok(witness),
+            None => Err(Error::Decode),
+        }
+    }
+}
@@ -0,0 +1,351 @@
+use serde::{Serialize, Deserialize};
+use std::{collections::BTreeMap, net::IpAddr};
+use crate::{error::Error, matcher, permission::Action};
+
+/// The attributes of a request to perform an action, such as the channel a
+/// message is sent to or the amount being transferred, by name.
+pub type Attributes = BTreeMap<String, String>;
+
+/// How a condition compares a request attribute to its values.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Operator {
+
+    /// The attribute equals one of the values.
+    OneOf,
+
+    /// The attribute is an integer no greater than the single value.
+    AtMost,
+
+    /// The attribute is an integer no less than the single value.
+    AtLeast,
+
+    /// The attribute is an IP address within one of the networks given in
+    /// CIDR notation, such as `10.0.0.0/8`.
+    InNetwork,
+}
+
+/// A condition that requests to perform an action must meet, such as
+/// "send-message only to channel X" or "transfer up to 1000".
+///
+/// Conditions are part of the accumulated element like the rest of a
+/// Permission, so they cannot be dropped or altered without invalidating
+/// its Witness.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Condition {
+
+    /// The pattern of the actions the condition applies to, as described by
+    /// `matcher::matches`.
+    pub action: Action,
+
+    /// The name of the request attribute the condition tests.
+    pub attribute: String,
+
+    /// How the attribute is compared to the values.
+    pub op: Operator,
+
+    /// The values the attribute is compared to.
+    pub values: Vec<String>,
+}
+
+impl Condition {
+
+    /// Ensure the condition can be evaluated.
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.action.is_empty() || self.attribute.is_empty() {
+            return Err(self.invalid("no action or attribute"));
+        }
+        match self.op {
+            Operator::OneOf => {
+                if self.values.is_empty() {
+                    return Err(self.invalid("no values"));
+                }
+            },
+            Operator::AtMost | Operator::AtLeast => {
+                let bound = match &self.values[..] {
+                    [bound] => bound,
+                    _ => {
+                        return Err(self.invalid("not a single bound"));
+                    },
+                };
+                if bound.parse::<i64>().is_err() {
+                    return Err(self.invalid("bound is not an integer"));
+                }
+            },
+            Operator::InNetwork => {
+                if self.values.is_empty() {
+                    return Err(self.invalid("no networks"));
+                }
+                for network in self.values.iter() {
+                    if parse_network(network).is_none() {
+                        return Err(self.invalid("invalid network"));
+                    }
+                }
+            },
+        }
+        Ok(())
+    }
+
+    /// Whether a request attribute meets the condition.
+    ///
+    /// Values that cannot be parsed never match, so a condition that was not
+    /// validated fails closed.
+    pub fn test(&self, value: &str) -> bool {
+        match self.op {
+            Operator::OneOf => self.values.iter().any(|v| v == value),
+            Operator::AtMost | Operator::AtLeast => {
+                let bound = match &self.values[..] {
+                    [bound] => Some(bound.parse::<i64>()),
+                    _ => None,
+                };
+                match (self.op, (value.parse::<i64>(), bound)) {
+                    (Operator::AtMost, (Ok(value), Some(Ok(bound)))) => {
+                        value <= bound
+                    },
+                    (Operator::AtLeast, (Ok(value), Some(Ok(bound)))) => {
+                        value >= bound
+                    },
+                    _ => false,
+                }
+            },
+            Operator::InNetwork => {
+                let addr: IpAddr = match value.parse() {
+                    Ok(addr) => addr,
+                    Err(_) => {
+                        return false;
+                    },
+                };
+                self.values.iter()
+                    .filter_map(|network| parse_network(network))
+                    .any(|(network, len)| contains(network, len, addr))
+            },
+        }
+    }
+
+    /// Internal helper to describe why the condition is invalid.
+    fn invalid(&self, reason: &str) -> Error {
+        Error::InvalidCondition(format!(
+            "condition on {} of {}: {}",
+            self.attribute,
+            self.action,
+            reason,
+        ))
+    }
+}
+
+/// Evaluate the conditions of a Permission for a request to perform an
+/// action.
+///
+/// Every condition whose pattern matches the action must be met, so a
+/// request missing an attribute that a condition tests is refused.
+/// Conditions on other actions are ignored. Evaluation only depends on its
+/// arguments, so every party verifying the request reaches the same
+/// decision.
+pub fn evaluate(
+    conditions: &[Condition],
+    action: &str,
+    attributes: &Attributes,
+) -> Result<(), Error> {
+    for condition in conditions {
+        if !matcher::matches(&condition.action, action) {
+            continue;
+        }
+        let met = match attributes.get(&condition.attribute) {
+            Some(value) => condition.test(value),
+            None => false,
+        };
+        if !met {
+            return Err(Error::ConditionFailed(condition.attribute.clone()));
+        }
+    }
+    Ok(())
+}
+
+/// Internal helper to parse a network in CIDR notation.
+fn parse_network(network: &str) -> Option<(IpAddr, u32)> {
+    let (addr, len) = network.split_once('/')?;
+    let addr: IpAddr = addr.parse().ok()?;
+    let len: u32 = len.parse().ok()?;
+    let max = match addr {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    };
+    match len <= max {
+        true => Some((addr, len)),
+        false => None,
+    }
+}
+
+/// Internal helper to tell whether an address is within a network.
+fn contains(network: IpAddr, len: u32, addr: IpAddr) -> bool {
+    let (network, addr, bits) = match (network, addr) {
+        (IpAddr::V4(network), IpAddr::V4(addr)) => {
+            (u32::from(network) as u128, u32::from(addr) as u128, 32)
+        },
+        (IpAddr::V6(network), IpAddr::V6(addr)) => {
+            (u128::from(network), u128::from(addr), 128)
+        },
+        _ => {
+            return false;
+        },
+    };
+    if len == 0 {
+        return true;
+    }
+    let shift = bits - len;
+    network >> shift == addr >> shift
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build a condition on the `transfer` action.
+    fn condition(op: Operator, attribute: &str, values: &[&str]) -> Condition {
+        Condition {
+            action: "transfer".to_owned(),
+            attribute: attribute.to_owned(),
+            op,
+            values: values.iter().map(|v| v.to_string()).collect(),
+        }
+    }
+
+    /// Build the attributes of a request with a single attribute.
+    fn attributes(name: &str, value: &str) -> Attributes {
+        [(name.to_owned(), value.to_owned())].into_iter().collect()
+    }
+
+    /// Whether a request with a single attribute meets the conditions.
+    fn met(conditions: &[Condition], name: &str, value: &str) -> bool {
+        evaluate(conditions, "transfer", &attributes(name, value)).is_ok()
+    }
+
+    #[test]
+    fn one_of() {
+        let conditions = [condition(Operator::OneOf, "to", &["a", "b"])];
+        assert!(met(&conditions, "to", "a"));
+        assert!(met(&conditions, "to", "b"));
+        assert!(!met(&conditions, "to", "c"));
+        assert!(!met(&conditions, "to", ""));
+        assert!(condition(Operator::OneOf, "to", &[]).validate().is_err());
+    }
+
+    #[test]
+    fn at_most() {
+        let conditions = [condition(Operator::AtMost, "amount", &["1000"])];
+        assert!(met(&conditions, "amount", "-5"));
+        assert!(met(&conditions, "amount", "1000"));
+        assert!(!met(&conditions, "amount", "1001"));
+        assert!(!met(&conditions, "amount", "1e3"));
+        assert!(!met(&conditions, "amount", " 1"));
+    }
+
+    #[test]
+    fn at_least() {
+        let conditions = [condition(Operator::AtLeast, "amount", &["10"])];
+        assert!(met(&conditions, "amount", "10"));
+        assert!(met(&conditions, "amount", "11"));
+        assert!(!met(&conditions, "amount", "9"));
+        assert!(!met(&conditions, "amount", "ten"));
+    }
+
+    #[test]
+    fn bounds_must_be_single_integers() {
+        for values in [&[][..], &["1", "2"][..], &["1.5"][..]] {
+            let condition = condition(Operator::AtMost, "amount", values);
+            assert!(condition.validate().is_err());
+            // An unvalidated condition fails closed.
+            assert!(!met(&[condition], "amount", "1"));
+        }
+    }
+
+    #[test]
+    fn in_network() {
+        let conditions = [
+            condition(Operator::InNetwork, "ip", &["10.0.0.0/8"]),
+        ];
+        assert!(met(&conditions, "ip", "10.255.0.1"));
+        assert!(!met(&conditions, "ip", "11.0.0.1"));
+        assert!(!met(&conditions, "ip", "10.0.0.1/8"));
+        assert!(!met(&conditions, "ip", "host"));
+    }
+
+    #[test]
+    fn in_network_of_any_address() {
+        let v4 = [condition(Operator::InNetwork, "ip", &["0.0.0.0/0"])];
+        assert!(met(&v4, "ip", "0.0.0.0"));
+        assert!(met(&v4, "ip", "255.255.255.255"));
+        assert!(!met(&v4, "ip", "::1"));
+        let v6 = [condition(Operator::InNetwork, "ip", &["::/0"])];
+        assert!(met(&v6, "ip", "::"));
+        assert!(met(&v6, "ip", "ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff"));
+        assert!(!met(&v6, "ip", "127.0.0.1"));
+    }
+
+    #[test]
+    fn in_network_of_single_address() {
+        let v4 = [condition(Operator::InNetwork, "ip", &["192.0.2.7/32"])];
+        assert!(met(&v4, "ip", "192.0.2.7"));
+        assert!(!met(&v4, "ip", "192.0.2.6"));
+        assert!(!met(&v4, "ip", "192.0.2.8"));
+        let v6 = [
+            condition(Operator::InNetwork, "ip", &["2001:db8::7/128"]),
+        ];
+        assert!(met(&v6, "ip", "2001:db8::7"));
+        assert!(!met(&v6, "ip", "2001:db8::6"));
+        assert!(!met(&v6, "ip", "2001:db8::8"));
+    }
+
+    #[test]
+    fn in_network_requires_matching_family() {
+        // An IPv4-mapped IPv6 address is not within an IPv4 network, nor an
+        // IPv4 address within the IPv6 network it maps to.
+        let v4 = [condition(Operator::InNetwork, "ip", &["192.0.2.0/24"])];
+        assert!(!met(&v4, "ip", "::ffff:192.0.2.1"));
+        let v6 = [
+            condition(Operator::InNetwork, "ip", &["::ffff:192.0.2.0/120"]),
+        ];
+        assert!(met(&v6, "ip", "::ffff:192.0.2.1"));
+        assert!(!met(&v6, "ip", "192.0.2.1"));
+        // One network of the right family is enough.
+        let both = [condition(
+            Operator::InNetwork,
+            "ip",
+            &["2001:db8::/32", "192.0.2.0/24"],
+        )];
+        assert!(met(&both, "ip", "192.0.2.1"));
+        assert!(met(&both, "ip", "2001:db8::1"));
+    }
+
+    #[test]
+    fn networks_must_have_valid_prefix() {
+        for network in ["10.0.0.0/33", "::/129", "10.0.0.0", "10.0.0.0/-1"] {
+            let condition = condition(Operator::InNetwork, "ip", &[network]);
+            assert!(condition.validate().is_err());
+            assert!(!met(&[condition], "ip", "10.0.0.1"));
+        }
+    }
+
+    #[test]
+    fn evaluate_requires_every_matching_condition() {
+        let conditions = [
+            condition(Operator::AtMost, "amount", &["1000"]),
+            condition(Operator::OneOf, "to", &["a"]),
+            Condition {
+                action: "refund".to_owned(),
+                ..condition(Operator::OneOf, "to", &["b"])
+            },
+        ];
+        let mut attributes = attributes("amount", "10");
+        attributes.insert("to".to_owned(), "a".to_owned());
+        assert!(evaluate(&conditions, "transfer", &attributes).is_ok());
+        // A missing attribute is refused.
+        attributes.remove("to");
+        assert!(matches!(
+            evaluate(&conditions, "transfer", &attributes),
+            Err(Error::ConditionFailed(attribute)) if attribute == "to",
+        ));
+        // Conditions on other actions are ignored.
+        assert!(evaluate(&conditions, "delete", &attributes).is_ok());
+    }
+}
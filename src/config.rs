@@ -0,0 +1,774 @@
+use serde::{Serialize, Deserialize};
+use std::{collections::HashSet, net::SocketAddr, path::Path};
+use tokio_rustls::TlsAcceptor;
+use crate::{
+    checkpoint::parse_public_key,
+    peer::Credentials,
+    tenant,
+    tls::{acceptor, parse_fingerprint, ClientTls},
+    util::Client,
+    constant::{
+        AUTHORITY_ADDR,
+        AUTHORITY_DATA_DIR,
+        SYNCHRONIZER_ADDR,
+        UPDATE_WINDOW_MILLIS,
+        WORKER_ADDR,
+        WORKER_DATA_DIR,
+    },
+    error::Error,
+};
+
+/// The config file that is read if none is given explicitly.
+const DEFAULT_CONFIG_FILE: &str = "compauth.toml";
+
+/// Prefix of the environment variables that override settings.
+const ENV_PREFIX: &str = "COMPAUTH_";
+
+/// The names of all settings.
+///
+/// A setting named `update_window_millis` is read from the key of the same
+/// name in the config file, from the `COMPAUTH_UPDATE_WINDOW_MILLIS`
+/// environment variable and from the `--update-window-millis` flag, in
+/// increasing order of precedence.
+const SETTINGS: [&str; 25] = [
+    "synchronizer_addr",
+    "authority_addr",
+    "worker_addr",
+    "update_window_millis",
+    "tenants",
+    "authority_data_dir",
+    "authority_sealed",
+    "authority_key_store",
+    "authority_key_secret",
+    "authority_pkcs11_module",
+    "authority_pkcs11_pin",
+    "authority_pkcs11_label",
+    "worker_data_dir",
+    "authority_signing_key",
+    "tenant_signing_keys",
+    "authority_secret",
+    "worker_secret",
+    "tls_ca",
+    "synchronizer_tls_cert",
+    "synchronizer_tls_key",
+    "authority_tls_cert",
+    "authority_tls_key",
+    "authority_tls_pin",
+    "worker_tls_cert",
+    "worker_tls_key",
+];
+
+const USAGE: &str = "\
+Options:
+    --config <path>                 Read settings from a TOML file
+                                    (default: compauth.toml, if present)
+    --synchronizer-addr <addr>      Address of the Synchronizer
+    --authority-addr <addr>         Address of the Authority
+    --worker-addr <addr>            Address of the Worker
+    --update-window-millis <ms>     Length of the update window
+    --tenants <ids>                 Comma separated IDs of the tenants
+                                    served besides the default one
+    --authority-data-dir <path>     Directory holding the Authority's state
+    --authority-sealed <bool>       Start the Authority sealed, to be unsealed
+                                    with shares of its private key
+    --authority-key-store <store>   Keep the Authority's private key
+                                    encrypted, in a plain file or in a
+                                    pkcs11 token (default: encrypted)
+    --authority-key-secret <hex>    Key the private key is encrypted with
+    --authority-pkcs11-module <path>
+                                    PKCS#11 module of the token
+    --authority-pkcs11-pin <pin>    User PIN of the token
+    --authority-pkcs11-label <label>
+                                    Label of the token's wrapping key
+    --worker-data-dir <path>        Directory holding the Worker's state
+    --authority-signing-key <hex>   Key the Authority signs checkpoints
+                                    with, which the Worker requires
+    --tenant-signing-keys <keys>    Comma separated <id>=<hex> pairs of the
+                                    keys of the tenants' Authorities
+    --authority-secret <hex>        Secret peers of the Authority sign with
+    --worker-secret <hex>           Secret peers of the Worker sign with
+    --tls-ca <path>                 CA certificates that services using TLS
+                                    are verified against
+    --synchronizer-tls-cert <path>  Certificate chain of the Synchronizer
+    --synchronizer-tls-key <path>   Private key of the Synchronizer
+    --authority-tls-cert <path>     Certificate chain of the Authority
+    --authority-tls-key <path>      Private key of the Authority
+    --authority-tls-pin <hex>       SHA-256 fingerprint of the Authority's
+                                    certificate
+    --worker-tls-cert <path>        Certificate chain of the Worker
+    --worker-tls-key <path>         Private key of the Worker
+    --print-config                  Print the effective settings and exit
+    --help                          Print this message and exit
+
+Every setting may also be given in the environment, e.g.
+COMPAUTH_AUTHORITY_ADDR=127.0.0.1:4001.";
+
+/// Settings shared by the Synchronizer, Authority and Worker.
+///
+/// All three services must agree on the addresses, so they are normally
+/// started from the same config file.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+
+    /// The address the Synchronizer listens on.
+    pub synchronizer_addr: String,
+
+    /// The address the Authority listens on.
+    pub authority_addr: String,
+
+    /// The address the Worker listens on.
+    pub worker_addr: String,
+
+    /// The length of the update window in milliseconds.
+    pub update_window_millis: u64,
+
+    /// The comma separated IDs of the tenants served besides the default
+    /// one. Each tenant has its own accumulation and update window, and
+    /// keeps its state in a `tenants/<id>` subdirectory of the data
+    /// directories.
+    pub tenants: String,
+
+    /// The directory in which the Authority persists its state.
+    pub authority_data_dir: String,
+
+    /// Whether the Authority starts sealed. A sealed Authority does not
+    /// store its private key, which is instead reconstructed from the shares
+    /// security officers submit to `POST /unseal`.
+    pub authority_sealed: bool,
+
+    /// Where the Authority keeps its private key: "encrypted", the default,
+    /// for encrypted with `authority_key_secret` in its data directory,
+    /// "file" for plain text, or "pkcs11" for wrapped by a key in a PKCS#11
+    /// token, if the pkcs11 feature is enabled.
+    pub authority_key_store: String,
+
+    /// The hex encoded 256 bit key the Authority's private key is encrypted
+    /// with. It is required by the Authority when its key store is
+    /// "encrypted" and it is not sealed.
+    pub authority_key_secret: String,
+
+    /// The path of the PKCS#11 module of the token.
+    pub authority_pkcs11_module: String,
+
+    /// The user PIN of the token.
+    pub authority_pkcs11_pin: String,
+
+    /// The label of the AES key in the token that wraps the private key.
+    pub authority_pkcs11_label: String,
+
+    /// The directory in which the Worker persists its state.
+    pub worker_data_dir: String,
+
+    /// The hex encoded public key the Authority signs Checkpoints with. The
+    /// Worker requires it, and only accepts Checkpoints signed with it.
+    pub authority_signing_key: String,
+
+    /// The comma separated `<id>=<hex>` pairs of the public keys the
+    /// Authorities of the tenants sign Checkpoints with. The Worker requires
+    /// one for every tenant.
+    pub tenant_signing_keys: String,
+
+    /// The hex encoded secret that requests to the Authority are signed
+    /// with. The Authority and the Synchronizer require it.
+    pub authority_secret: String,
+
+    /// The hex encoded secret that requests to the Worker are signed with.
+    /// The Worker and the Synchronizer require it.
+    pub worker_secret: String,
+
+    /// The PEM file holding the certificate authorities that services
+    /// serving TLS are verified against. It is required if any service
+    /// serves TLS.
+    pub tls_ca: String,
+
+    /// The PEM file holding the Synchronizer's certificate chain. If empty,
+    /// the Synchronizer serves plain HTTP.
+    pub synchronizer_tls_cert: String,
+
+    /// The PEM file holding the Synchronizer's private key.
+    pub synchronizer_tls_key: String,
+
+    /// The PEM file holding the Authority's certificate chain. If empty,
+    /// the Authority serves plain HTTP.
+    pub authority_tls_cert: String,
+
+    /// The PEM file holding the Authority's private key.
+    pub authority_tls_key: String,
+
+    /// The hex encoded SHA-256 fingerprint of the certificate the Authority
+    /// must present. If empty, any certificate issued by `tls_ca` is
+    /// accepted.
+    pub authority_tls_pin: String,
+
+    /// The PEM file holding the Worker's certificate chain. If empty, the
+    /// Worker serves plain HTTP.
+    pub worker_tls_cert: String,
+
+    /// The PEM file holding the Worker's private key.
+    pub worker_tls_key: String,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            synchronizer_addr: SYNCHRONIZER_ADDR.to_owned(),
+            authority_addr: AUTHORITY_ADDR.to_owned(),
+            worker_addr: WORKER_ADDR.to_owned(),
+            update_window_millis: UPDATE_WINDOW_MILLIS,
+            tenants: String::new(),
+            authority_data_dir: AUTHORITY_DATA_DIR.to_owned(),
+            authority_sealed: false,
+            authority_key_store: "encrypted".to_owned(),
+            authority_key_secret: String::new(),
+            authority_pkcs11_module: String::new(),
+            authority_pkcs11_pin: String::new(),
+            authority_pkcs11_label: String::new(),
+            worker_data_dir: WORKER_DATA_DIR.to_owned(),
+            authority_signing_key: String::new(),
+            tenant_signing_keys: String::new(),
+            authority_secret: String::new(),
+            worker_secret: String::new(),
+            tls_ca: String::new(),
+            synchronizer_tls_cert: String::new(),
+            synchronizer_tls_key: String::new(),
+            authority_tls_cert: String::new(),
+            authority_tls_key: String::new(),
+            authority_tls_pin: String::new(),
+            worker_tls_cert: String::new(),
+            worker_tls_key: String::new(),
+        }
+    }
+}
+
+impl Config {
+
+    /// Load the configuration of a service from its config file, the
+    /// environment and its command-line arguments.
+    ///
+    /// This is intended to be called first thing in `main`: on an error, or
+    /// after handling `--help` or `--print-config`, the process exits. It is
+    /// an error for any of the `required` settings to be empty.
+    pub fn load(required: &[&str]) -> Self {
+        let args: Vec<String> = std::env::args().skip(1).collect();
+        Self::load_from(&args, "", required)
+    }
+
+    /// Load a configuration like `load`, but from the given arguments.
+    ///
+    /// This allows tools to take settings alongside arguments of their own.
+    /// `usage` is printed before the description of the settings.
+    pub fn load_from(
+        args: &[String],
+        usage: &str,
+        required: &[&str],
+    ) -> Self {
+        let env: Vec<(String, String)> = std::env::vars().collect();
+        let res = Self::parse(args, &env).and_then(|command| {
+            if let Command::Run(config) = &command {
+                config.require(required)?;
+            }
+            Ok(command)
+        });
+        match res {
+            Ok(Command::Run(config)) => config,
+            Ok(Command::Print(config)) => {
+                print!("{}", toml::to_string(&config).unwrap());
+                std::process::exit(0);
+            },
+            Ok(Command::Help) => {
+                println!("{}{}", usage, USAGE);
+                std::process::exit(0);
+            },
+            Err(err) => {
+                eprintln!("{}\n\n{}{}", err, usage, USAGE);
+                std::process::exit(2);
+            },
+        }
+    }
+
+    /// Build a configuration from command-line arguments and environment
+    /// variables, reading the config file they point to.
+    fn parse(
+        args: &[String],
+        env: &[(String, String)],
+    ) -> Result<Command, Error> {
+        // Split the arguments into settings and switches.
+        let mut flags = Vec::new();
+        let mut path = None;
+        let mut print = false;
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            let arg = match arg.strip_prefix("--") {
+                Some(arg) => arg,
+                None => {
+                    return Err(invalid(format!("unexpected argument {}", arg)));
+                },
+            };
+            // Accept both "--name value" and "--name=value".
+            let (name, value) = match arg.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_owned())),
+                None => (arg, None),
+            };
+            match name {
+                "help" => {
+                    return Ok(Command::Help);
+                },
+                "print-config" => {
+                    print = true;
+                    continue;
+                },
+                _ => {},
+            }
+            let value = match value.or_else(|| iter.next().cloned()) {
+                Some(value) => value,
+                None => {
+                    let msg = format!("missing value for --{}", name);
+                    return Err(invalid(msg));
+                },
+            };
+            if name == "config" {
+                path = Some(value);
+            } else {
+                flags.push((name.replace('-', "_"), value));
+            }
+        }
+        // Read the config file. An explicitly requested file must exist.
+        let env_path = env.iter()
+            .find(|(name, _)| name == "COMPAUTH_CONFIG")
+            .map(|(_, value)| value.clone());
+        let mut config = match path.or(env_path) {
+            Some(path) => Self::read(Path::new(&path))?,
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => {
+                Self::read(Path::new(DEFAULT_CONFIG_FILE))?
+            },
+            None => Config::default(),
+        };
+        // Apply the environment, then the flags.
+        for name in SETTINGS.iter() {
+            let var = format!("{}{}", ENV_PREFIX, name.to_uppercase());
+            if let Some((_, value)) = env.iter().find(|(n, _)| n == &var) {
+                config.set(name, value)?;
+            }
+        }
+        for (name, value) in flags.iter() {
+            config.set(name, value)?;
+        }
+        config.validate()?;
+        if print {
+            Ok(Command::Print(config))
+        } else {
+            Ok(Command::Run(config))
+        }
+    }
+
+    /// Read a config file.
+    fn read(path: &Path) -> Result<Self, Error> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) => {
+                return Err(invalid(format!("{}: {}", path.display(), e)));
+            },
+        };
+        match toml::from_str(&text) {
+            Ok(config) => Ok(config),
+            Err(e) => Err(invalid(format!("{}: {}", path.display(), e))),
+        }
+    }
+
+    /// Override a setting by name.
+    fn set(&mut self, name: &str, value: &str) -> Result<(), Error> {
+        let value = value.to_owned();
+        match name {
+            "synchronizer_addr" => self.synchronizer_addr = value,
+            "authority_addr" => self.authority_addr = value,
+            "worker_addr" => self.worker_addr = value,
+            "update_window_millis" => match value.parse() {
+                Ok(millis) => self.update_window_millis = millis,
+                Err(_) => {
+                    return Err(invalid(
+                        "update_window_millis must be an integer",
+                    ));
+                },
+            },
+            "tenants" => self.tenants = value,
+            "authority_data_dir" => self.authority_data_dir = value,
+            "authority_sealed" => match value.parse() {
+                Ok(sealed) => self.authority_sealed = sealed,
+                Err(_) => {
+                    return Err(invalid(
+                        "authority_sealed must be true or false",
+                    ));
+                },
+            },
+            "authority_key_store" => self.authority_key_store = value,
+            "authority_key_secret" => self.authority_key_secret = value,
+            "authority_pkcs11_module" => self.authority_pkcs11_module = value,
+            "authority_pkcs11_pin" => self.authority_pkcs11_pin = value,
+            "authority_pkcs11_label" => self.authority_pkcs11_label = value,
+            "worker_data_dir" => self.worker_data_dir = value,
+            "authority_signing_key" => self.authority_signing_key = value,
+            "tenant_signing_keys" => self.tenant_signing_keys = value,
+            "authority_secret" => self.authority_secret = value,
+            "worker_secret" => self.worker_secret = value,
+            "tls_ca" => self.tls_ca = value,
+            "synchronizer_tls_cert" => self.synchronizer_tls_cert = value,
+            "synchronizer_tls_key" => self.synchronizer_tls_key = value,
+            "authority_tls_cert" => self.authority_tls_cert = value,
+            "authority_tls_key" => self.authority_tls_key = value,
+            "authority_tls_pin" => self.authority_tls_pin = value,
+            "worker_tls_cert" => self.worker_tls_cert = value,
+            "worker_tls_key" => self.worker_tls_key = value,
+            _ => {
+                return Err(invalid(format!("unknown setting {}", name)));
+            },
+        }
+        Ok(())
+    }
+
+    /// Ensure the given settings are not empty.
+    fn require(&self, names: &[&str]) -> Result<(), Error> {
+        for name in names {
+            let value = match *name {
+                "authority_signing_key" => {
+                    // The keys of the tenants must be pinned as well.
+                    for id in self.tenant_ids() {
+                        if self.tenant_signing_key(&id).is_none() {
+                            return Err(invalid(format!(
+                                "tenant_signing_keys must pin the key of {}",
+                                id,
+                            )));
+                        }
+                    }
+                    &self.authority_signing_key
+                },
+                "authority_key_secret" => {
+                    if self.authority_sealed
+                        || self.authority_key_store != "encrypted" {
+                        continue;
+                    }
+                    &self.authority_key_secret
+                },
+                "authority_secret" => &self.authority_secret,
+                "worker_secret" => &self.worker_secret,
+                _ => {
+                    return Err(invalid(format!("unknown setting {}", name)));
+                },
+            };
+            if value.is_empty() {
+                return Err(invalid(format!("{} must be set", name)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Ensure the settings are usable.
+    fn validate(&self) -> Result<(), Error> {
+        let mut addrs = HashSet::new();
+        for addr in [
+            &self.synchronizer_addr,
+            &self.authority_addr,
+            &self.worker_addr,
+        ] {
+            let addr: SocketAddr = match addr.parse() {
+                Ok(addr) => addr,
+                Err(_) => {
+                    return Err(invalid(format!(
+                        "service address {} is not of the form ip:port",
+                        addr,
+                    )));
+                },
+            };
+            if !addrs.insert(addr) {
+                return Err(invalid("service addresses must be distinct"));
+            }
+        }
+        if self.update_window_millis == 0 {
+            return Err(invalid(
+                "update_window_millis must be greater than zero",
+            ));
+        }
+        if self.authority_data_dir.is_empty()
+            || self.worker_data_dir.is_empty() {
+            return Err(invalid("data directories must not be empty"));
+        }
+        if self.authority_data_dir == self.worker_data_dir {
+            return Err(invalid("data directories must be distinct"));
+        }
+        let mut tenants = HashSet::new();
+        for id in self.tenant_ids() {
+            tenant::validate(&id)?;
+            if !tenants.insert(id) {
+                return Err(invalid("tenant IDs must be distinct"));
+            }
+        }
+        if !self.authority_signing_key.is_empty()
+            && parse_public_key(&self.authority_signing_key).is_err() {
+            return Err(invalid(
+                "authority_signing_key must be a hex encoded Ed25519 key",
+            ));
+        }
+        for pair in self.tenant_signing_key_pairs() {
+            match pair {
+                Some((id, key)) if tenants.contains(id) => {
+                    if parse_public_key(key).is_err() {
+                        return Err(invalid(format!(
+                            "the signing key of {} must be a hex encoded \
+                             Ed25519 key",
+                            id,
+                        )));
+                    }
+                },
+                Some((id, _)) => {
+                    return Err(invalid(format!(
+                        "tenant_signing_keys names unknown tenant {}",
+                        id,
+                    )));
+                },
+                None => {
+                    return Err(invalid(
+                        "tenant_signing_keys must be <id>=<hex> pairs",
+                    ));
+                },
+            }
+        }
+        for secret in [&self.authority_secret, &self.worker_secret] {
+            if !secret.is_empty() {
+                Credentials::new(secret)?;
+            }
+        }
+        match self.authority_key_store.as_str() {
+            "file" => {},
+            // The secret is only required by the Authority, which asks for
+            // it when loading its configuration.
+            "encrypted" if !self.authority_key_secret.is_empty() => {
+                let secret = hex::decode(&self.authority_key_secret);
+                if !secret.is_ok_and(|secret| secret.len() == 32) {
+                    return Err(invalid(
+                        "authority_key_secret must be 32 hex encoded bytes",
+                    ));
+                }
+            },
+            "encrypted" => {},
+            "pkcs11" if cfg!(feature = "pkcs11") => {
+                if self.authority_pkcs11_module.is_empty()
+                    || self.authority_pkcs11_label.is_empty() {
+                    return Err(invalid(
+                        "the pkcs11 key store needs a module and a label",
+                    ));
+                }
+            },
+            store => {
+                return Err(invalid(format!("unsupported key store {}", store)));
+            },
+        }
+        let mut tls = false;
+        for (cert, key) in [
+            (&self.synchronizer_tls_cert, &self.synchronizer_tls_key),
+            (&self.authority_tls_cert, &self.authority_tls_key),
+            (&self.worker_tls_cert, &self.worker_tls_key),
+        ] {
+            if cert.is_empty() != key.is_empty() {
+                return Err(invalid(
+                    "TLS certificates and keys must be set together",
+                ));
+            }
+            tls |= !cert.is_empty();
+        }
+        if tls && self.tls_ca.is_empty() {
+            return Err(invalid("tls_ca must be set if any service uses TLS"));
+        }
+        if !self.authority_tls_pin.is_empty() {
+            if self.authority_tls_cert.is_empty() {
+                return Err(invalid(
+                    "authority_tls_pin requires the Authority to use TLS",
+                ));
+            }
+            parse_fingerprint(&self.authority_tls_pin)?;
+        }
+        Ok(())
+    }
+
+    /// Create the TLS acceptor of the Synchronizer, if it serves TLS.
+    pub fn synchronizer_tls(&self) -> Result<Option<TlsAcceptor>, Error> {
+        Self::listener(&self.synchronizer_tls_cert, &self.synchronizer_tls_key)
+    }
+
+    /// Create the TLS acceptor of the Authority, if it serves TLS.
+    pub fn authority_tls(&self) -> Result<Option<TlsAcceptor>, Error> {
+        Self::listener(&self.authority_tls_cert, &self.authority_tls_key)
+    }
+
+    /// Create the TLS acceptor of the Worker, if it serves TLS.
+    pub fn worker_tls(&self) -> Result<Option<TlsAcceptor>, Error> {
+        Self::listener(&self.worker_tls_cert, &self.worker_tls_key)
+    }
+
+    /// Create a client of the Synchronizer.
+    pub fn synchronizer_client(&self) -> Result<Client, Error> {
+        self.client(&self.synchronizer_addr, &self.synchronizer_tls_cert, "")
+    }
+
+    /// Create a client of the Authority.
+    ///
+    /// Requests are not signed; see `with_credentials`.
+    pub fn authority_client(&self) -> Result<Client, Error> {
+        self.client(
+            &self.authority_addr,
+            &self.authority_tls_cert,
+            &self.authority_tls_pin,
+        )
+    }
+
+    /// Return the IDs of the tenants served besides the default one.
+    pub fn tenant_ids(&self) -> Vec<String> {
+        self.tenants.split(',')
+            .map(|id| id.trim())
+            .filter(|id| !id.is_empty())
+            .map(|id| id.to_owned())
+            .collect()
+    }
+
+    /// Internal helper to split `tenant_signing_keys` into pairs of tenant
+    /// IDs and keys, or `None` for each malformed entry.
+    fn tenant_signing_key_pairs(&self) -> Vec<Option<(&str, &str)>> {
+        self.tenant_signing_keys.split(',')
+            .map(|pair| pair.trim())
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                pair.split_once('=')
+                    .map(|(id, key)| (id.trim(), key.trim()))
+            })
+            .collect()
+    }
+
+    /// Return the signing key pinned for a tenant, if any.
+    fn tenant_signing_key(&self, tenant: &str) -> Option<String> {
+        self.tenant_signing_key_pairs()
+            .into_iter()
+            .flatten()
+            .find(|(id, _)| *id == tenant)
+            .map(|(_, key)| key.to_owned())
+    }
+
+    /// Return the configuration of the services of a tenant.
+    ///
+    /// The tenant's Authority and Worker keep their state, including the
+    /// Authority's private and signing keys, in a `tenants/<id>`
+    /// subdirectory of the data directories. The tenant's signing key is
+    /// the one pinned for it in `tenant_signing_keys`.
+    pub fn for_tenant(&self, tenant: &str) -> Config {
+        let dir = |base: &str| {
+            Path::new(base).join("tenants").join(tenant)
+                .to_string_lossy()
+                .into_owned()
+        };
+        Config {
+            authority_data_dir: dir(&self.authority_data_dir),
+            worker_data_dir: dir(&self.worker_data_dir),
+            authority_signing_key: self.tenant_signing_key(tenant)
+                .unwrap_or_default(),
+            tenant_signing_keys: String::new(),
+            tenants: String::new(),
+            ..self.clone()
+        }
+    }
+
+    /// Create a client of the Worker.
+    ///
+    /// Requests are not signed; see `with_credentials`.
+    pub fn worker_client(&self) -> Result<Client, Error> {
+        self.client(&self.worker_addr, &self.worker_tls_cert, "")
+    }
+
+    /// Create the TLS settings for clients of the Synchronizer's public
+    /// API, if it serves TLS.
+    pub fn synchronizer_client_tls(&self) -> Result<Option<ClientTls>, Error> {
+        match self.synchronizer_tls_cert.as_str() {
+            "" => Ok(None),
+            _ => Ok(Some(ClientTls::new(&self.tls_ca, None)?)),
+        }
+    }
+
+    /// Internal helper to create the TLS acceptor of a service.
+    fn listener(cert: &str, key: &str) -> Result<Option<TlsAcceptor>, Error> {
+        match cert {
+            "" => Ok(None),
+            cert => Ok(Some(acceptor(cert, key)?)),
+        }
+    }
+
+    /// Internal helper to create a client of a service, which uses TLS if
+    /// the service has a certificate.
+    fn client(
+        &self,
+        addr: &str,
+        cert: &str,
+        pin: &str,
+    ) -> Result<Client, Error> {
+        if cert.is_empty() {
+            return Ok(Client::new(addr));
+        }
+        let pin = match pin {
+            "" => None,
+            pin => Some(pin),
+        };
+        Ok(Client::with_tls(addr, &ClientTls::new(&self.tls_ca, pin)?))
+    }
+}
+
+/// Describe an invalid configuration.
+fn invalid<S: Into<String>>(msg: S) -> Error {
+    Error::Config(msg.into())
+}
+
+/// What a service should do after its configuration has been parsed.
+enum Command {
+    Run(Config),
+    Print(Config),
+    Help,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn for_tenant_separates_state() {
+        let config = Config {
+            tenants: "billing,support".to_owned(),
+            authority_data_dir: "data/authority".to_owned(),
+            worker_data_dir: "data/worker".to_owned(),
+            authority_signing_key: "00".repeat(32),
+            tenant_signing_keys: format!(
+                "billing={},support={}",
+                "11".repeat(32),
+                "22".repeat(32),
+            ),
+            ..Config::default()
+        };
+        let billing = config.for_tenant("billing");
+        let support = config.for_tenant("support");
+        let dir = |path: &str| Path::new(path).to_path_buf();
+        assert_eq!(
+            dir(&billing.authority_data_dir),
+            dir("data/authority/tenants/billing"),
+        );
+        assert_eq!(
+            dir(&billing.worker_data_dir),
+            dir("data/worker/tenants/billing"),
+        );
+        assert_eq!(
+            dir(&support.authority_data_dir),
+            dir("data/authority/tenants/support"),
+        );
+        assert_eq!(
+            dir(&support.worker_data_dir),
+            dir("data/worker/tenants/support"),
+        );
+        // Each tenant's Worker only trusts the key pinned for its tenant.
+        assert_eq!(billing.authority_signing_key, "11".repeat(32));
+        assert_eq!(support.authority_signing_key, "22".repeat(32));
+        assert!(billing.tenant_ids().is_empty());
+    }
+}
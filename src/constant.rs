@@ -1,4 +1,10 @@
 pub const SYNCHRONIZER_ADDR: &str = "127.0.0.1:3000";
 pub const AUTHORITY_ADDR: &str = "127.0.0.1:3001";
 pub const WORKER_ADDR: &str = "127.0.0.1:3002";
-pub const UPDATE_WINDOW_MILLIS: u64 = 1 * 60 * 1000;
+pub const UPDATE_WINDOW_MILLIS: u64 = 60 * 1000;
+pub const AUTHORITY_DATA_DIR: &str = "data/authority";
+pub const WORKER_DATA_DIR: &str = "data/worker";
+pub const CHECKPOINT_HISTORY: usize = 1024;
+pub const MAX_CLOCK_SKEW_MILLIS: u64 = 30 * 1000;
+pub const MIN_SECRET_BYTES: usize = 16;
+pub const ROTATION_POLL_MILLIS: u64 = 1000;
@@ -0,0 +1,293 @@
+use serde::{Serialize, Deserialize};
+use std::collections::{HashMap, HashSet};
+use crate::{
+    error::Error,
+    matcher::{self, DENY},
+    permission::{Action, Nonce, Permission},
+    request::{DelegateRequest, DelegatedPermission},
+};
+
+/// The action a subject proves when delegating a Permission bound to it.
+///
+/// It starts with `matcher::DENY`, so it can never be requested as an
+/// action and a proof for it cannot be used to perform one.
+pub const DELEGATE: &str = "!delegate";
+
+/// The Permission a child Permission was delegated from, at the version
+/// the child was last derived from.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct Parent {
+
+    /// The parent's Nonce.
+    pub nonce: Nonce,
+
+    /// The parent's version.
+    pub version: usize,
+}
+
+/// Derive a child Permission that allows a subset of a parent's actions.
+///
+/// Each requested allow entry must be covered by one of the parent's allow
+/// entries, including those of its roles, and the child also carries every
+/// deny entry and condition of the parent. The child expires no later than
+/// the parent and is bound to the parent's subject unless another one is
+/// requested, so it never allows more than the parent. The returned
+/// Permission has no Nonce yet.
+pub fn derive(
+    parent: &Permission,
+    req: &DelegateRequest,
+) -> Result<Permission, Error> {
+    if req.actions.is_empty() {
+        return Err(Error::BadRequest);
+    }
+    for action in req.actions.iter() {
+        if !action.starts_with(DENY) && !covered(parent, action) {
+            return Err(Error::Delegation(format!(
+                "{} is not allowed by the parent",
+                action,
+            )));
+        }
+    }
+    let expires_at = match (req.expires_at, parent.expires_at) {
+        (Some(child), Some(parent)) => Some(child.min(parent)),
+        (child, parent) => child.or(parent),
+    };
+    Ok(Permission {
+        nonce: 0.into(),
+        actions: with_denials(parent, req.actions.clone()),
+        version: 0,
+        not_before: parent.not_before,
+        expires_at,
+        roles: Vec::new(),
+        subject: req.subject.clone().or_else(|| parent.subject.clone()),
+        conditions: parent.conditions.clone(),
+        parent: Some(Parent {
+            nonce: parent.nonce,
+            version: parent.version,
+        }),
+    })
+}
+
+/// Derive the next version of a child Permission from the current version
+/// of its parent.
+///
+/// Allow entries the parent no longer covers are dropped, and the parent's
+/// current deny entries, conditions and expiry are applied. Returns `None`
+/// if the child is left without any allow entry, in which case it should
+/// be revoked. The version is left for the caller to increment.
+pub fn rederive(parent: &Permission, child: &Permission) -> Option<Permission> {
+    let actions: Vec<Action> = child.actions.iter()
+        .filter(|action| action.starts_with(DENY) || covered(parent, action))
+        .cloned()
+        .collect();
+    if actions.iter().all(|action| action.starts_with(DENY)) {
+        return None;
+    }
+    let expires_at = match (child.expires_at, parent.expires_at) {
+        (Some(child), Some(parent)) => Some(child.min(parent)),
+        (child, parent) => child.or(parent),
+    };
+    Some(Permission {
+        actions: with_denials(parent, actions),
+        not_before: parent.not_before,
+        expires_at,
+        conditions: parent.conditions.clone(),
+        parent: Some(Parent {
+            nonce: parent.nonce,
+            version: parent.version,
+        }),
+        ..child.clone()
+    })
+}
+
+/// Plan the changes that cascade from the current version of parents to
+/// the Permissions delegated from them.
+///
+/// A child whose parent is itself changed by the plan is derived from the
+/// parent's next version rather than its current one, so a change reaches
+/// every Permission delegated from it, directly or not, in a single pass.
+/// Each change is given as the child's current version along with its next
+/// version, or `None` if it should be revoked. Parents are given before
+/// their children.
+pub fn cascade(
+    delegated: &[DelegatedPermission],
+) -> Vec<(Permission, Option<Permission>)> {
+    let nonces: HashSet<Nonce> = delegated.iter()
+        .map(|item| item.perm.nonce)
+        .collect();
+    let mut children: HashMap<Nonce, Vec<&Permission>> = HashMap::new();
+    let mut pending = Vec::new();
+    for DelegatedPermission { perm, parent } in delegated {
+        let nonce = match perm.parent {
+            Some(parent) => parent.nonce,
+            None => continue,
+        };
+        // Start from the children whose parent was not delegated, and reach
+        // the others by walking down from their parent.
+        match nonces.contains(&nonce) {
+            true => children.entry(nonce).or_default().push(perm),
+            false => pending.push((perm, parent.clone())),
+        }
+    }
+    let mut changes = Vec::new();
+    while let Some((perm, parent)) = pending.pop() {
+        let next = match parent.and_then(|parent| rederive(&parent, perm)) {
+            Some(update) if update == *perm => Some(perm.clone()),
+            Some(update) => {
+                let update = Permission {
+                    version: perm.version + 1,
+                    ..update
+                };
+                changes.push((perm.clone(), Some(update.clone())));
+                Some(update)
+            },
+            None => {
+                changes.push((perm.clone(), None));
+                None
+            },
+        };
+        for child in children.get(&perm.nonce).into_iter().flatten() {
+            pending.push((child, next.clone()));
+        }
+    }
+    changes
+}
+
+/// Internal helper to tell whether an allow entry is covered by one of a
+/// Permission's allow entries.
+///
+/// Wildcards in the entry are compared as literal segments, so a pattern is
+/// only covered by a pattern at least as broad.
+fn covered(perm: &Permission, action: &str) -> bool {
+    perm.all_actions()
+        .filter(|entry| !entry.starts_with(DENY))
+        .any(|entry| matcher::matches(entry, action))
+}
+
+/// Internal helper to append a Permission's deny entries that are missing
+/// from a list of actions.
+fn with_denials(perm: &Permission, mut actions: Vec<Action>) -> Vec<Action> {
+    for entry in perm.all_actions() {
+        if entry.starts_with(DENY) && !actions.contains(entry) {
+            actions.push(entry.clone());
+        }
+    }
+    actions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build a root Permission allowing actions.
+    fn root(actions: &[&str]) -> Permission {
+        serde_json::from_value(serde_json::json!({
+            "nonce": 1,
+            "actions": actions,
+            "version": 0,
+        })).unwrap()
+    }
+
+    /// Delegate a child Permission allowing actions from a parent.
+    fn delegate(
+        parent: &Permission,
+        nonce: u32,
+        actions: &[&str],
+    ) -> Permission {
+        let req = DelegateRequest {
+            perm: parent.clone(),
+            actions: actions.iter().map(|a| a.to_string()).collect(),
+            expires_at: None,
+            subject: None,
+            proof: None,
+        };
+        Permission {
+            nonce: nonce.into(),
+            ..derive(parent, &req).unwrap()
+        }
+    }
+
+    /// Build a chain of three delegations from a root Permission, and return
+    /// them as the Worker lists them given the root's current version.
+    fn chain(
+        root: &Permission,
+        current: Option<Permission>,
+    ) -> (Vec<Permission>, Vec<DelegatedPermission>) {
+        let a = delegate(root, 2, &["read", "write", "list"]);
+        let b = delegate(&a, 3, &["read", "write"]);
+        let c = delegate(&b, 4, &["read", "write"]);
+        // List the deepest child first so that the walk cannot rely on the
+        // order of the list.
+        let delegated = vec![
+            DelegatedPermission { perm: c.clone(), parent: Some(b.clone()) },
+            DelegatedPermission { perm: b.clone(), parent: Some(a.clone()) },
+            DelegatedPermission { perm: a.clone(), parent: current },
+        ];
+        (vec![a, b, c], delegated)
+    }
+
+    #[test]
+    fn cascade_leaves_unchanged_chain() {
+        let root = root(&["read", "write", "list"]);
+        let (_, delegated) = chain(&root, Some(root.clone()));
+        assert!(cascade(&delegated).is_empty());
+    }
+
+    #[test]
+    fn cascade_revokes_chain() {
+        let root = root(&["read", "write", "list"]);
+        let (chain, delegated) = chain(&root, None);
+        let changes = cascade(&delegated);
+        assert_eq!(changes.len(), 3);
+        for ((perm, update), child) in changes.iter().zip(chain.iter()) {
+            assert!(perm == child);
+            assert!(update.is_none());
+        }
+    }
+
+    #[test]
+    fn cascade_narrows_chain() {
+        let root = root(&["read", "write", "list"]);
+        let update = Permission {
+            actions: vec!["read".to_owned(), "list".to_owned()],
+            version: 1,
+            ..root.clone()
+        };
+        let (chain, delegated) = chain(&root, Some(update.clone()));
+        let changes = cascade(&delegated);
+        assert_eq!(changes.len(), 3);
+        // Each child is derived from the next version of its parent.
+        let mut parent = update;
+        for ((perm, next), child) in changes.into_iter().zip(chain.iter()) {
+            assert!(perm == *child);
+            let next = next.unwrap();
+            assert_eq!(next.version, child.version + 1);
+            assert!(!next.actions.contains(&"write".to_owned()));
+            assert!(next.actions.contains(&"read".to_owned()));
+            assert!(next.parent == Some(Parent {
+                nonce: parent.nonce,
+                version: parent.version,
+            }));
+            parent = next;
+        }
+    }
+
+    #[test]
+    fn cascade_revokes_children_of_emptied_permission() {
+        let root = root(&["read", "write", "list"]);
+        let update = Permission {
+            actions: vec!["list".to_owned()],
+            version: 1,
+            ..root.clone()
+        };
+        let (chain, delegated) = chain(&root, Some(update));
+        let changes = cascade(&delegated);
+        assert_eq!(changes.len(), 3);
+        // The first child keeps `list`, but the others are left without any
+        // allow entry.
+        assert!(changes[0].0 == chain[0]);
+        assert!(changes[0].1.as_ref().unwrap().actions == vec!["list"]);
+        assert!(changes[1].0 == chain[1] && changes[1].1.is_none());
+        assert!(changes[2].0 == chain[2] && changes[2].1.is_none());
+    }
+}
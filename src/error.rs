@@ -0,0 +1,237 @@
+use hyper::StatusCode;
+use serde::{Serialize, Deserialize};
+
+/// Errors returned by the library and by the services over HTTP.
+///
+/// Services respond with the status code given by `status` and a JSON body
+/// of the form `{"error": "not_granted", "message": "..."}`, with a
+/// `detail` member for variants that carry data. `util::Client` decodes
+/// that body so an error raised by the Authority or the Worker surfaces
+/// unchanged from the Synchronizer.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "error", content = "detail", rename_all = "snake_case")]
+pub enum Error {
+
+    /// A request body could not be decoded.
+    BadRequest,
+
+    /// A request to the Authority or the Worker was not signed by a peer
+    /// holding the service's secret.
+    Unauthenticated,
+
+    /// A Witness did not prove that its Permission is a member of the
+    /// accumulation.
+    WitnessVerification,
+
+    /// No Witness is held for the Permission's Nonce.
+    UnknownPermission,
+
+    /// The Permission is valid but does not allow the requested action.
+    NotGranted,
+
+    /// The Permission's validity period has not started yet.
+    NotYetValid,
+
+    /// The Permission's validity period has ended.
+    Expired,
+
+    /// The Permission's validity period ends before it starts.
+    InvalidValidity,
+
+    /// A role is not defined, or a Permission carries an outdated definition
+    /// of it.
+    InvalidRole(String),
+
+    /// A Permission's subject is not a hex encoded Ed25519 public key.
+    InvalidSubject,
+
+    /// An action with a Permission bound to a subject was not accompanied by
+    /// a valid, fresh proof signed with the subject's key.
+    SubjectProof,
+
+    /// A condition of a Permission cannot be evaluated.
+    InvalidCondition(String),
+
+    /// A request does not meet a condition of the Permission on the action,
+    /// identified by the attribute it tests.
+    ConditionFailed(String),
+
+    /// A child Permission would allow more than its parent.
+    Delegation(String),
+
+    /// A request was routed to a tenant that is not configured.
+    UnknownTenant(String),
+
+    /// The Nonces of the old and new versions of a Permission differ.
+    NonceMismatch,
+
+    /// The new version of a Permission is not greater than the old one.
+    VersionRegression,
+
+    /// The Worker has not been given the Authority's public key yet.
+    MissingPublicKey,
+
+    /// The Worker was given a public key different from the one it has.
+    KeyAlreadySet,
+
+    /// The Worker cannot be keyed because no signing key of the Authority
+    /// is pinned in its configuration.
+    MissingSigningKey,
+
+    /// An accumulation value was not covered by a valid checkpoint signed by
+    /// the Authority, or the checkpoint was older than one already seen.
+    InvalidCheckpoint,
+
+    /// The Authority has not been unsealed with enough shares of its private
+    /// key yet.
+    Sealed,
+
+    /// A share of the Authority's private key was inconsistent with the
+    /// others, or the shares did not reconstruct the private key.
+    InvalidShare,
+
+    /// A key rotation could not be performed.
+    Rotation(String),
+
+    /// A KeyStore could not perform an operation with the Authority's
+    /// private key.
+    KeyStore(String),
+
+    /// Another service could not be reached.
+    Transport(String),
+
+    /// Another service responded with an unexpected status code.
+    Upstream(u16),
+
+    /// A response from another service could not be decoded.
+    Decode,
+
+    /// State could not be read from or written to disk.
+    Storage(String),
+
+    /// The configuration is invalid.
+    Config(String),
+}
+
+impl Error {
+
+    /// The HTTP status code a service responds with for the error.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Error::BadRequest => StatusCode::BAD_REQUEST,
+            Error::Unauthenticated => StatusCode::UNAUTHORIZED,
+            Error::WitnessVerification => StatusCode::UNAUTHORIZED,
+            Error::UnknownPermission => StatusCode::UNAUTHORIZED,
+            Error::NotGranted => StatusCode::FORBIDDEN,
+            Error::NotYetValid => StatusCode::FORBIDDEN,
+            Error::Expired => StatusCode::FORBIDDEN,
+            Error::InvalidValidity => StatusCode::BAD_REQUEST,
+            Error::InvalidRole(_) => StatusCode::BAD_REQUEST,
+            Error::InvalidSubject => StatusCode::BAD_REQUEST,
+            Error::SubjectProof => StatusCode::UNAUTHORIZED,
+            Error::InvalidCondition(_) => StatusCode::BAD_REQUEST,
+            Error::ConditionFailed(_) => StatusCode::FORBIDDEN,
+            Error::Delegation(_) => StatusCode::FORBIDDEN,
+            Error::UnknownTenant(_) => StatusCode::NOT_FOUND,
+            Error::NonceMismatch => StatusCode::UNPROCESSABLE_ENTITY,
+            Error::VersionRegression => StatusCode::CONFLICT,
+            Error::MissingPublicKey => StatusCode::SERVICE_UNAVAILABLE,
+            Error::KeyAlreadySet => StatusCode::CONFLICT,
+            Error::MissingSigningKey => StatusCode::SERVICE_UNAVAILABLE,
+            Error::InvalidCheckpoint => StatusCode::BAD_REQUEST,
+            Error::Sealed => StatusCode::SERVICE_UNAVAILABLE,
+            Error::InvalidShare => StatusCode::BAD_REQUEST,
+            Error::Rotation(_) => StatusCode::CONFLICT,
+            Error::KeyStore(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Error::Transport(_) => StatusCode::BAD_GATEWAY,
+            Error::Upstream(_) => StatusCode::BAD_GATEWAY,
+            Error::Decode => StatusCode::BAD_GATEWAY,
+            Error::Storage(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Error::Config(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::BadRequest => write!(f, "could not decode request"),
+            Error::Unauthenticated => {
+                write!(f, "request is not from an authenticated peer")
+            },
+            Error::WitnessVerification => {
+                write!(f, "could not verify permission")
+            },
+            Error::UnknownPermission => write!(f, "unknown permission"),
+            Error::NotGranted => {
+                write!(f, "permission not granted to perform action")
+            },
+            Error::NotYetValid => write!(f, "permission is not valid yet"),
+            Error::Expired => write!(f, "permission has expired"),
+            Error::InvalidValidity => {
+                write!(f, "permission expires before it becomes valid")
+            },
+            Error::InvalidRole(name) => {
+                write!(f, "role {} is not defined or is outdated", name)
+            },
+            Error::InvalidSubject => write!(f, "subject is not a public key"),
+            Error::SubjectProof => {
+                write!(f, "could not verify proof of subject")
+            },
+            Error::InvalidCondition(err) => {
+                write!(f, "invalid condition: {}", err)
+            },
+            Error::ConditionFailed(attribute) => {
+                write!(f, "condition on {} not met", attribute)
+            },
+            Error::Delegation(err) => write!(f, "cannot delegate: {}", err),
+            Error::UnknownTenant(tenant) => {
+                write!(f, "unknown tenant {}", tenant)
+            },
+            Error::NonceMismatch => write!(f, "nonce mismatch"),
+            Error::VersionRegression => {
+                write!(f, "new version must be greater than old version")
+            },
+            Error::MissingPublicKey => write!(f, "need public key"),
+            Error::KeyAlreadySet => write!(f, "already have public key"),
+            Error::MissingSigningKey => write!(f, "need pinned signing key"),
+            Error::InvalidCheckpoint => {
+                write!(f, "accumulation value is not covered by a checkpoint")
+            },
+            Error::Sealed => write!(f, "authority is sealed"),
+            Error::InvalidShare => write!(f, "invalid key share"),
+            Error::Rotation(err) => write!(f, "key rotation: {}", err),
+            Error::KeyStore(err) => write!(f, "key store error: {}", err),
+            Error::Transport(err) => write!(f, "request error: {}", err),
+            Error::Upstream(status) => {
+                write!(f, "upstream responded with status {}", status)
+            },
+            Error::Decode => write!(f, "could not decode response"),
+            Error::Storage(err) => write!(f, "storage error: {}", err),
+            Error::Config(err) => write!(f, "invalid configuration: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// The body of an error response.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ErrorBody {
+
+    /// The error.
+    #[serde(flatten)]
+    pub error: Error,
+
+    /// A human readable description of the error.
+    pub message: String,
+}
+
+impl From<Error> for ErrorBody {
+    fn from(error: Error) -> Self {
+        ErrorBody {
+            message: error.to_string(),
+            error,
+        }
+    }
+}
@@ -0,0 +1,499 @@
+use aes_gcm::{
+    Aes256Gcm,
+    Key,
+    Nonce as AesNonce,
+    aead::{Aead, KeyInit},
+};
+use clacc::{Accumulator, sha3::Shake128 as Map};
+use gmp::mpz::Mpz;
+use rand::RngCore;
+use serde::{Serialize, Deserialize};
+use std::{fs, path::{Path, PathBuf}};
+use crate::{
+    config::Config,
+    error::Error,
+    permission::Permission,
+    store,
+};
+
+/// Performs the operations that need the Accumulator's private key.
+///
+/// Adding Permissions and verifying Witnesses only need the public key, so
+/// the Authority keeps its Accumulators with the public key alone and asks
+/// its KeyStore whenever a Permission is deleted from one of them. This
+/// keeps the private key behind an interface that can be backed by a
+/// hardware security module.
+pub trait KeyStore: Send + Sync {
+
+    /// Return the Accumulator's public key.
+    fn public_key(&self) -> Mpz;
+
+    /// Delete a Permission from an accumulation value and return the new
+    /// value.
+    ///
+    /// No Witness is needed, since the private key can take the root of the
+    /// value for any Permission. The caller must therefore ensure that the
+    /// Permission is a member of the accumulation.
+    fn del(&self, value: Mpz, perm: Permission) -> Result<Mpz, Error>;
+
+    /// Generate a new private key, kept the same way as this one, to rotate
+    /// to.
+    ///
+    /// The new key is not persisted until `commit` is called on the
+    /// returned store.
+    fn generate(&self) -> Result<Box<dyn KeyStore>, Error>;
+
+    /// Durably replace the private key that was persisted where this store
+    /// keeps its key with this store's key.
+    fn commit(&self) -> Result<(), Error>;
+}
+
+/// The factors of the Accumulator's modulus.
+#[derive(Serialize, Deserialize)]
+struct PrivateKey {
+    p: Mpz,
+    q: Mpz,
+}
+
+impl PrivateKey {
+
+    /// Generate a random private key.
+    fn generate() -> Self {
+        let mut rng = rand::thread_rng();
+        let (_, p, q) = Accumulator::<Mpz, Map>::with_random_key(
+            |bytes| rng.fill_bytes(bytes),
+            None,
+        );
+        PrivateKey { p, q }
+    }
+
+    /// Delete a Permission from an accumulation value.
+    fn del(&self, value: Mpz, perm: Permission) -> Result<Mpz, Error> {
+        let mut acc = Accumulator::<Mpz, Map>::with_private_key(
+            self.p.clone(),
+            self.q.clone(),
+        );
+        acc.set_value(value);
+        // Compute the Permission's Witness for this value, which only the
+        // private key can do.
+        let res = acc.prove(perm.clone())
+            .and_then(|witness| acc.del(perm, witness));
+        if res.is_err() {
+            return Err(Error::WitnessVerification);
+        }
+        Ok(acc.get_value())
+    }
+}
+
+/// A private key encrypted under a key that is held elsewhere.
+#[derive(Serialize, Deserialize)]
+struct Wrapped {
+
+    /// The Accumulator's public key, which the decrypted private key must
+    /// match.
+    key: Mpz,
+
+    /// The hex encoded nonce or initialization vector.
+    nonce: String,
+
+    /// The hex encoded ciphertext of the JSON encoded private key.
+    ciphertext: String,
+}
+
+impl Wrapped {
+
+    /// Decode the nonce and ciphertext.
+    fn decode(&self) -> Result<(Vec<u8>, Vec<u8>), Error> {
+        match (hex::decode(&self.nonce), hex::decode(&self.ciphertext)) {
+            (Ok(nonce), Ok(ciphertext)) => Ok((nonce, ciphertext)),
+            _ => Err(Error::KeyStore(
+                "wrapped key is not hex encoded".to_owned(),
+            )),
+        }
+    }
+
+    /// Decode a decrypted private key and ensure it matches the public key.
+    fn decode_key(&self, plaintext: &[u8]) -> Result<PrivateKey, Error> {
+        let key: PrivateKey = match serde_json::from_slice(plaintext) {
+            Ok(key) => key,
+            Err(_) => {
+                return Err(Error::KeyStore(
+                    "could not decode private key".to_owned(),
+                ));
+            },
+        };
+        if &key.p * &key.q != self.key {
+            return Err(Error::KeyStore(
+                "private key does not match public key".to_owned(),
+            ));
+        }
+        Ok(key)
+    }
+}
+
+/// Where a SoftwareKeyStore persists its private key.
+enum Location {
+
+    /// The key was generated in memory and is not persisted.
+    Ephemeral,
+
+    /// The key was given, e.g. after combining the shares of a key
+    /// ceremony, and is persisted elsewhere.
+    Provided,
+
+    /// The key is persisted in plain text in a file.
+    Plain(PathBuf),
+
+    /// The key is persisted in a file, encrypted with a 256 bit key.
+    Encrypted(PathBuf, Vec<u8>),
+}
+
+/// A KeyStore that performs private key operations in software.
+///
+/// The private key is held in process memory. When opened from a file with
+/// `open_encrypted`, it is encrypted at rest with AES-256-GCM under a key
+/// that is supplied separately, e.g. from the configuration.
+pub struct SoftwareKeyStore {
+    key: PrivateKey,
+    location: Location,
+}
+
+impl SoftwareKeyStore {
+
+    /// Create a KeyStore from the factors of the Accumulator's modulus.
+    ///
+    /// The key is not persisted, and cannot be rotated since it would be
+    /// lost when the process exits.
+    pub fn new(p: Mpz, q: Mpz) -> Self {
+        SoftwareKeyStore {
+            key: PrivateKey { p, q },
+            location: Location::Provided,
+        }
+    }
+
+    /// Create a KeyStore with a random private key that is not persisted.
+    pub fn generate() -> Self {
+        SoftwareKeyStore {
+            key: PrivateKey::generate(),
+            location: Location::Ephemeral,
+        }
+    }
+
+    /// Open a private key stored in plain text, generating it if the file
+    /// does not exist.
+    ///
+    /// The file is only accessible to the owner of the process.
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        let key = match store::load(path)? {
+            Some(key) => key,
+            None => {
+                let key = PrivateKey::generate();
+                store::save_private(path, &key)?;
+                key
+            },
+        };
+        Ok(SoftwareKeyStore {
+            key,
+            location: Location::Plain(path.to_owned()),
+        })
+    }
+
+    /// Open a private key encrypted with a hex encoded 256 bit key,
+    /// generating it if the file does not exist.
+    pub fn open_encrypted(path: &Path, secret: &str) -> Result<Self, Error> {
+        let secret = match hex::decode(secret) {
+            Ok(secret) if secret.len() == 32 => secret,
+            _ => {
+                return Err(Error::Config(
+                    "authority_key_secret must be 32 hex encoded bytes"
+                        .to_owned(),
+                ));
+            },
+        };
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&secret));
+        let location = Location::Encrypted(path.to_owned(), secret);
+        let wrapped = match store::load::<Wrapped>(path)? {
+            Some(wrapped) => wrapped,
+            None => {
+                // Generate a private key and encrypt it.
+                let store = SoftwareKeyStore {
+                    key: PrivateKey::generate(),
+                    location,
+                };
+                store::save_private(path, &Self::encrypt(&cipher, &store.key))?;
+                return Ok(store);
+            },
+        };
+        let (nonce, ciphertext) = wrapped.decode()?;
+        if nonce.len() != 12 {
+            return Err(Error::KeyStore(
+                "wrapped key has the wrong nonce length".to_owned(),
+            ));
+        }
+        let nonce = AesNonce::from_slice(&nonce);
+        let plaintext = match cipher.decrypt(nonce, &ciphertext[..]) {
+            Ok(plaintext) => plaintext,
+            Err(_) => {
+                return Err(Error::KeyStore(
+                    "could not decrypt private key".to_owned(),
+                ));
+            },
+        };
+        Ok(SoftwareKeyStore {
+            key: wrapped.decode_key(&plaintext)?,
+            location,
+        })
+    }
+
+    /// Internal helper to encrypt a private key.
+    fn encrypt(cipher: &Aes256Gcm, key: &PrivateKey) -> Wrapped {
+        let mut nonce = [0u8; 12];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let plaintext = serde_json::to_vec(key).unwrap();
+        let ciphertext = cipher
+            .encrypt(AesNonce::from_slice(&nonce), &plaintext[..])
+            .unwrap();
+        Wrapped {
+            key: &key.p * &key.q,
+            nonce: hex::encode(nonce),
+            ciphertext: hex::encode(ciphertext),
+        }
+    }
+}
+
+impl KeyStore for SoftwareKeyStore {
+
+    fn public_key(&self) -> Mpz {
+        &self.key.p * &self.key.q
+    }
+
+    fn del(&self, value: Mpz, perm: Permission) -> Result<Mpz, Error> {
+        self.key.del(value, perm)
+    }
+
+    fn generate(&self) -> Result<Box<dyn KeyStore>, Error> {
+        let location = match &self.location {
+            Location::Ephemeral => Location::Ephemeral,
+            Location::Provided => {
+                return Err(Error::Rotation(
+                    "a key combined from shares must be replaced by a new \
+                     key ceremony".to_owned(),
+                ));
+            },
+            Location::Plain(path) => Location::Plain(path.clone()),
+            Location::Encrypted(path, secret) => {
+                Location::Encrypted(path.clone(), secret.clone())
+            },
+        };
+        Ok(Box::new(SoftwareKeyStore {
+            key: PrivateKey::generate(),
+            location,
+        }))
+    }
+
+    fn commit(&self) -> Result<(), Error> {
+        match &self.location {
+            Location::Ephemeral | Location::Provided => Ok(()),
+            Location::Plain(path) => store::replace_private(path, &self.key),
+            Location::Encrypted(path, secret) => {
+                let cipher = Aes256Gcm::new(
+                    Key::<Aes256Gcm>::from_slice(secret),
+                );
+                store::replace_private(path, &Self::encrypt(&cipher, &self.key))
+            },
+        }
+    }
+}
+
+/// A KeyStore backed by a PKCS#11 token such as SoftHSM.
+///
+/// General purpose tokens cannot raise a value to an arbitrary exponent
+/// modulo a composite, so the private key is wrapped with an AES key that
+/// never leaves the token. The wrapped key is kept in a file and only
+/// unwrapped by the token for the duration of each deletion.
+#[cfg(feature = "pkcs11")]
+pub struct Pkcs11KeyStore {
+    session: std::sync::Arc<std::sync::Mutex<cryptoki::session::Session>>,
+    handle: cryptoki::object::ObjectHandle,
+    path: PathBuf,
+    wrapped: Wrapped,
+}
+
+#[cfg(feature = "pkcs11")]
+impl Pkcs11KeyStore {
+
+    /// Open a private key wrapped by the AES key with a given label in the
+    /// first token of a PKCS#11 module, generating and wrapping it if the
+    /// file does not exist.
+    pub fn open(
+        path: &Path,
+        module: &str,
+        pin: &str,
+        label: &str,
+    ) -> Result<Self, Error> {
+        use cryptoki::{
+            context::{CInitializeArgs, Pkcs11},
+            object::{Attribute, ObjectClass},
+            session::UserType,
+            types::AuthPin,
+        };
+        let err = |err: cryptoki::error::Error| {
+            Error::KeyStore(format!("pkcs11: {}", err))
+        };
+        // Log in to the first token and find the wrapping key.
+        let ctx = Pkcs11::new(module).map_err(err)?;
+        ctx.initialize(CInitializeArgs::OsThreads).map_err(err)?;
+        let slot = match ctx.get_slots_with_token().map_err(err)?.first() {
+            Some(slot) => *slot,
+            None => {
+                return Err(Error::KeyStore("pkcs11: no token".to_owned()));
+            },
+        };
+        let session = ctx.open_rw_session(slot).map_err(err)?;
+        session.login(UserType::User, Some(&AuthPin::new(pin.to_owned())))
+            .map_err(err)?;
+        let handle = match session.find_objects(&[
+            Attribute::Class(ObjectClass::SECRET_KEY),
+            Attribute::Label(label.as_bytes().to_vec()),
+        ]).map_err(err)?.first() {
+            Some(handle) => *handle,
+            None => {
+                return Err(Error::KeyStore(format!(
+                    "pkcs11: no key labeled {}",
+                    label,
+                )));
+            },
+        };
+        let session = std::sync::Arc::new(std::sync::Mutex::new(session));
+        // Restore the wrapped key, or generate one and wrap it.
+        let wrapped = match store::load::<Wrapped>(path)? {
+            Some(wrapped) => wrapped,
+            None => {
+                let wrapped = Self::wrap(&session, handle)?;
+                store::save_private(path, &wrapped)?;
+                wrapped
+            },
+        };
+        let store = Pkcs11KeyStore {
+            session,
+            handle,
+            path: path.to_owned(),
+            wrapped,
+        };
+        // Ensure the token can unwrap the key before it is relied upon.
+        store.unwrap_key()?;
+        Ok(store)
+    }
+
+    /// Internal helper to generate a private key and have the token wrap
+    /// it.
+    fn wrap(
+        session: &std::sync::Mutex<cryptoki::session::Session>,
+        handle: cryptoki::object::ObjectHandle,
+    ) -> Result<Wrapped, Error> {
+        use cryptoki::mechanism::Mechanism;
+        let key = PrivateKey::generate();
+        let mut iv = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut iv);
+        let plaintext = serde_json::to_vec(&key).unwrap();
+        let ciphertext = match session.lock().unwrap().encrypt(
+            &Mechanism::AesCbcPad(iv),
+            handle,
+            &plaintext,
+        ) {
+            Ok(ciphertext) => ciphertext,
+            Err(err) => {
+                return Err(Error::KeyStore(format!("pkcs11: {}", err)));
+            },
+        };
+        Ok(Wrapped {
+            key: &key.p * &key.q,
+            nonce: hex::encode(iv),
+            ciphertext: hex::encode(ciphertext),
+        })
+    }
+
+    /// Internal helper to have the token unwrap the private key.
+    fn unwrap_key(&self) -> Result<PrivateKey, Error> {
+        use cryptoki::mechanism::Mechanism;
+        let (iv, ciphertext) = self.wrapped.decode()?;
+        let iv: [u8; 16] = match iv.try_into() {
+            Ok(iv) => iv,
+            Err(_) => {
+                return Err(Error::KeyStore(
+                    "wrapped key has the wrong nonce length".to_owned(),
+                ));
+            },
+        };
+        let session = self.session.lock().unwrap();
+        let plaintext = match session.decrypt(
+            &Mechanism::AesCbcPad(iv),
+            self.handle,
+            &ciphertext,
+        ) {
+            Ok(plaintext) => plaintext,
+            Err(err) => {
+                return Err(Error::KeyStore(format!("pkcs11: {}", err)));
+            },
+        };
+        self.wrapped.decode_key(&plaintext)
+    }
+}
+
+#[cfg(feature = "pkcs11")]
+impl KeyStore for Pkcs11KeyStore {
+
+    fn public_key(&self) -> Mpz {
+        self.wrapped.key.clone()
+    }
+
+    fn del(&self, value: Mpz, perm: Permission) -> Result<Mpz, Error> {
+        self.unwrap_key()?.del(value, perm)
+    }
+
+    fn generate(&self) -> Result<Box<dyn KeyStore>, Error> {
+        Ok(Box::new(Pkcs11KeyStore {
+            session: std::sync::Arc::clone(&self.session),
+            handle: self.handle,
+            path: self.path.clone(),
+            wrapped: Self::wrap(&self.session, self.handle)?,
+        }))
+    }
+
+    fn commit(&self) -> Result<(), Error> {
+        store::replace_private(&self.path, &self.wrapped)
+    }
+}
+
+/// Open the KeyStore an Authority is configured with.
+///
+/// The private key is kept in the Authority's data directory, encrypted with
+/// `authority_key_secret` in `key.enc` by default, in plain text in
+/// `key.json` or wrapped by a PKCS#11 token in `key.p11`.
+pub fn open(config: &Config) -> Result<Box<dyn KeyStore>, Error> {
+    let dir = PathBuf::from(&config.authority_data_dir);
+    if fs::create_dir_all(&dir).is_err() {
+        return Err(Error::Storage(
+            "could not create data directory".to_owned(),
+        ));
+    }
+    match config.authority_key_store.as_str() {
+        "file" => Ok(Box::new(SoftwareKeyStore::open(
+            &dir.join("key.json"),
+        )?)),
+        "encrypted" => Ok(Box::new(SoftwareKeyStore::open_encrypted(
+            &dir.join("key.enc"),
+            &config.authority_key_secret,
+        )?)),
+        #[cfg(feature = "pkcs11")]
+        "pkcs11" => Ok(Box::new(Pkcs11KeyStore::open(
+            &dir.join("key.p11"),
+            &config.authority_pkcs11_module,
+            &config.authority_pkcs11_pin,
+            &config.authority_pkcs11_label,
+        )?)),
+        store => {
+            Err(Error::Config(format!("unsupported key store {}", store)))
+        },
+    }
+}
@@ -1,8 +1,24 @@
 pub mod authority;
 pub mod worker;
 pub mod synchronizer;
+pub mod ceremony;
+pub mod checkpoint;
+pub mod client;
+pub mod condition;
+pub mod config;
 pub mod constant;
+pub mod delegation;
+pub mod error;
+pub mod keystore;
+pub mod matcher;
+pub mod peer;
 pub mod permission;
 pub mod request;
+pub mod role;
+pub mod store;
+pub mod subject;
+pub mod tenant;
+pub mod tls;
 pub mod u53;
 pub mod util;
+pub mod verifier;
@@ -0,0 +1,168 @@
+use crate::{error::Error, permission::Action};
+
+/// The wildcard that matches any segment of an action.
+pub const WILDCARD: &str = "*";
+
+/// The prefix that turns an entry of a Permission's actions into a deny
+/// entry.
+pub const DENY: char = '!';
+
+/// Decide whether a Permission's actions allow an action.
+///
+/// Actions are structured names whose segments are separated by `:` or
+/// `/`, such as `repo:read` or `billing/invoices/42`. Each entry of a
+/// Permission's actions is a pattern that is either:
+///
+/// * an action, which matches only itself,
+/// * a pattern with `*` in place of a segment, such as `repo:*:read`, which
+///   matches any single segment there,
+/// * a pattern ending in `*`, such as `repo:*` or `billing/invoices/*`,
+///   which matches any action that extends the prefix by one or more
+///   segments, or `*` on its own, which matches every action.
+///
+/// Entries prefixed with `!` deny the actions they match. Deny entries take
+/// precedence: an action is allowed if it matches at least one allow entry
+/// and no deny entry, regardless of the order of the entries. The action
+/// itself must be concrete, so it may not be empty or contain wildcards.
+pub fn authorize<'a, I>(actions: I, action: &str) -> Result<(), Error>
+where I: IntoIterator<Item = &'a Action> {
+    if action.is_empty()
+        || action.contains(WILDCARD)
+        || action.starts_with(DENY) {
+        return Err(Error::NotGranted);
+    }
+    let mut allowed = false;
+    for entry in actions {
+        match entry.strip_prefix(DENY) {
+            Some(pattern) => {
+                if matches(pattern, action) {
+                    return Err(Error::NotGranted);
+                }
+            },
+            None => {
+                allowed = allowed || matches(entry, action);
+            },
+        }
+    }
+    match allowed {
+        true => Ok(()),
+        false => Err(Error::NotGranted),
+    }
+}
+
+/// Whether an action matches a pattern.
+pub fn matches(pattern: &str, action: &str) -> bool {
+    let pattern = segments(pattern);
+    let action = segments(action);
+    for (i, segment) in pattern.iter().enumerate() {
+        let other = match action.get(i) {
+            Some(other) => other,
+            None => {
+                return false;
+            },
+        };
+        // A trailing wildcard matches this and every remaining segment.
+        if *segment == WILDCARD {
+            return true;
+        }
+        // An inner wildcard matches any segment followed by the same
+        // separator.
+        let matched = match segment.strip_prefix(WILDCARD) {
+            Some(sep) if is_separator(sep) => {
+                other.len() > sep.len() && other.ends_with(sep)
+            },
+            _ => segment == other,
+        };
+        if !matched {
+            return false;
+        }
+    }
+    pattern.len() == action.len()
+}
+
+/// Internal helper to split an action into segments, each including the
+/// separator that follows it.
+fn segments(action: &str) -> Vec<&str> {
+    action.split_inclusive([':', '/']).collect()
+}
+
+/// Internal helper to tell whether a string is a single separator.
+fn is_separator(sep: &str) -> bool {
+    sep == ":" || sep == "/"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Whether a list of entries allows an action.
+    fn allows(entries: &[&str], action: &str) -> bool {
+        let entries: Vec<Action> = entries.iter()
+            .map(|entry| entry.to_string())
+            .collect();
+        authorize(&entries, action).is_ok()
+    }
+
+    #[test]
+    fn exact_action() {
+        assert!(allows(&["repo:read"], "repo:read"));
+        assert!(!allows(&["repo:read"], "repo:write"));
+        assert!(!allows(&["repo:read"], "repo:read:all"));
+        assert!(!allows(&["repo:read:all"], "repo:read"));
+        assert!(!allows(&[], "repo:read"));
+    }
+
+    #[test]
+    fn trailing_wildcard() {
+        assert!(allows(&["repo:*"], "repo:read"));
+        assert!(allows(&["repo:*"], "repo:branches/main"));
+        assert!(allows(&["billing/invoices/*"], "billing/invoices/42"));
+        // The prefix must be extended by at least one segment.
+        assert!(!allows(&["repo:*"], "repo"));
+        assert!(!allows(&["repo:*"], "repo:"));
+        assert!(!allows(&["repo:*"], "repository:read"));
+        // `*` on its own matches every action.
+        assert!(allows(&["*"], "sign-in"));
+        assert!(allows(&["*"], "billing/invoices/42"));
+    }
+
+    #[test]
+    fn inner_wildcard() {
+        assert!(allows(&["repo:*:read"], "repo:compauth:read"));
+        // It matches exactly one segment.
+        assert!(!allows(&["repo:*:read"], "repo:read"));
+        assert!(!allows(&["repo:*:read"], "repo:a:b:read"));
+        assert!(!allows(&["repo:*:read"], "repo::read"));
+        assert!(!allows(&["repo:*:read"], "repo:compauth:write"));
+    }
+
+    #[test]
+    fn separator_mismatch() {
+        assert!(!allows(&["repo:read"], "repo/read"));
+        assert!(!allows(&["repo:*"], "repo/read"));
+        assert!(!allows(&["repo/*/read"], "repo/compauth:read"));
+        assert!(!allows(&["repo:*:read"], "repo:compauth/read"));
+    }
+
+    #[test]
+    fn deny_takes_precedence() {
+        let entries = ["repo:*", "!repo:delete"];
+        assert!(allows(&entries, "repo:read"));
+        assert!(!allows(&entries, "repo:delete"));
+        // Regardless of the order of the entries.
+        assert!(!allows(&["!repo:delete", "repo:*"], "repo:delete"));
+        assert!(!allows(&["repo:delete", "!repo:*"], "repo:delete"));
+        // A deny entry on its own allows nothing.
+        assert!(!allows(&["!repo:delete"], "repo:read"));
+        assert!(!allows(&["*", "!*"], "sign-in"));
+    }
+
+    #[test]
+    fn action_must_be_concrete() {
+        assert!(!allows(&["*"], ""));
+        assert!(!allows(&[""], ""));
+        assert!(!allows(&["*"], "repo:*"));
+        assert!(!allows(&["repo:*"], "repo:*"));
+        assert!(!allows(&["*"], "!repo:read"));
+    }
+}
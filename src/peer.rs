@@ -0,0 +1,311 @@
+use hmac::{Hmac, Mac};
+use hyper::{Body, Request, body::to_bytes, http::request::Builder};
+use sha2::Sha256;
+use std::{collections::HashMap, sync::Mutex};
+use crate::{
+    constant::{MAX_CLOCK_SKEW_MILLIS, MIN_SECRET_BYTES},
+    error::Error,
+    util::now_millis,
+};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// The header carrying the time a request was signed, in milliseconds since
+/// the Unix epoch.
+pub const TIMESTAMP_HEADER: &str = "x-compauth-timestamp";
+
+/// The header carrying a random value that makes every request unique.
+pub const NONCE_HEADER: &str = "x-compauth-nonce";
+
+/// The header carrying the hex encoded HMAC-SHA256 of a request.
+pub const SIGNATURE_HEADER: &str = "x-compauth-signature";
+
+/// A secret shared by a service and the peers allowed to call it.
+///
+/// Peers sign the method, path, body and a timestamp and nonce of every
+/// request with the secret, and the service rejects requests whose
+/// signature does not match.
+#[derive(Clone)]
+pub struct Credentials {
+    secret: Vec<u8>,
+}
+
+impl Credentials {
+
+    /// Create credentials from a hex encoded secret.
+    pub fn new(secret: &str) -> Result<Self, Error> {
+        let secret = match hex::decode(secret) {
+            Ok(secret) => secret,
+            Err(_) => {
+                return Err(Error::Config(
+                    "secrets must be hex encoded".to_owned(),
+                ));
+            },
+        };
+        if secret.len() < MIN_SECRET_BYTES {
+            return Err(Error::Config(format!(
+                "secrets must be at least {} bytes long",
+                MIN_SECRET_BYTES,
+            )));
+        }
+        Ok(Credentials { secret })
+    }
+
+    /// Internal helper to compute the MAC of a request.
+    fn mac(
+        &self,
+        method: &str,
+        path: &str,
+        timestamp: &str,
+        nonce: &str,
+        body: &[u8],
+    ) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.secret).unwrap();
+        // None of the fields may contain a newline, so the message is
+        // unambiguous.
+        let head = format!("{}\n{}\n{}\n{}\n", method, path, timestamp, nonce);
+        mac.update(head.as_bytes());
+        mac.update(body);
+        mac
+    }
+
+    /// Add the authentication headers for a request to a builder.
+    pub fn sign(&self, req: Builder, body: &[u8]) -> Builder {
+        let method = req.method_ref().unwrap().to_string();
+        let path = match req.uri_ref().unwrap().path_and_query() {
+            Some(path) => path.to_string(),
+            None => "/".to_owned(),
+        };
+        let timestamp = now_millis().to_string();
+        let nonce = hex::encode(rand::random::<[u8; 16]>());
+        let mac = self.mac(&method, &path, &timestamp, &nonce, body);
+        let signature = hex::encode(mac.finalize().into_bytes());
+        req.header(TIMESTAMP_HEADER, timestamp)
+            .header(NONCE_HEADER, nonce)
+            .header(SIGNATURE_HEADER, signature)
+    }
+}
+
+/// Checks that requests to a service come from a peer holding its secret.
+///
+/// Requests must have been signed within `MAX_CLOCK_SKEW_MILLIS` of being
+/// received, and every signature is only accepted once, so a captured
+/// request cannot be replayed.
+pub struct Authenticator {
+    credentials: Credentials,
+
+    /// The signatures accepted recently, with the time at which they expire.
+    seen: Mutex<HashMap<String, u64>>,
+}
+
+impl Authenticator {
+
+    /// Create an Authenticator for a service's credentials.
+    pub fn new(credentials: Credentials) -> Self {
+        Authenticator {
+            credentials,
+            seen: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Authenticate a request.
+    ///
+    /// The body is read in order to check the signature, so the request is
+    /// returned with a body holding the same bytes.
+    pub async fn authenticate(
+        &self,
+        req: Request<Body>,
+    ) -> Result<Request<Body>, Error> {
+        let (parts, body) = req.into_parts();
+        let body = match to_bytes(body).await {
+            Ok(body) => body,
+            Err(_) => {
+                return Err(Error::BadRequest);
+            },
+        };
+        // Read the authentication headers.
+        let header = |name| match parts.headers.get(name) {
+            Some(value) => match value.to_str() {
+                Ok(value) => Ok(value),
+                Err(_) => Err(Error::Unauthenticated),
+            },
+            None => Err(Error::Unauthenticated),
+        };
+        let timestamp = header(TIMESTAMP_HEADER)?;
+        let nonce = header(NONCE_HEADER)?;
+        let signature = match hex::decode(header(SIGNATURE_HEADER)?) {
+            Ok(signature) => signature,
+            Err(_) => {
+                return Err(Error::Unauthenticated);
+            },
+        };
+        // Ensure the request was signed recently.
+        let signed_at: u64 = match timestamp.parse() {
+            Ok(signed_at) => signed_at,
+            Err(_) => {
+                return Err(Error::Unauthenticated);
+            },
+        };
+        let now = now_millis();
+        if signed_at.abs_diff(now) > MAX_CLOCK_SKEW_MILLIS {
+            return Err(Error::Unauthenticated);
+        }
+        // Verify the signature.
+        let path = match parts.uri.path_and_query() {
+            Some(path) => path.as_str(),
+            None => "/",
+        };
+        let mac = self.credentials.mac(
+            parts.method.as_str(),
+            path,
+            timestamp,
+            nonce,
+            &body,
+        );
+        if mac.verify_slice(&signature).is_err() {
+            return Err(Error::Unauthenticated);
+        }
+        // Reject replays, forgetting signatures that would now be rejected
+        // for their timestamp anyway.
+        {
+            let mut seen = self.seen.lock().unwrap();
+            seen.retain(|_, expires| *expires >= now);
+            let expires = signed_at + MAX_CLOCK_SKEW_MILLIS;
+            if seen.insert(hex::encode(&signature), expires).is_some() {
+                return Err(Error::Unauthenticated);
+            }
+        }
+        Ok(Request::from_parts(parts, Body::from(body)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "000102030405060708090a0b0c0d0e0f";
+
+    fn credentials() -> Credentials {
+        Credentials::new(SECRET).unwrap()
+    }
+
+    /// Build a request signed with some credentials.
+    fn signed(credentials: &Credentials, body: &[u8]) -> Request<Body> {
+        let req = Request::builder().method("POST").uri("/permission");
+        credentials.sign(req, body).body(Body::from(body.to_vec())).unwrap()
+    }
+
+    /// Build a request signed at a given time.
+    fn signed_at(credentials: &Credentials, timestamp: u64) -> Request<Body> {
+        let timestamp = timestamp.to_string();
+        let nonce = hex::encode(rand::random::<[u8; 16]>());
+        let mac = credentials.mac(
+            "POST",
+            "/permission",
+            &timestamp,
+            &nonce,
+            &[],
+        );
+        let signature = hex::encode(mac.finalize().into_bytes());
+        Request::builder()
+            .method("POST")
+            .uri("/permission")
+            .header(TIMESTAMP_HEADER, timestamp)
+            .header(NONCE_HEADER, nonce)
+            .header(SIGNATURE_HEADER, signature)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn accepts_signed_request() {
+        let peers = Authenticator::new(credentials());
+        let req = signed(&credentials(), b"[\"tick\"]");
+        let req = peers.authenticate(req).await.unwrap();
+        let body = to_bytes(req.into_body()).await.unwrap();
+        assert_eq!(&body[..], b"[\"tick\"]");
+    }
+
+    #[tokio::test]
+    async fn rejects_unsigned_request() {
+        let peers = Authenticator::new(credentials());
+        let req = Request::builder()
+            .method("POST")
+            .uri("/permission")
+            .body(Body::empty())
+            .unwrap();
+        let res = peers.authenticate(req).await;
+        assert!(matches!(res, Err(Error::Unauthenticated)));
+    }
+
+    #[tokio::test]
+    async fn rejects_other_secret() {
+        let peers = Authenticator::new(credentials());
+        let other = Credentials::new(&"ff".repeat(16)).unwrap();
+        let res = peers.authenticate(signed(&other, b"")).await;
+        assert!(matches!(res, Err(Error::Unauthenticated)));
+    }
+
+    #[tokio::test]
+    async fn rejects_tampered_body() {
+        let peers = Authenticator::new(credentials());
+        let req = signed(&credentials(), b"[\"tick\"]");
+        let (parts, _) = req.into_parts();
+        let req = Request::from_parts(parts, Body::from("[\"tock\"]"));
+        let res = peers.authenticate(req).await;
+        assert!(matches!(res, Err(Error::Unauthenticated)));
+    }
+
+    #[tokio::test]
+    async fn rejects_timestamp_skew() {
+        let peers = Authenticator::new(credentials());
+        let now = now_millis();
+        // Requests signed within the allowed skew are accepted.
+        let req = signed_at(&credentials(), now - MAX_CLOCK_SKEW_MILLIS / 2);
+        assert!(peers.authenticate(req).await.is_ok());
+        let req = signed_at(&credentials(), now + MAX_CLOCK_SKEW_MILLIS / 2);
+        assert!(peers.authenticate(req).await.is_ok());
+        // Requests signed too long ago or too far ahead are not.
+        let req = signed_at(&credentials(), now - MAX_CLOCK_SKEW_MILLIS - 1000);
+        let res = peers.authenticate(req).await;
+        assert!(matches!(res, Err(Error::Unauthenticated)));
+        let req = signed_at(&credentials(), now + MAX_CLOCK_SKEW_MILLIS + 1000);
+        let res = peers.authenticate(req).await;
+        assert!(matches!(res, Err(Error::Unauthenticated)));
+    }
+
+    /// Copy a request's method, path and headers into a new request.
+    fn copy(req: &Request<Body>) -> Request<Body> {
+        let mut copy = Request::builder()
+            .method(req.method())
+            .uri(req.uri())
+            .body(Body::empty())
+            .unwrap();
+        *copy.headers_mut() = req.headers().clone();
+        copy
+    }
+
+    #[tokio::test]
+    async fn rejects_replay() {
+        let peers = Authenticator::new(credentials());
+        let req = signed(&credentials(), b"");
+        let replay = copy(&req);
+        assert!(peers.authenticate(req).await.is_ok());
+        let res = peers.authenticate(replay).await;
+        assert!(matches!(res, Err(Error::Unauthenticated)));
+    }
+
+    #[tokio::test]
+    async fn rejects_replay_with_reencoded_signature() {
+        let peers = Authenticator::new(credentials());
+        let req = signed(&credentials(), b"");
+        // The same signature in upper case hex is the same signature.
+        let mut replay = copy(&req);
+        let signature = replay.headers()[SIGNATURE_HEADER].to_str().unwrap();
+        let upper = signature.to_uppercase().parse().unwrap();
+        replay.headers_mut().insert(SIGNATURE_HEADER, upper);
+        assert!(peers.authenticate(req).await.is_ok());
+        let res = peers.authenticate(replay).await;
+        assert!(matches!(res, Err(Error::Unauthenticated)));
+    }
+}
@@ -1,14 +1,25 @@
 use serde::{Serialize, Deserialize};
-use crate::u53::u53;
+use crate::{
+    condition::Condition,
+    delegation::Parent,
+    error::Error,
+    role::Role,
+    subject,
+    u53::u53,
+};
 
 /// A unique number assigned to new Permissions by the Authority.
 pub type Nonce = u53;
 
-/// Actions are identified by a string such as "sign-in" or "send-message".
+/// Actions are identified by a string such as "sign-in" or "send-message",
+/// or by a structured name such as "repo:read" or "billing/invoices/42".
+///
+/// The actions of a Permission may also be patterns and deny entries, as
+/// described by `matcher::authorize`.
 pub type Action = String;
 
 /// A Permission is a versioned collection of actions.
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq)]
 pub struct Permission {
 
     /// The Permission's unique nonce.
@@ -17,7 +28,7 @@ pub struct Permission {
     /// its lifetime of updates.
     pub nonce: Nonce,
 
-    /// The actions this Permission allows its owner to take.
+    /// The actions this Permission allows or denies its owner to take.
     pub actions: Vec<Action>,
 
     /// The version of the Permission.
@@ -25,10 +36,99 @@ pub struct Permission {
     /// This must be incremented every time the Permission is updated with
     /// different actions.
     pub version: usize,
+
+    /// When the Permission starts allowing its actions, in milliseconds
+    /// since the Unix epoch.
+    ///
+    /// Like every other field, it is part of the accumulated element, so it
+    /// cannot be altered without invalidating the Permission's Witness. It
+    /// is omitted from the element when it is not set, which keeps the
+    /// elements of Permissions without one unchanged.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub not_before: Option<u64>,
+
+    /// When the Permission stops allowing its actions, in milliseconds since
+    /// the Unix epoch.
+    ///
+    /// Expired Permissions are revoked by the Synchronizer at the next update
+    /// window.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+
+    /// The definitions of the roles granted to the Permission, whose actions
+    /// it allows or denies along with its own.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub roles: Vec<Role>,
+
+    /// The hex encoded Ed25519 public key of the subject the Permission is
+    /// bound to.
+    ///
+    /// Actions with a bound Permission are only authorized along with a
+    /// `subject::Proof` signed with the subject's key, so a leaked
+    /// Permission and Witness cannot be used by anyone else.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subject: Option<String>,
+
+    /// The conditions that requests to perform the Permission's actions
+    /// must meet.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub conditions: Vec<Condition>,
+
+    /// The Permission this one was delegated from, if any.
+    ///
+    /// A delegated Permission never allows more than its parent. It is
+    /// revoked along with the parent, and derived again from the parent
+    /// whenever the parent changes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<Parent>,
+}
+
+impl Permission {
+
+    /// Ensure the Permission's validity period is not empty, its subject,
+    /// if any, is a public key and its conditions can be evaluated.
+    pub fn validate(&self) -> Result<(), Error> {
+        if let (Some(start), Some(end)) = (self.not_before, self.expires_at) {
+            if end <= start {
+                return Err(Error::InvalidValidity);
+            }
+        }
+        if let Some(key) = &self.subject {
+            subject::validate(key)?;
+        }
+        for condition in self.conditions.iter() {
+            condition.validate()?;
+        }
+        Ok(())
+    }
+
+    /// Ensure the Permission is valid at a time given in milliseconds since
+    /// the Unix epoch.
+    pub fn check_validity(&self, now: u64) -> Result<(), Error> {
+        if self.not_before.is_some_and(|not_before| now < not_before) {
+            return Err(Error::NotYetValid);
+        }
+        if self.is_expired(now) {
+            return Err(Error::Expired);
+        }
+        Ok(())
+    }
+
+    /// Iterate over the Permission's own actions and those of its roles.
+    pub fn all_actions(&self) -> impl Iterator<Item = &Action> {
+        self.actions.iter()
+            .chain(self.roles.iter().flat_map(|role| role.actions.iter()))
+    }
+
+    /// Whether the Permission has expired at a time given in milliseconds
+    /// since the Unix epoch.
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|expires_at| now >= expires_at)
+    }
 }
 
-impl Into<Vec<u8>> for Permission {
-    fn into(self) -> Vec<u8> {
-        velocypack::to_bytes(&self).unwrap()
+impl From<Permission> for Vec<u8> {
+    fn from(perm: Permission) -> Vec<u8> {
+        velocypack::to_bytes(&perm).unwrap()
     }
 }
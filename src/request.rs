@@ -1,7 +1,13 @@
 use clacc::Witness;
 use gmp::mpz::Mpz;
 use serde::{Serialize, Deserialize};
-use crate::permission::{Action, Permission};
+use crate::{
+    checkpoint::SignedCheckpoint,
+    condition::{Attributes, Condition},
+    error::{Error, ErrorBody},
+    permission::{Action, Permission},
+    subject::Proof,
+};
 
 /// A request to perform an action.
 #[derive(Deserialize, Serialize)]
@@ -16,10 +22,20 @@ pub struct ActionRequest {
 
     /// The action being taken.
     pub action: Action,
+
+    /// The attributes of the request, which the Permission's conditions on
+    /// the action are evaluated against.
+    #[serde(default, skip_serializing_if = "Attributes::is_empty")]
+    pub attributes: Attributes,
+
+    /// Proof that the action is requested by the Permission's subject, if it
+    /// is bound to one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proof: Option<Proof>,
 }
 
 /// A request to update an existing Permission by altering its actions.
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, Clone)]
 pub struct UpdateRequest {
 
     /// The previous version of the Permission.
@@ -33,13 +49,369 @@ pub struct UpdateRequest {
     pub update: Permission,
 }
 
+/// A request to delete an existing Permission from the accumulation.
+#[derive(Deserialize, Serialize, Clone)]
+pub struct DeleteRequest {
+
+    /// The Permission being deleted.
+    pub perm: Permission,
+
+    /// The Witness attesting that the Permission is a member of the
+    /// accumulation.
+    pub witness: Witness<Mpz>,
+}
+
 /// A response to the UpdateRequest.
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, Clone)]
 pub struct UpdateResponse {
 
     /// The original UpdateRequest.
     pub req: UpdateRequest,
 
-    /// The accumulation value after the Permision has been updated.
-    pub value: Mpz,
+    /// A Checkpoint of the accumulation value after the Permission has been
+    /// updated.
+    pub checkpoint: SignedCheckpoint,
+}
+
+/// A response to the DeleteRequest.
+#[derive(Deserialize, Serialize, Clone)]
+pub struct DeleteResponse {
+
+    /// The original DeleteRequest.
+    pub req: DeleteRequest,
+
+    /// A Checkpoint of the accumulation value after the Permission has been
+    /// deleted.
+    pub checkpoint: SignedCheckpoint,
+}
+
+/// A response to a batch of UpdateRequests.
+#[derive(Deserialize, Serialize, Clone)]
+pub struct BatchUpdateResponse {
+
+    /// The outcome of each UpdateRequest, in the order they were requested.
+    /// An applied update carries the new version of its Permission.
+    pub results: Vec<BatchResult>,
+
+    /// The UpdateRequests that were applied, in the order they were applied.
+    pub reqs: Vec<UpdateRequest>,
+
+    /// A Checkpoint of the accumulation value after the Permissions have
+    /// been updated.
+    pub checkpoint: SignedCheckpoint,
+}
+
+/// The accumulation values of the Authority's Accumulators.
+#[derive(Deserialize, Serialize, Clone)]
+pub struct ValuesResponse {
+
+    /// The value Witnesses are verified against.
+    pub verifying: Mpz,
+
+    /// The value the Worker is updating Witnesses to.
+    pub updating: Mpz,
+
+    /// The value including every change made so far.
+    pub staging: Mpz,
+}
+
+/// A request to the Worker to allocate its Accumulator.
+#[derive(Deserialize, Serialize)]
+pub struct KeyRequest {
+
+    /// The Authority's latest Checkpoint, which carries the Accumulator's
+    /// public key.
+    pub checkpoint: SignedCheckpoint,
+}
+
+/// A request to the Synchronizer to create a Permission.
+#[derive(Deserialize, Serialize, Clone)]
+pub struct CreateRequest {
+
+    /// The actions the Permission allows.
+    pub actions: Vec<Action>,
+
+    /// When the Permission starts allowing its actions, in milliseconds
+    /// since the Unix epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub not_before: Option<u64>,
+
+    /// When the Permission stops allowing its actions, in milliseconds since
+    /// the Unix epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+
+    /// The names of the roles the Permission is granted.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub roles: Vec<String>,
+
+    /// The hex encoded public key of the subject the Permission is bound
+    /// to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subject: Option<String>,
+
+    /// The conditions that requests to perform the actions must meet.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub conditions: Vec<Condition>,
+}
+
+impl From<Vec<Action>> for CreateRequest {
+    fn from(actions: Vec<Action>) -> Self {
+        CreateRequest {
+            actions,
+            not_before: None,
+            expires_at: None,
+            roles: Vec::new(),
+            subject: None,
+            conditions: Vec::new(),
+        }
+    }
+}
+
+/// A request to define a role, or redefine it with new actions.
+#[derive(Deserialize, Serialize, Clone)]
+pub struct RoleRequest {
+
+    /// The name of the role.
+    pub name: String,
+
+    /// The actions the role allows or denies.
+    pub actions: Vec<Action>,
+}
+
+/// A request to the Synchronizer to replace the actions of a Permission.
+#[derive(Deserialize, Serialize)]
+pub struct UpdateActionsRequest {
+
+    /// The current version of the Permission.
+    pub perm: Permission,
+
+    /// The actions the next version of the Permission allows.
+    pub actions: Vec<Action>,
+
+    /// The names of the roles the next version of the Permission is
+    /// granted, if they change.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub roles: Option<Vec<String>>,
+
+    /// The conditions of the next version of the Permission, if they
+    /// change.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub conditions: Option<Vec<Condition>>,
+}
+
+/// A request to the Synchronizer to authorize an action.
+#[derive(Deserialize, Serialize)]
+pub struct AuthorizeRequest {
+
+    /// The Permission associated with the action.
+    pub perm: Permission,
+
+    /// The action being taken.
+    pub action: Action,
+
+    /// The attributes of the request, which the Permission's conditions on
+    /// the action are evaluated against.
+    #[serde(default, skip_serializing_if = "Attributes::is_empty")]
+    pub attributes: Attributes,
+
+    /// Proof that the action is requested by the Permission's subject, if it
+    /// is bound to one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proof: Option<Proof>,
+}
+
+/// A request to the Synchronizer to authorize every action of a list for
+/// every Permission of a list.
+#[derive(Deserialize, Serialize, Clone)]
+pub struct ActionsRequest {
+
+    /// The Permissions associated with the actions.
+    pub perms: Vec<Permission>,
+
+    /// The actions being taken.
+    pub actions: Vec<Action>,
+
+    /// The attributes of the request, which the Permissions' conditions on
+    /// the actions are evaluated against.
+    #[serde(default, skip_serializing_if = "Attributes::is_empty")]
+    pub attributes: Attributes,
+
+    /// Proofs that the actions are requested by the subjects of the
+    /// Permissions bound to one, indexed by Permission and then by action
+    /// like the decisions.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub proofs: Vec<Vec<Option<Proof>>>,
+}
+
+/// A request to the Authority to authorize every action of a list for
+/// every Permission of a list.
+#[derive(Deserialize, Serialize)]
+pub struct WitnessedActions {
+
+    /// The original ActionsRequest.
+    pub req: ActionsRequest,
+
+    /// The Witnesses attesting that the Permissions are members of the
+    /// accumulation, or `None` for Permissions without one.
+    pub witnesses: Vec<Option<Witness<Mpz>>>,
+}
+
+/// The decision on one action for one Permission of an ActionsRequest.
+#[derive(Deserialize, Serialize)]
+pub struct Decision {
+
+    /// Whether the action is allowed.
+    pub allowed: bool,
+
+    /// The reason the action is not allowed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorBody>,
+}
+
+impl Decision {
+
+    /// Convert the decision back into a Result.
+    pub fn into_result(self) -> Result<(), Error> {
+        match (self.allowed, self.error) {
+            (true, _) => Ok(()),
+            (false, Some(body)) => Err(body.error),
+            (false, None) => Err(Error::NotGranted),
+        }
+    }
+}
+
+impl From<Result<(), Error>> for Decision {
+    fn from(res: Result<(), Error>) -> Self {
+        match res {
+            Ok(_) => Decision {
+                allowed: true,
+                error: None,
+            },
+            Err(err) => Decision {
+                allowed: false,
+                error: Some(err.into()),
+            },
+        }
+    }
+}
+
+/// A request to the Synchronizer to delegate a subset of a Permission's
+/// actions to a new child Permission.
+#[derive(Deserialize, Serialize, Clone)]
+pub struct DelegateRequest {
+
+    /// The current version of the parent Permission.
+    pub perm: Permission,
+
+    /// The actions the child allows or denies, each of which the parent
+    /// must allow.
+    pub actions: Vec<Action>,
+
+    /// When the child stops allowing its actions, in milliseconds since the
+    /// Unix epoch. The parent's expiry applies if it is earlier.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+
+    /// The hex encoded public key of the subject the child is bound to, if
+    /// not the parent's.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subject: Option<String>,
+
+    /// Proof that the delegation is requested by the parent's subject, if it
+    /// is bound to one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proof: Option<Proof>,
+}
+
+/// A request to the Authority to add a child Permission.
+#[derive(Deserialize, Serialize)]
+pub struct WitnessedDelegation {
+
+    /// The original DelegateRequest.
+    pub req: DelegateRequest,
+
+    /// The Witness attesting that the parent Permission is a member of the
+    /// accumulation.
+    pub witness: Witness<Mpz>,
+}
+
+/// A delegated Permission along with the current version of its parent.
+#[derive(Deserialize, Serialize)]
+pub struct DelegatedPermission {
+
+    /// The delegated Permission.
+    pub perm: Permission,
+
+    /// The current version of the parent, or `None` if it has been revoked.
+    pub parent: Option<Permission>,
+}
+
+/// The outcome of one item of a batch of Permissions to create or update.
+///
+/// It is used instead of a Result both by the public API and between the
+/// services, since velocypack cannot serialize enums with data.
+#[derive(Deserialize, Serialize, Clone)]
+pub struct BatchResult {
+
+    /// The Permission that was created or updated.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub perm: Option<Permission>,
+
+    /// The reason the item was rejected.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorBody>,
+}
+
+impl BatchResult {
+
+    /// Convert the outcome back into a Result.
+    pub fn into_result(self) -> Result<Permission, Error> {
+        match (self.perm, self.error) {
+            (_, Some(body)) => Err(body.error),
+            (Some(perm), None) => Ok(perm),
+            (None, None) => Err(Error::BadRequest),
+        }
+    }
+}
+
+impl From<Result<Permission, Error>> for BatchResult {
+    fn from(res: Result<Permission, Error>) -> Self {
+        match res {
+            Ok(perm) => BatchResult {
+                perm: Some(perm),
+                error: None,
+            },
+            Err(err) => BatchResult {
+                perm: None,
+                error: Some(err.into()),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::body::Bytes;
+    use crate::util::from_bytes;
+
+    #[test]
+    fn decisions_round_trip_between_services() {
+        let results = vec![
+            vec![Ok(()), Err(Error::NotGranted)],
+            vec![Err(Error::Rotation("in progress".to_owned())), Ok(())],
+        ];
+        // Encode the decisions like the Authority and decode them like the
+        // Synchronizer.
+        let decisions: Vec<Vec<Decision>> = results.iter()
+            .map(|row| row.iter().cloned().map(Decision::from).collect())
+            .collect();
+        let bytes = velocypack::to_bytes(&decisions).map(Bytes::from);
+        let decoded: Vec<Vec<Decision>> = from_bytes(&bytes).unwrap();
+        let decoded: Vec<Vec<Result<(), Error>>> = decoded.into_iter()
+            .map(|row| row.into_iter().map(Decision::into_result).collect())
+            .collect();
+        assert_eq!(decoded, results);
+    }
 }
@@ -0,0 +1,110 @@
+use serde::{Serialize, Deserialize};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+use crate::{
+    error::Error,
+    permission::{Action, Permission},
+    request::RoleRequest,
+    store,
+};
+
+/// A role is a named, versioned set of actions, such as "admin" or
+/// "support-agent".
+///
+/// A Permission references a role by carrying the definition it was granted
+/// with. The definition is part of the accumulated element just like the
+/// Permission's own actions, so verifying an action needs nothing but the
+/// Permission and its Witness. When a role is redefined, the Synchronizer
+/// updates every Permission carrying an earlier version of it.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Role {
+
+    /// The role's unique name.
+    pub name: String,
+
+    /// The version of the role, incremented every time it is redefined.
+    pub version: usize,
+
+    /// The actions the role allows or denies, which may be patterns as
+    /// described by `matcher::authorize`.
+    pub actions: Vec<Action>,
+}
+
+/// The current definitions of the roles, kept by the Authority.
+pub struct Registry {
+    roles: HashMap<String, Role>,
+
+    /// The file the definitions are persisted to, if any.
+    path: Option<PathBuf>,
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Registry {
+
+    /// Create an empty Registry that is not persisted.
+    pub fn new() -> Self {
+        Registry {
+            roles: HashMap::new(),
+            path: None,
+        }
+    }
+
+    /// Open a Registry persisted to a file.
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        let roles: Vec<Role> = store::load(path)?.unwrap_or_default();
+        Ok(Registry {
+            roles: roles.into_iter()
+                .map(|role| (role.name.clone(), role))
+                .collect(),
+            path: Some(path.to_owned()),
+        })
+    }
+
+    /// Return every role, ordered by name.
+    pub fn list(&self) -> Vec<Role> {
+        let mut roles: Vec<Role> = self.roles.values().cloned().collect();
+        roles.sort_by(|a, b| a.name.cmp(&b.name));
+        roles
+    }
+
+    /// Define a role, or redefine it with the next version.
+    pub fn define(&mut self, req: RoleRequest) -> Result<Role, Error> {
+        if req.name.is_empty() {
+            return Err(Error::BadRequest);
+        }
+        let version = match self.roles.get(&req.name) {
+            Some(role) => role.version + 1,
+            None => 0,
+        };
+        let role = Role {
+            name: req.name,
+            version,
+            actions: req.actions,
+        };
+        let mut roles = self.roles.clone();
+        roles.insert(role.name.clone(), role.clone());
+        if let Some(path) = &self.path {
+            let list: Vec<&Role> = roles.values().collect();
+            store::save(path, &list)?;
+        }
+        self.roles = roles;
+        Ok(role)
+    }
+
+    /// Ensure every role a Permission carries is the current definition.
+    pub fn check(&self, perm: &Permission) -> Result<(), Error> {
+        for role in perm.roles.iter() {
+            if self.roles.get(&role.name) != Some(role) {
+                return Err(Error::InvalidRole(role.name.clone()));
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,135 +1,401 @@
 use compauth::{
     authority::Authority,
-    constant::AUTHORITY_ADDR,
+    ceremony::{Share, Vault},
+    checkpoint::format_public_key,
+    config::Config,
+    error::Error,
+    keystore,
+    peer::{Authenticator, Credentials},
     permission::Permission, 
-    request::{UpdateRequest, ActionRequest},
-    util::from_bytes,
+    request::{
+        BatchResult,
+        Decision,
+        UpdateRequest,
+        DeleteRequest,
+        ActionRequest,
+        RoleRequest,
+        WitnessedActions,
+        WitnessedDelegation,
+    },
+    tenant::Tenants,
+    tls::serve,
+    util::{error_response, from_bytes},
 };
 use hyper::{
-    Body, Error, Method, Request, Response, Server, StatusCode,
+    Body, Error as HyperError, Method, Request, Response, StatusCode,
     body::to_bytes,
-    service::{make_service_fn, service_fn},
 };
-use std::{convert::Infallible, sync::{Arc, atomic::AtomicPtr}};
-use tokio::sync::Mutex;
+use std::sync::Arc;
 
 async fn handle_key(
-    m: Arc<Mutex<AtomicPtr<Authority>>>,
+    auth: Arc<Authority>,
 ) -> Response<Body> {
-    let auth = unsafe {
-        (*m.lock().await).get_mut().as_ref().unwrap()
-    };
-    let resp = velocypack::to_bytes(auth.get_key()).unwrap();
+    let resp = velocypack::to_bytes(&auth.get_key()).unwrap();
+    Response::new(resp.into())
+}
+
+async fn handle_signing_key(
+    auth: Arc<Authority>,
+) -> Response<Body> {
+    let key = format_public_key(&auth.get_signing_key());
+    let resp = velocypack::to_bytes(&key).unwrap();
+    Response::new(resp.into())
+}
+
+async fn handle_checkpoint(
+    auth: Arc<Authority>,
+) -> Response<Body> {
+    let resp = velocypack::to_bytes(&auth.checkpoint().await).unwrap();
+    Response::new(resp.into())
+}
+
+async fn handle_checkpoints(
+    auth: Arc<Authority>,
+) -> Response<Body> {
+    let resp = velocypack::to_bytes(&auth.checkpoints().await).unwrap();
+    Response::new(resp.into())
+}
+
+async fn handle_values(
+    auth: Arc<Authority>,
+) -> Response<Body> {
+    let resp = velocypack::to_bytes(&auth.values().await).unwrap();
+    Response::new(resp.into())
+}
+
+async fn handle_roles(
+    auth: Arc<Authority>,
+) -> Response<Body> {
+    let resp = velocypack::to_bytes(&auth.roles().await).unwrap();
     Response::new(resp.into())
 }
 
+async fn handle_define_role(
+    auth: Arc<Authority>,
+    req: Request<Body>,
+) -> Response<Body> {
+    let bytes = to_bytes(req.into_body()).await;
+    let req: RoleRequest = match from_bytes(&bytes) {
+        Some(res) => res,
+        None => {
+            return error_response(Error::BadRequest);
+        },
+    };
+    match auth.define_role(req).await {
+        Ok(role) => {
+            let resp = velocypack::to_bytes(&role).unwrap();
+            Response::new(resp.into())
+        },
+        Err(err) => error_response(err),
+    }
+}
+
 async fn handle_add_perm(
-    m: Arc<Mutex<AtomicPtr<Authority>>>,
+    auth: Arc<Authority>,
     req: Request<Body>,
 ) -> Response<Body> {
     let bytes = to_bytes(req.into_body()).await;
     let perm: Permission = match from_bytes(&bytes) {
         Some(res) => res,
         None => {
-            let mut bad_request = Response::default();
-            *bad_request.status_mut() = StatusCode::BAD_REQUEST;
-            return bad_request;
+            return error_response(Error::BadRequest);
         },
     };
-    let auth = unsafe {
-        (*m.lock().await).get_mut().as_mut().unwrap()
+    match auth.add_permission(perm).await {
+        Ok(result) => {
+            let resp = velocypack::to_bytes(&result).unwrap();
+            Response::new(resp.into())
+        },
+        Err(err) => error_response(err),
+    }
+}
+
+async fn handle_add_perms(
+    auth: Arc<Authority>,
+    req: Request<Body>,
+) -> Response<Body> {
+    let bytes = to_bytes(req.into_body()).await;
+    let perms: Vec<Permission> = match from_bytes(&bytes) {
+        Some(res) => res,
+        None => {
+            return error_response(Error::BadRequest);
+        },
     };
-    let result = auth.add_permission(perm).await;
-    let resp = velocypack::to_bytes(&result).unwrap();
+    let results: Vec<BatchResult> = auth.add_permissions(perms).await
+        .into_iter()
+        .map(BatchResult::from)
+        .collect();
+    let resp = velocypack::to_bytes(&results).unwrap();
     Response::new(resp.into())
 }
 
+async fn handle_delegate(
+    auth: Arc<Authority>,
+    req: Request<Body>,
+) -> Response<Body> {
+    let bytes = to_bytes(req.into_body()).await;
+    let req: WitnessedDelegation = match from_bytes(&bytes) {
+        Some(res) => res,
+        None => {
+            return error_response(Error::BadRequest);
+        },
+    };
+    match auth.delegate(req).await {
+        Ok(result) => {
+            let resp = velocypack::to_bytes(&result).unwrap();
+            Response::new(resp.into())
+        },
+        Err(err) => error_response(err),
+    }
+}
+
 async fn handle_update_perm(
-    m: Arc<Mutex<AtomicPtr<Authority>>>,
+    auth: Arc<Authority>,
     req: Request<Body>,
 ) -> Response<Body> {
     let bytes = to_bytes(req.into_body()).await;
     let req: UpdateRequest = match from_bytes(&bytes) {
         Some(res) => res,
         None => {
-            let mut bad_request = Response::default();
-            *bad_request.status_mut() = StatusCode::BAD_REQUEST;
-            return bad_request;
+            return error_response(Error::BadRequest);
         },
     };
-    let auth = unsafe {
-        (*m.lock().await).get_mut().as_mut().unwrap()
-    };
     match auth.update_permission(req).await {
         Ok(result) => {
             let resp = velocypack::to_bytes(&result).unwrap();
             Response::new(resp.into())
         },
-        _ => {
-            let mut unauthorized = Response::default();
-            *unauthorized.status_mut() = StatusCode::UNAUTHORIZED;
-            unauthorized
+        Err(err) => error_response(err),
+    }
+}
+
+async fn handle_update_perms(
+    auth: Arc<Authority>,
+    req: Request<Body>,
+) -> Response<Body> {
+    let bytes = to_bytes(req.into_body()).await;
+    let reqs: Vec<UpdateRequest> = match from_bytes(&bytes) {
+        Some(res) => res,
+        None => {
+            return error_response(Error::BadRequest);
+        },
+    };
+    let result = auth.update_permissions(reqs).await;
+    let resp = velocypack::to_bytes(&result).unwrap();
+    Response::new(resp.into())
+}
+
+async fn handle_delete_perm(
+    auth: Arc<Authority>,
+    req: Request<Body>,
+) -> Response<Body> {
+    let bytes = to_bytes(req.into_body()).await;
+    let req: DeleteRequest = match from_bytes(&bytes) {
+        Some(res) => res,
+        None => {
+            return error_response(Error::BadRequest);
         },
+    };
+    match auth.delete_permission(req).await {
+        Ok(result) => {
+            let resp = velocypack::to_bytes(&result).unwrap();
+            Response::new(resp.into())
+        },
+        Err(err) => error_response(err),
     }
 }
 
 async fn handle_action(
-    m: Arc<Mutex<AtomicPtr<Authority>>>,
+    auth: Arc<Authority>,
     req: Request<Body>,
 ) -> Response<Body> {
     let bytes = to_bytes(req.into_body()).await;
     let req: ActionRequest = match from_bytes(&bytes) {
         Some(res) => res,
         None => {
-            let mut bad_request = Response::default();
-            *bad_request.status_mut() = StatusCode::BAD_REQUEST;
-            return bad_request;
+            return error_response(Error::BadRequest);
         },
     };
-    let auth = unsafe {
-        (*m.lock().await).get_mut().as_mut().unwrap()
-    };
     match auth.action(req).await {
         Ok(_) => Response::default(),
-        _ => {
-            let mut unauthorized = Response::default();
-            *unauthorized.status_mut() = StatusCode::UNAUTHORIZED;
-            unauthorized
+        Err(err) => error_response(err),
+    }
+}
+
+async fn handle_actions(
+    auth: Arc<Authority>,
+    req: Request<Body>,
+) -> Response<Body> {
+    let bytes = to_bytes(req.into_body()).await;
+    let req: WitnessedActions = match from_bytes(&bytes) {
+        Some(res) => res,
+        None => {
+            return error_response(Error::BadRequest);
+        },
+    };
+    match auth.actions(req).await {
+        Ok(decisions) => {
+            let decisions: Vec<Vec<Decision>> = decisions.into_iter()
+                .map(|row| row.into_iter().map(Decision::from).collect())
+                .collect();
+            let resp = velocypack::to_bytes(&decisions).unwrap();
+            Response::new(resp.into())
         },
+        Err(err) => error_response(err),
     }
 }
 
 async fn handle_update(
-    m: Arc<Mutex<AtomicPtr<Authority>>>,
+    auth: Arc<Authority>,
 ) -> Response<Body> {
-    let auth = unsafe {
-        (*m.lock().await).get_mut().as_mut().unwrap()
-    };
-    auth.update().await;
-    Response::default()
+    match auth.update().await {
+        Ok(_) => Response::default(),
+        Err(err) => error_response(err),
+    }
 }
 
 async fn handle_sync(
-    m: Arc<Mutex<AtomicPtr<Authority>>>,
+    auth: Arc<Authority>,
+) -> Response<Body> {
+    match auth.sync().await {
+        Ok(_) => Response::default(),
+        Err(err) => error_response(err),
+    }
+}
+
+async fn handle_begin_rotation(
+    auth: Arc<Authority>,
+    req: Request<Body>,
 ) -> Response<Body> {
-    let auth = unsafe {
-        (*m.lock().await).get_mut().as_mut().unwrap()
+    let bytes = to_bytes(req.into_body()).await;
+    let perms: Vec<Permission> = match from_bytes(&bytes) {
+        Some(res) => res,
+        None => {
+            return error_response(Error::BadRequest);
+        },
     };
-    auth.sync().await;
+    match auth.begin_rotation(perms).await {
+        Ok(checkpoint) => {
+            let resp = velocypack::to_bytes(&checkpoint).unwrap();
+            Response::new(resp.into())
+        },
+        Err(err) => error_response(err),
+    }
+}
+
+async fn handle_commit_rotation(
+    auth: Arc<Authority>,
+) -> Response<Body> {
+    match auth.commit_rotation().await {
+        Ok(_) => Response::default(),
+        Err(err) => error_response(err),
+    }
+}
+
+async fn handle_abort_rotation(
+    auth: Arc<Authority>,
+) -> Response<Body> {
+    auth.abort_rotation().await;
     Response::default()
 }
 
+async fn handle_seal_status(
+    vault: Arc<Vault>,
+) -> Response<Body> {
+    let resp = velocypack::to_bytes(&vault.status().await).unwrap();
+    Response::new(resp.into())
+}
+
+async fn handle_unseal(
+    vault: Arc<Vault>,
+    req: Request<Body>,
+) -> Response<Body> {
+    let bytes = to_bytes(req.into_body()).await;
+    let share: Share = match from_bytes(&bytes) {
+        Some(res) => res,
+        None => {
+            return error_response(Error::BadRequest);
+        },
+    };
+    match vault.unseal(share).await {
+        Ok(status) => {
+            let resp = velocypack::to_bytes(&status).unwrap();
+            Response::new(resp.into())
+        },
+        Err(err) => error_response(err),
+    }
+}
+
 async fn handle(
-    m: Arc<Mutex<AtomicPtr<Authority>>>,
+    vaults: Arc<Tenants<Vault>>,
+    peers: Arc<Authenticator>,
     req: Request<Body>,
-) -> Result<Response<Body>, Error> {
+) -> Result<Response<Body>, HyperError> {
+    // Only serve peers holding the Authority's secret.
+    let req = match peers.authenticate(req).await {
+        Ok(req) => req,
+        Err(err) => {
+            return Ok(error_response(err));
+        },
+    };
+    // Serve the request with the Authority of its tenant, which is sealed
+    // and unsealed on its own.
+    let (vault, req) = match vaults.route(req) {
+        Ok(routed) => routed,
+        Err(err) => {
+            return Ok(error_response(err));
+        },
+    };
+    // Serve unsealing requests regardless of whether the Authority is
+    // sealed, and every other request only once it has been unsealed.
+    match (req.method(), req.uri().path()) {
+        (&Method::GET, "/seal") => {
+            return Ok(handle_seal_status(vault).await);
+        },
+        (&Method::POST, "/unseal") => {
+            return Ok(handle_unseal(vault, req).await);
+        },
+        _ => {},
+    }
+    let auth = match vault.authority() {
+        Ok(auth) => auth,
+        Err(err) => {
+            return Ok(error_response(err));
+        },
+    };
     match (req.method(), req.uri().path()) {
-        (&Method::GET, "/key") => Ok(handle_key(m).await),
-        (&Method::POST, "/permission") => Ok(handle_add_perm(m, req).await),
-        (&Method::PUT, "/permission") => Ok(handle_update_perm(m, req).await),
-        (&Method::POST, "/action") => Ok(handle_action(m, req).await),
-        (&Method::GET, "/update") => Ok(handle_update(m).await),
-        (&Method::GET, "/sync") => Ok(handle_sync(m).await),
+        (&Method::GET, "/key") => Ok(handle_key(auth).await),
+        (&Method::GET, "/signing-key") => Ok(handle_signing_key(auth).await),
+        (&Method::GET, "/checkpoint") => Ok(handle_checkpoint(auth).await),
+        (&Method::GET, "/checkpoints") => Ok(handle_checkpoints(auth).await),
+        (&Method::GET, "/values") => Ok(handle_values(auth).await),
+        (&Method::GET, "/roles") => Ok(handle_roles(auth).await),
+        (&Method::PUT, "/role") => Ok(handle_define_role(auth, req).await),
+        (&Method::POST, "/permission") => Ok(handle_add_perm(auth, req).await),
+        (&Method::PUT, "/permission") => {
+            Ok(handle_update_perm(auth, req).await)
+        },
+        (&Method::DELETE, "/permission") => {
+            Ok(handle_delete_perm(auth, req).await)
+        },
+        (&Method::POST, "/permissions") => {
+            Ok(handle_add_perms(auth, req).await)
+        },
+        (&Method::PUT, "/permissions") => {
+            Ok(handle_update_perms(auth, req).await)
+        },
+        (&Method::POST, "/delegate") => Ok(handle_delegate(auth, req).await),
+        (&Method::POST, "/action") => Ok(handle_action(auth, req).await),
+        (&Method::POST, "/actions") => Ok(handle_actions(auth, req).await),
+        (&Method::GET, "/update") => Ok(handle_update(auth).await),
+        (&Method::GET, "/sync") => Ok(handle_sync(auth).await),
+        (&Method::POST, "/rotate") => {
+            Ok(handle_begin_rotation(auth, req).await)
+        },
+        (&Method::DELETE, "/rotate") => Ok(handle_abort_rotation(auth).await),
+        (&Method::POST, "/rotate/commit") => {
+            Ok(handle_commit_rotation(auth).await)
+        },
         _ => {
             let mut not_found = Response::default();
             *not_found.status_mut() = StatusCode::NOT_FOUND;
@@ -138,19 +404,33 @@ async fn handle(
     }
 }
 
+fn open_vault(config: &Config) -> Vault {
+    let dir = &config.authority_data_dir;
+    match config.authority_sealed {
+        true => Vault::sealed(dir),
+        false => {
+            let keys = keystore::open(config).unwrap();
+            let auth = Authority::open_with_key_store(dir, keys).unwrap();
+            Vault::unsealed(dir, auth)
+        },
+    }
+}
+
 #[tokio::main]
 async fn main() {
-    let mut authority = Authority::new();
-    let m = Arc::new(Mutex::new(AtomicPtr::new(&mut authority)));
-    let make_service = make_service_fn(move |_| {
-        let m = Arc::clone(&m);
-        async move {
-            Ok::<_, Infallible>(service_fn(move |req| {
-                handle(Arc::clone(&m), req)
-            }))
-        }
-    });
-    let addr = AUTHORITY_ADDR.parse().unwrap();
-    let server = Server::bind(&addr).serve(make_service);
-    server.await.unwrap();
+    let env = env_logger::Env::default().default_filter_or("warn");
+    env_logger::Builder::from_env(env).init();
+    let config = Config::load(&["authority_secret", "authority_key_secret"]);
+    let credentials = Credentials::new(&config.authority_secret).unwrap();
+    let peers = Arc::new(Authenticator::new(credentials));
+    let mut vaults = Tenants::new(open_vault(&config));
+    for id in config.tenant_ids() {
+        vaults.insert(&id, open_vault(&config.for_tenant(&id)));
+    }
+    let vaults = Arc::new(vaults);
+    let tls = config.authority_tls().unwrap();
+    let addr = config.authority_addr.parse().unwrap();
+    serve(addr, tls, move |req| {
+        handle(Arc::clone(&vaults), Arc::clone(&peers), req)
+    }).await.unwrap();
 }
@@ -1,117 +1,342 @@
 use compauth::{
     synchronizer::Synchronizer,
-    constant::SYNCHRONIZER_ADDR,
-    permission::{Action, Permission},
-    util::from_json,
+    config::Config,
+    error::Error,
+    peer::{Authenticator, Credentials},
+    permission::{Action, Nonce, Permission},
+    request::{
+        ActionsRequest,
+        AuthorizeRequest,
+        BatchResult,
+        CreateRequest,
+        Decision,
+        DelegateRequest,
+        RoleRequest,
+        UpdateActionsRequest,
+    },
+    tenant::{self, Tenants},
+    tls::serve,
+    util::{error_response, from_json},
 };
 use hyper::{
-    Body, Error, Method, Request, Response, Server, StatusCode,
+    Body, Error as HyperError, Method, Request, Response, StatusCode,
     body::to_bytes,
-    service::{make_service_fn, service_fn},
 };
-use serde::Deserialize;
-use std::{convert::Infallible, sync::{Arc, atomic::AtomicPtr}};
-use tokio::sync::Mutex;
+use std::sync::Arc;
 
-#[derive(Deserialize)]
-struct UpdateRequest {
-    perm: Permission,
-    actions: Vec<Action>,
+async fn handle_add_perm(
+    sync: Arc<Synchronizer>,
+    req: Request<Body>,
+) -> Response<Body> {
+    let bytes = to_bytes(req.into_body()).await;
+    // Accept a bare list of actions as well as a full request.
+    let req: CreateRequest = match from_json::<Vec<Action>, _>(&bytes) {
+        Some(actions) => actions.into(),
+        None => match from_json(&bytes) {
+            Some(res) => res,
+            None => {
+                return error_response(Error::BadRequest);
+            },
+        },
+    };
+    match sync.add_permission(req).await {
+        Ok(res) => Response::new(serde_json::to_string(&res).unwrap().into()),
+        Err(err) => error_response(err),
+    }
 }
 
-#[derive(Deserialize)]
-struct ActionRequest {
-    perm: Permission,
-    action: Action,
+async fn handle_update_perm(
+    sync: Arc<Synchronizer>,
+    req: Request<Body>,
+) -> Response<Body> {
+    let bytes = to_bytes(req.into_body()).await;
+    let req: UpdateActionsRequest = match from_json(&bytes) {
+        Some(res) => res,
+        None => {
+            return error_response(Error::BadRequest);
+        },
+    };
+    match sync.update_permission(req).await {
+        Ok(res) => Response::new(serde_json::to_string(&res).unwrap().into()),
+        Err(err) => error_response(err),
+    }
 }
 
-async fn handle_add_perm(
-    m: Arc<Mutex<AtomicPtr<Synchronizer>>>,
+async fn handle_add_perms(
+    sync: Arc<Synchronizer>,
     req: Request<Body>,
 ) -> Response<Body> {
     let bytes = to_bytes(req.into_body()).await;
-    let actions: Vec<Action> = match from_json(&bytes) {
+    let reqs: Vec<CreateRequest> = match from_json(&bytes) {
         Some(res) => res,
         None => {
-            let mut bad_request = Response::default();
-            *bad_request.status_mut() = StatusCode::BAD_REQUEST;
-            return bad_request;
+            return error_response(Error::BadRequest);
         },
     };
-    let sync = unsafe {
-        (*m.lock().await).get_mut().as_mut().unwrap()
-    };
-    match sync.add_permission(actions).await {
-        Ok(res) => Response::new(serde_json::to_string(&res).unwrap().into()),
-        _ => {
-            let mut unauthorized = Response::default();
-            *unauthorized.status_mut() = StatusCode::UNAUTHORIZED;
-            unauthorized
+    match sync.add_permissions(reqs).await {
+        Ok(results) => {
+            let results: Vec<BatchResult> = results.into_iter()
+                .map(BatchResult::from)
+                .collect();
+            Response::new(serde_json::to_string(&results).unwrap().into())
         },
+        Err(err) => error_response(err),
     }
 }
 
-async fn handle_update_perm(
-    m: Arc<Mutex<AtomicPtr<Synchronizer>>>,
+async fn handle_update_perms(
+    sync: Arc<Synchronizer>,
     req: Request<Body>,
 ) -> Response<Body> {
     let bytes = to_bytes(req.into_body()).await;
-    let req: UpdateRequest = match from_json(&bytes) {
+    let reqs: Vec<UpdateActionsRequest> = match from_json(&bytes) {
         Some(res) => res,
         None => {
-            let mut bad_request = Response::default();
-            *bad_request.status_mut() = StatusCode::BAD_REQUEST;
-            return bad_request;
+            return error_response(Error::BadRequest);
         },
     };
-    let sync = unsafe {
-        (*m.lock().await).get_mut().as_mut().unwrap()
+    match sync.update_permissions(reqs).await {
+        Ok(results) => {
+            let results: Vec<BatchResult> = results.into_iter()
+                .map(BatchResult::from)
+                .collect();
+            Response::new(serde_json::to_string(&results).unwrap().into())
+        },
+        Err(err) => error_response(err),
+    }
+}
+
+async fn handle_delegate(
+    sync: Arc<Synchronizer>,
+    req: Request<Body>,
+) -> Response<Body> {
+    let bytes = to_bytes(req.into_body()).await;
+    let req: DelegateRequest = match from_json(&bytes) {
+        Some(res) => res,
+        None => {
+            return error_response(Error::BadRequest);
+        },
     };
-    match sync.update_permission(req.perm, req.actions).await {
+    match sync.delegate(req).await {
         Ok(res) => Response::new(serde_json::to_string(&res).unwrap().into()),
-        _ => {
-            let mut unauthorized = Response::default();
-            *unauthorized.status_mut() = StatusCode::UNAUTHORIZED;
-            unauthorized
+        Err(err) => error_response(err),
+    }
+}
+
+async fn handle_roles(
+    sync: Arc<Synchronizer>,
+) -> Response<Body> {
+    match sync.roles().await {
+        Ok(roles) => {
+            Response::new(serde_json::to_string(&roles).unwrap().into())
         },
+        Err(err) => error_response(err),
     }
 }
 
-async fn handle_action(
-    m: Arc<Mutex<AtomicPtr<Synchronizer>>>,
+async fn handle_define_role(
+    sync: Arc<Synchronizer>,
     req: Request<Body>,
 ) -> Response<Body> {
     let bytes = to_bytes(req.into_body()).await;
-    let req: ActionRequest = match from_json(&bytes) {
+    let req: RoleRequest = match from_json(&bytes) {
         Some(res) => res,
         None => {
-            let mut bad_request = Response::default();
-            *bad_request.status_mut() = StatusCode::BAD_REQUEST;
-            return bad_request;
+            return error_response(Error::BadRequest);
         },
     };
-    let sync = unsafe {
-        (*m.lock().await).get_mut().as_mut().unwrap()
+    match sync.define_role(req).await {
+        Ok(role) => Response::new(serde_json::to_string(&role).unwrap().into()),
+        Err(err) => error_response(err),
+    }
+}
+
+async fn handle_permission(
+    sync: Arc<Synchronizer>,
+    nonce: Nonce,
+) -> Response<Body> {
+    match sync.permission(nonce).await {
+        Ok(perm) => Response::new(serde_json::to_string(&perm).unwrap().into()),
+        Err(err) => error_response(err),
+    }
+}
+
+async fn handle_delete_perm(
+    sync: Arc<Synchronizer>,
+    req: Request<Body>,
+) -> Response<Body> {
+    let bytes = to_bytes(req.into_body()).await;
+    let perm: Permission = match from_json(&bytes) {
+        Some(res) => res,
+        None => {
+            return error_response(Error::BadRequest);
+        },
     };
-    match sync.action(req.perm, req.action).await {
+    match sync.delete_permission(perm).await {
         Ok(_) => Response::default(),
-        _ => {
-            let mut unauthorized = Response::default();
-            *unauthorized.status_mut() = StatusCode::UNAUTHORIZED;
-            unauthorized
+        Err(err) => error_response(err),
+    }
+}
+
+async fn handle_action(
+    sync: Arc<Synchronizer>,
+    req: Request<Body>,
+) -> Response<Body> {
+    let bytes = to_bytes(req.into_body()).await;
+    let req: AuthorizeRequest = match from_json(&bytes) {
+        Some(res) => res,
+        None => {
+            return error_response(Error::BadRequest);
+        },
+    };
+    let res = sync.action(
+        req.perm,
+        req.action,
+        req.attributes,
+        req.proof,
+    ).await;
+    match res {
+        Ok(_) => Response::default(),
+        Err(err) => error_response(err),
+    }
+}
+
+async fn handle_actions(
+    sync: Arc<Synchronizer>,
+    req: Request<Body>,
+) -> Response<Body> {
+    let bytes = to_bytes(req.into_body()).await;
+    let req: ActionsRequest = match from_json(&bytes) {
+        Some(res) => res,
+        None => {
+            return error_response(Error::BadRequest);
         },
+    };
+    match sync.actions(req).await {
+        Ok(decisions) => {
+            let decisions: Vec<Vec<Decision>> = decisions.into_iter()
+                .map(|row| row.into_iter().map(Decision::from).collect())
+                .collect();
+            Response::new(serde_json::to_string(&decisions).unwrap().into())
+        },
+        Err(err) => error_response(err),
     }
-}    
+}
+
+async fn handle_window_status(
+    sync: Arc<Synchronizer>,
+) -> Response<Body> {
+    let status = sync.window_status().await;
+    Response::new(serde_json::to_string(&status).unwrap().into())
+}
+
+async fn handle_close_window(
+    sync: Arc<Synchronizer>,
+) -> Response<Body> {
+    match sync.close_window().await {
+        Ok(_) => {
+            let status = sync.window_status().await;
+            Response::new(serde_json::to_string(&status).unwrap().into())
+        },
+        Err(err) => error_response(err),
+    }
+}
+
+async fn handle_rotate(
+    sync: Arc<Synchronizer>,
+) -> Response<Body> {
+    match sync.rotate().await {
+        Ok(checkpoint) => {
+            Response::new(serde_json::to_string(&checkpoint).unwrap().into())
+        },
+        Err(err) => error_response(err),
+    }
+}
+
+async fn handle_checkpoint(
+    sync: Arc<Synchronizer>,
+) -> Response<Body> {
+    let checkpoint = sync.checkpoint().await;
+    Response::new(serde_json::to_string(&checkpoint).unwrap().into())
+}
+
+async fn handle_checkpoints(
+    sync: Arc<Synchronizer>,
+) -> Response<Body> {
+    match sync.checkpoints().await {
+        Ok(checkpoints) => {
+            Response::new(serde_json::to_string(&checkpoints).unwrap().into())
+        },
+        Err(err) => error_response(err),
+    }
+}
+
+async fn handle_signing_key(
+    sync: Arc<Synchronizer>,
+) -> Response<Body> {
+    let key = sync.signing_key();
+    Response::new(serde_json::to_string(key).unwrap().into())
+}
+
+async fn handle_witness(
+    sync: Arc<Synchronizer>,
+    nonce: Nonce,
+) -> Response<Body> {
+    match sync.witness(nonce).await {
+        Ok(witness) => {
+            Response::new(serde_json::to_string(&witness).unwrap().into())
+        },
+        Err(err) => error_response(err),
+    }
+}
 
 async fn handle(
-    m: Arc<Mutex<AtomicPtr<Synchronizer>>>,
+    sync: Arc<Synchronizer>,
     req: Request<Body>,
-) -> Result<Response<Body>, Error> {
+) -> Result<Response<Body>, HyperError> {
     match (req.method(), req.uri().path()) {
-        (&Method::POST, "/permission") => Ok(handle_add_perm(m, req).await),
-        (&Method::PUT, "/permission") => Ok(handle_update_perm(m, req).await),
-        (&Method::POST, "/action") => Ok(handle_action(m, req).await),
+        (&Method::POST, "/permission") => Ok(handle_add_perm(sync, req).await),
+        (&Method::PUT, "/permission") => {
+            Ok(handle_update_perm(sync, req).await)
+        },
+        (&Method::DELETE, "/permission") => {
+            Ok(handle_delete_perm(sync, req).await)
+        },
+        (&Method::POST, "/permissions") => {
+            Ok(handle_add_perms(sync, req).await)
+        },
+        (&Method::PUT, "/permissions") => {
+            Ok(handle_update_perms(sync, req).await)
+        },
+        (&Method::POST, "/delegate") => Ok(handle_delegate(sync, req).await),
+        (&Method::POST, "/action") => Ok(handle_action(sync, req).await),
+        (&Method::POST, "/actions") => Ok(handle_actions(sync, req).await),
+        (&Method::GET, "/window") => Ok(handle_window_status(sync).await),
+        (&Method::POST, "/window") => Ok(handle_close_window(sync).await),
+        (&Method::POST, "/rotate") => Ok(handle_rotate(sync).await),
+        (&Method::GET, "/roles") => Ok(handle_roles(sync).await),
+        (&Method::PUT, "/role") => Ok(handle_define_role(sync, req).await),
+        (&Method::GET, "/checkpoint") => Ok(handle_checkpoint(sync).await),
+        (&Method::GET, "/checkpoints") => Ok(handle_checkpoints(sync).await),
+        (&Method::GET, "/signing-key") => Ok(handle_signing_key(sync).await),
         _ => {
+            let parts: Vec<&str> = req.uri().path().split('/').collect();
+            if req.method() == Method::GET
+                && parts.len() == 3
+                && parts[0].is_empty()
+                && parts[1] == "witness" {
+                if let Ok(nonce) = parts[2].parse::<u64>() {
+                    return Ok(handle_witness(sync, nonce.into()).await);
+                }
+            }
+            if req.method() == Method::GET
+                && parts.len() == 3
+                && parts[0].is_empty()
+                && parts[1] == "permission" {
+                if let Ok(nonce) = parts[2].parse::<u64>() {
+                    return Ok(handle_permission(sync, nonce.into()).await);
+                }
+            }
             let mut not_found = Response::default();
             *not_found.status_mut() = StatusCode::NOT_FOUND;
             Ok(not_found)
@@ -119,21 +344,69 @@ async fn handle(
     }
 }
 
+/// Whether a request may only be made by operators holding the Authority's
+/// secret.
+fn is_admin<B>(req: &Request<B>) -> bool {
+    let path = match tenant::split(req.uri().path()) {
+        Some((_, path)) => path,
+        None => req.uri().path(),
+    };
+    // Redefining a role changes the actions of every Permission granted
+    // it, so it is as privileged as rotating the key.
+    matches!(
+        (req.method(), path),
+        (&Method::POST, "/window")
+            | (&Method::POST, "/rotate")
+            | (&Method::PUT, "/role"),
+    )
+}
+
+async fn route(
+    tenants: Arc<Tenants<Synchronizer>>,
+    admins: Arc<Authenticator>,
+    req: Request<Body>,
+) -> Result<Response<Body>, HyperError> {
+    // Authenticate administrative requests before they are routed, since
+    // operators sign the path with the tenant prefix.
+    let req = match is_admin(&req) {
+        true => match admins.authenticate(req).await {
+            Ok(req) => req,
+            Err(err) => {
+                return Ok(error_response(err));
+            },
+        },
+        false => req,
+    };
+    match tenants.route(req) {
+        Ok((sync, req)) => handle(sync, req).await,
+        Err(err) => Ok(error_response(err)),
+    }
+}
+
 #[tokio::main]
 async fn main() {
-    let mut sync = Synchronizer::new().await.unwrap();
-    let m = Arc::new(Mutex::new(AtomicPtr::new(&mut sync)));
-    let make_service = make_service_fn(move |_| {
-        let m = Arc::clone(&m);
-        async move {
-            Ok::<_, Infallible>(service_fn(move |req| {
-                handle(Arc::clone(&m), req)
-            }))
-        }
-    });
-    let addr = SYNCHRONIZER_ADDR.parse().unwrap();
-    let server = Server::bind(&addr).serve(make_service);
-    let sync_future = sync.sync();
-    server.await.unwrap();
-    sync_future.await.unwrap().unwrap();
+    let env = env_logger::Env::default().default_filter_or("warn");
+    env_logger::Builder::from_env(env).init();
+    let config = Config::load(&["authority_secret", "worker_secret"]);
+    let credentials = Credentials::new(&config.authority_secret).unwrap();
+    let admins = Arc::new(Authenticator::new(credentials));
+    let mut tenants = Tenants::new(Synchronizer::new(&config).await.unwrap());
+    for id in config.tenant_ids() {
+        let sync = Synchronizer::for_tenant(&config, Some(&id)).await.unwrap();
+        tenants.insert(&id, sync);
+    }
+    // Every tenant closes its own update windows.
+    let mut sync_futures = vec![tenants.default_tenant().sync()];
+    for (_, sync) in tenants.iter() {
+        sync_futures.push(Arc::clone(sync).sync());
+    }
+    let tenants = Arc::new(tenants);
+    let tls = config.synchronizer_tls().unwrap();
+    let addr = config.synchronizer_addr.parse().unwrap();
+    serve(addr, tls, move |req| {
+        route(Arc::clone(&tenants), Arc::clone(&admins), req)
+    }).await.unwrap();
+    for sync_future in sync_futures {
+        sync_future.await.unwrap();
+    }
 }
@@ -1,176 +1,368 @@
 use compauth::{
-    constant::WORKER_ADDR,
+    checkpoint::{SignedCheckpoint, parse_public_key},
+    config::Config,
+    error::Error,
+    peer::{Authenticator, Credentials},
     permission::{Nonce, Permission},
-    request::UpdateResponse,
-    util::from_bytes,
+    request::{
+        BatchUpdateResponse,
+        DeleteResponse,
+        KeyRequest,
+        UpdateResponse,
+    },
+    role::Role,
+    tenant::Tenants,
+    tls::serve,
+    util::{error_response, from_bytes, now_millis},
     worker::Worker,
 };
-use gmp::mpz::Mpz;
 use hyper::{
-    Body, Error, Method, Request, Response, Server, StatusCode,
+    Body, Error as HyperError, Method, Request, Response, StatusCode,
     body::to_bytes,
-    service::{make_service_fn, service_fn},
 };
-use tokio::sync::Mutex;
-use std::{convert::Infallible, sync::{Arc, atomic::AtomicPtr}};
+use std::sync::Arc;
 
 async fn handle_key(
-    m: Arc<Mutex<AtomicPtr<Worker>>>,
+    worker: Arc<Worker>,
     req: Request<Body>,
 ) -> Response<Body> {
     let bytes = to_bytes(req.into_body()).await;
-    let key: Mpz = match from_bytes(&bytes) {
+    let req: KeyRequest = match from_bytes(&bytes) {
         Some(res) => res,
         None => {
-            let mut bad_request = Response::default();
-            *bad_request.status_mut() = StatusCode::BAD_REQUEST;
-            return bad_request;
+            return error_response(Error::BadRequest);
         },
     };
-    let worker = unsafe {
-        (*m.lock().await).get_mut().as_mut().unwrap()
-    };
-    match worker.set_key(key).await {
+    match worker.set_key(req).await {
         Ok(_) => Response::default(),
-        _ => {
-            let mut forbidden = Response::default();
-            *forbidden.status_mut() = StatusCode::FORBIDDEN;
-            forbidden
-        },
+        Err(err) => error_response(err),
     }
 }
 
 async fn handle_add_perm(
-    m: Arc<Mutex<AtomicPtr<Worker>>>,
+    worker: Arc<Worker>,
     req: Request<Body>,
 ) -> Response<Body> {
     let bytes = to_bytes(req.into_body()).await;
     let perm: Permission = match from_bytes(&bytes) {
         Some(res) => res,
         None => {
-            let mut bad_request = Response::default();
-            *bad_request.status_mut() = StatusCode::BAD_REQUEST;
-            return bad_request;
+            return error_response(Error::BadRequest);
         },
     };
-    let worker = unsafe {
-        (*m.lock().await).get_mut().as_mut().unwrap()
-    };
     match worker.add_permission(perm).await {
         Ok(_) => Response::default(),
-        _ => {
-            let mut forbidden = Response::default();
-            *forbidden.status_mut() = StatusCode::FORBIDDEN;
-            forbidden
-        },
+        Err(err) => error_response(err),
     }
 }
 
 async fn handle_update_perm(
-    m: Arc<Mutex<AtomicPtr<Worker>>>,
+    worker: Arc<Worker>,
     req: Request<Body>,
 ) -> Response<Body> {
     let bytes = to_bytes(req.into_body()).await;
     let res: UpdateResponse = match from_bytes(&bytes) {
         Some(res) => res,
         None => {
-            let mut bad_request = Response::default();
-            *bad_request.status_mut() = StatusCode::BAD_REQUEST;
-            return bad_request;
+            return error_response(Error::BadRequest);
         },
     };
-    let worker = unsafe {
-        (*m.lock().await).get_mut().as_mut().unwrap()
-    };
     match worker.update_permission(res).await {
         Ok(_) => Response::default(),
-        _ => {
-            let mut forbidden = Response::default();
-            *forbidden.status_mut() = StatusCode::FORBIDDEN;
-            forbidden
+        Err(err) => error_response(err),
+    }
+}
+
+async fn handle_add_perms(
+    worker: Arc<Worker>,
+    req: Request<Body>,
+) -> Response<Body> {
+    let bytes = to_bytes(req.into_body()).await;
+    let perms: Vec<Permission> = match from_bytes(&bytes) {
+        Some(res) => res,
+        None => {
+            return error_response(Error::BadRequest);
+        },
+    };
+    match worker.add_permissions(perms).await {
+        Ok(_) => Response::default(),
+        Err(err) => error_response(err),
+    }
+}
+
+async fn handle_update_perms(
+    worker: Arc<Worker>,
+    req: Request<Body>,
+) -> Response<Body> {
+    let bytes = to_bytes(req.into_body()).await;
+    let res: BatchUpdateResponse = match from_bytes(&bytes) {
+        Some(res) => res,
+        None => {
+            return error_response(Error::BadRequest);
         },
+    };
+    match worker.update_permissions(res).await {
+        Ok(_) => Response::default(),
+        Err(err) => error_response(err),
+    }
+}
+
+async fn handle_delete_perm(
+    worker: Arc<Worker>,
+    req: Request<Body>,
+) -> Response<Body> {
+    let bytes = to_bytes(req.into_body()).await;
+    let res: DeleteResponse = match from_bytes(&bytes) {
+        Some(res) => res,
+        None => {
+            return error_response(Error::BadRequest);
+        },
+    };
+    match worker.delete_permission(res).await {
+        Ok(_) => Response::default(),
+        Err(err) => error_response(err),
     }
 }
 
 async fn handle_witness(
-    m: Arc<Mutex<AtomicPtr<Worker>>>,
+    worker: Arc<Worker>,
     nonce: Nonce,
 ) -> Response<Body> {
-    let worker = unsafe {
-        (*m.lock().await).get_mut().as_mut().unwrap()
-    };
     match worker.witness(nonce).await {
         Ok(res) => match res {
             Some(witness) => {
                 let resp = velocypack::to_bytes(&witness).unwrap();
                 Response::new(resp.into())
             },
-            None => {
-                let mut unauthorized = Response::default();
-                *unauthorized.status_mut() = StatusCode::UNAUTHORIZED;
-                unauthorized
+            None => error_response(Error::UnknownPermission),
+        },
+        Err(err) => error_response(err),
+    }
+}
+
+async fn handle_witnesses(
+    worker: Arc<Worker>,
+    req: Request<Body>,
+) -> Response<Body> {
+    let bytes = to_bytes(req.into_body()).await;
+    let nonces: Vec<Nonce> = match from_bytes(&bytes) {
+        Some(res) => res,
+        None => {
+            return error_response(Error::BadRequest);
+        },
+    };
+    match worker.witnesses(nonces).await {
+        Ok(witnesses) => {
+            let resp = velocypack::to_bytes(&witnesses).unwrap();
+            Response::new(resp.into())
+        },
+        Err(err) => error_response(err),
+    }
+}
+
+async fn handle_permission(
+    worker: Arc<Worker>,
+    nonce: Nonce,
+) -> Response<Body> {
+    match worker.permission(nonce).await {
+        Ok(res) => match res {
+            Some(perm) => {
+                let resp = velocypack::to_bytes(&perm).unwrap();
+                Response::new(resp.into())
             },
+            None => error_response(Error::UnknownPermission),
         },
-        _ => {
-            let mut forbidden = Response::default();
-            *forbidden.status_mut() = StatusCode::FORBIDDEN;
-            forbidden
+        Err(err) => error_response(err),
+    }
+}
+
+async fn handle_outdated(
+    worker: Arc<Worker>,
+    req: Request<Body>,
+) -> Response<Body> {
+    let bytes = to_bytes(req.into_body()).await;
+    let roles: Vec<Role> = match from_bytes(&bytes) {
+        Some(res) => res,
+        None => {
+            return error_response(Error::BadRequest);
+        },
+    };
+    match worker.outdated(roles).await {
+        Ok(perms) => {
+            let resp = velocypack::to_bytes(&perms).unwrap();
+            Response::new(resp.into())
+        },
+        Err(err) => error_response(err),
+    }
+}
+
+async fn handle_expired(
+    worker: Arc<Worker>,
+) -> Response<Body> {
+    match worker.expired(now_millis()).await {
+        Ok(perms) => {
+            let resp = velocypack::to_bytes(&perms).unwrap();
+            Response::new(resp.into())
         },
+        Err(err) => error_response(err),
+    }
+}
+
+async fn handle_delegated(
+    worker: Arc<Worker>,
+) -> Response<Body> {
+    match worker.delegated().await {
+        Ok(perms) => {
+            let resp = velocypack::to_bytes(&perms).unwrap();
+            Response::new(resp.into())
+        },
+        Err(err) => error_response(err),
     }
 }
 
 async fn handle_update(
-    m: Arc<Mutex<AtomicPtr<Worker>>>,
+    worker: Arc<Worker>,
 ) -> Response<Body> {
-    let worker = unsafe {
-        (*m.lock().await).get_mut().as_mut().unwrap()
-    };
     match worker.update().await {
         Ok(_) => Response::default(),
-        _ => {
-            let mut forbidden = Response::default();
-            *forbidden.status_mut() = StatusCode::FORBIDDEN;
-            forbidden
-        },
+        Err(err) => error_response(err),
     }
 }
 
 async fn handle_sync(
-    m: Arc<Mutex<AtomicPtr<Worker>>>,
+    worker: Arc<Worker>,
 ) -> Response<Body> {
-    let worker = unsafe {
-        (*m.lock().await).get_mut().as_mut().unwrap()
+    match worker.sync().await {
+        Ok(_) => Response::default(),
+        Err(err) => error_response(err),
+    }
+}
+
+async fn handle_permissions(
+    worker: Arc<Worker>,
+) -> Response<Body> {
+    match worker.permissions().await {
+        Ok(perms) => {
+            let resp = velocypack::to_bytes(&perms).unwrap();
+            Response::new(resp.into())
+        },
+        Err(err) => error_response(err),
+    }
+}
+
+async fn handle_begin_rotation(
+    worker: Arc<Worker>,
+    req: Request<Body>,
+) -> Response<Body> {
+    let bytes = to_bytes(req.into_body()).await;
+    let checkpoint: SignedCheckpoint = match from_bytes(&bytes) {
+        Some(res) => res,
+        None => {
+            return error_response(Error::BadRequest);
+        },
     };
-    worker.sync().await;
+    match worker.begin_rotation(checkpoint).await {
+        Ok(_) => Response::default(),
+        Err(err) => error_response(err),
+    }
+}
+
+async fn handle_rotation_status(
+    worker: Arc<Worker>,
+) -> Response<Body> {
+    let status = worker.rotation_status().await;
+    let resp = velocypack::to_bytes(&status).unwrap();
+    Response::new(resp.into())
+}
+
+async fn handle_commit_rotation(
+    worker: Arc<Worker>,
+) -> Response<Body> {
+    match worker.commit_rotation().await {
+        Ok(_) => Response::default(),
+        Err(err) => error_response(err),
+    }
+}
+
+async fn handle_abort_rotation(
+    worker: Arc<Worker>,
+) -> Response<Body> {
+    worker.abort_rotation().await;
     Response::default()
 }
 
 async fn handle(
-    m: Arc<Mutex<AtomicPtr<Worker>>>,
+    workers: Arc<Tenants<Worker>>,
+    peers: Arc<Authenticator>,
     req: Request<Body>,
-) -> Result<Response<Body>, Error> {
+) -> Result<Response<Body>, HyperError> {
+    // Only serve peers holding the Worker's secret.
+    let req = match peers.authenticate(req).await {
+        Ok(req) => req,
+        Err(err) => {
+            return Ok(error_response(err));
+        },
+    };
+    // Serve the request with the Worker of its tenant.
+    let (worker, req) = match workers.route(req) {
+        Ok(routed) => routed,
+        Err(err) => {
+            return Ok(error_response(err));
+        },
+    };
     match (req.method(), req.uri().path()) {
-        (&Method::POST, "/key") => Ok(handle_key(m, req).await),
-        (&Method::POST, "/permission") => Ok(handle_add_perm(m, req).await),
-        (&Method::PUT, "/permission") => Ok(handle_update_perm(m, req).await),
-        (&Method::GET, "/update") => Ok(handle_update(m).await),
-        (&Method::GET, "/sync") => Ok(handle_sync(m).await),
+        (&Method::POST, "/key") => Ok(handle_key(worker, req).await),
+        (&Method::POST, "/permission") => {
+            Ok(handle_add_perm(worker, req).await)
+        },
+        (&Method::PUT, "/permission") => {
+            Ok(handle_update_perm(worker, req).await)
+        },
+        (&Method::DELETE, "/permission") => {
+            Ok(handle_delete_perm(worker, req).await)
+        },
+        (&Method::POST, "/permissions") => {
+            Ok(handle_add_perms(worker, req).await)
+        },
+        (&Method::PUT, "/permissions") => {
+            Ok(handle_update_perms(worker, req).await)
+        },
+        (&Method::POST, "/witnesses") => {
+            Ok(handle_witnesses(worker, req).await)
+        },
+        (&Method::GET, "/update") => Ok(handle_update(worker).await),
+        (&Method::GET, "/sync") => Ok(handle_sync(worker).await),
+        (&Method::GET, "/expired") => Ok(handle_expired(worker).await),
+        (&Method::GET, "/delegated") => Ok(handle_delegated(worker).await),
+        (&Method::POST, "/outdated") => {
+            Ok(handle_outdated(worker, req).await)
+        },
+        (&Method::GET, "/permissions") => {
+            Ok(handle_permissions(worker).await)
+        },
+        (&Method::POST, "/rotate") => {
+            Ok(handle_begin_rotation(worker, req).await)
+        },
+        (&Method::GET, "/rotate") => Ok(handle_rotation_status(worker).await),
+        (&Method::DELETE, "/rotate") => {
+            Ok(handle_abort_rotation(worker).await)
+        },
+        (&Method::POST, "/rotate/commit") => {
+            Ok(handle_commit_rotation(worker).await)
+        },
         _ => {
             let path_bytes = req.uri().path().as_bytes();
-            if path_bytes.len() > 0 && path_bytes[0] == b'/' {
-                let parts: Vec<&str> = req.uri().path().split("/").collect();
-                if parts.len() == 3 {
-                    match parts[1] {
-                        "witness" => match parts[2].parse::<u64>() {
-                            Ok(nonce) => {
-                                return Ok(handle_witness(
-                                    m,
-                                    nonce.into()
-                                ).await);
-                            },
-                            _ => {},
-                        },
-                        _ => {},
+            if !path_bytes.is_empty() && path_bytes[0] == b'/' {
+                let parts: Vec<&str> = req.uri().path().split('/').collect();
+                if parts.len() == 3 && parts[1] == "witness" {
+                    if let Ok(nonce) = parts[2].parse::<u64>() {
+                        return Ok(handle_witness(worker, nonce.into()).await);
+                    }
+                }
+                if parts.len() == 3 && parts[1] == "permission" {
+                    if let Ok(nonce) = parts[2].parse::<u64>() {
+                        let nonce = nonce.into();
+                        return Ok(handle_permission(worker, nonce).await);
                     }
                 }
             }
@@ -181,19 +373,26 @@ async fn handle(
     }
 }
 
+fn open_worker(config: &Config) -> Worker {
+    let signing_key = parse_public_key(&config.authority_signing_key).unwrap();
+    Worker::open(&config.worker_data_dir, Some(signing_key)).unwrap()
+}
+
 #[tokio::main]
 async fn main() {
-    let mut worker = Worker::new();
-    let m = Arc::new(Mutex::new(AtomicPtr::new(&mut worker)));
-    let make_service = make_service_fn(move |_| {
-        let m = Arc::clone(&m);
-        async move {
-            Ok::<_, Infallible>(service_fn(move |req| {
-                handle(Arc::clone(&m), req)
-            }))
-        }
-    });
-    let addr = WORKER_ADDR.parse().unwrap();
-    let server = Server::bind(&addr).serve(make_service);
-    server.await.unwrap();
+    let env = env_logger::Env::default().default_filter_or("warn");
+    env_logger::Builder::from_env(env).init();
+    let config = Config::load(&["worker_secret", "authority_signing_key"]);
+    let credentials = Credentials::new(&config.worker_secret).unwrap();
+    let peers = Arc::new(Authenticator::new(credentials));
+    let mut workers = Tenants::new(open_worker(&config));
+    for id in config.tenant_ids() {
+        workers.insert(&id, open_worker(&config.for_tenant(&id)));
+    }
+    let workers = Arc::new(workers);
+    let tls = config.worker_tls().unwrap();
+    let addr = config.worker_addr.parse().unwrap();
+    serve(addr, tls, move |req| {
+        handle(Arc::clone(&workers), Arc::clone(&peers), req)
+    }).await.unwrap();
 }
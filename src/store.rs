@@ -0,0 +1,265 @@
+use serde::{Serialize, de::DeserializeOwned};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    marker::PhantomData,
+    path::{Path, PathBuf},
+};
+use crate::error::Error;
+
+/// Describe a failed storage operation on a path.
+fn storage_error<E: std::fmt::Display>(
+    action: &str,
+    path: &Path,
+    err: E,
+) -> Error {
+    Error::Storage(format!("{} {}: {}", action, path.display(), err))
+}
+
+/// Read a value that was previously written with `save`.
+///
+/// Returns `None` if nothing has been saved at the path yet.
+pub fn load<T: DeserializeOwned>(
+    path: &Path,
+) -> Result<Option<T>, Error> {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(None);
+        },
+        Err(e) => {
+            return Err(storage_error("could not read", path, e));
+        },
+    };
+    match serde_json::from_slice(&bytes) {
+        Ok(value) => Ok(Some(value)),
+        Err(e) => Err(storage_error("could not decode", path, e)),
+    }
+}
+
+/// Atomically replace the value stored at a path.
+///
+/// The value is written to a temporary file which is flushed to disk before
+/// being renamed over the destination, so a crash leaves either the old or
+/// the new value in place but never a partial one.
+pub fn save<T: Serialize>(
+    path: &Path,
+    value: &T,
+) -> Result<(), Error> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    let bytes = serde_json::to_vec(value).unwrap();
+    let mut file = match File::create(&tmp) {
+        Ok(file) => file,
+        Err(e) => {
+            return Err(storage_error("could not create", &tmp, e));
+        },
+    };
+    if let Err(e) = file.write_all(&bytes).and_then(|_| file.sync_all()) {
+        return Err(storage_error("could not write", &tmp, e));
+    }
+    if let Err(e) = fs::rename(&tmp, path) {
+        return Err(storage_error("could not replace", path, e));
+    }
+    Ok(())
+}
+
+/// Create a file that is only readable and writable by its owner.
+///
+/// Used for files holding private key material.
+pub fn save_private<T: Serialize>(
+    path: &Path,
+    value: &T,
+) -> Result<(), Error> {
+    let bytes = serde_json::to_vec(value).unwrap();
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = match options.open(path) {
+        Ok(file) => file,
+        Err(e) => {
+            return Err(storage_error("could not create", path, e));
+        },
+    };
+    if let Err(e) = file.write_all(&bytes).and_then(|_| file.sync_all()) {
+        return Err(storage_error("could not write", path, e));
+    }
+    Ok(())
+}
+
+/// Atomically replace a file that is only readable and writable by its
+/// owner.
+///
+/// Used to replace files holding private key material, e.g. when the key is
+/// rotated.
+pub fn replace_private<T: Serialize>(
+    path: &Path,
+    value: &T,
+) -> Result<(), Error> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    // Remove a temporary file left behind by a crash so that it can be
+    // created with the right permissions.
+    let _ = fs::remove_file(&tmp);
+    save_private(&tmp, value)?;
+    if let Err(e) = fs::rename(&tmp, path) {
+        return Err(storage_error("could not replace", path, e));
+    }
+    Ok(())
+}
+
+/// An append-only log of records.
+///
+/// Each record is stored as a single line of JSON and flushed to disk before
+/// `append` returns. A trailing record that was only partially written when
+/// the process died is discarded when the log is reopened.
+pub struct Log<T> {
+    path: PathBuf,
+    file: File,
+    record: PhantomData<T>,
+}
+
+impl<T: Serialize + DeserializeOwned> Log<T> {
+
+    /// Open the log at a path, creating it if it does not exist, and return
+    /// it along with the records it already contains.
+    pub fn open(path: &Path) -> Result<(Self, Vec<T>), Error> {
+        let mut records = Vec::new();
+        let mut valid = 0;
+        if let Ok(file) = File::open(path) {
+            let mut reader = BufReader::new(file);
+            let mut line = Vec::new();
+            loop {
+                line.clear();
+                match reader.read_until(b'\n', &mut line) {
+                    Ok(0) => break,
+                    Ok(n) => {
+                        // Stop at the first torn or undecodable record.
+                        if line[n - 1] != b'\n' {
+                            break;
+                        }
+                        match serde_json::from_slice(&line[..n - 1]) {
+                            Ok(record) => records.push(record),
+                            Err(_) => break,
+                        }
+                        valid += n as u64;
+                    },
+                    Err(e) => {
+                        return Err(storage_error("could not read", path, e));
+                    },
+                }
+            }
+        }
+        let file = match OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path) {
+            Ok(file) => file,
+            Err(e) => {
+                return Err(storage_error("could not open", path, e));
+            },
+        };
+        // Drop anything after the last complete record so that new records
+        // are not appended to a torn one.
+        if let Err(e) = file.set_len(valid) {
+            return Err(storage_error("could not truncate", path, e));
+        }
+        Ok((
+            Log { path: path.to_owned(), file, record: PhantomData },
+            records,
+        ))
+    }
+
+    /// Durably append a record to the log.
+    pub fn append(&mut self, record: &T) -> Result<(), Error> {
+        let mut bytes = serde_json::to_vec(record).unwrap();
+        bytes.push(b'\n');
+        let file = &mut self.file;
+        match file.write_all(&bytes).and_then(|_| file.sync_data()) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(storage_error("could not append to", &self.path, e)),
+        }
+    }
+
+    /// Remove every record from the log.
+    pub fn clear(&mut self) -> Result<(), Error> {
+        match self.file.set_len(0).and_then(|_| self.file.sync_data()) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(storage_error("could not clear", &self.path, e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Return a path in a fresh temporary directory.
+    fn temp_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "compauth-store-{}-{}",
+            std::process::id(),
+            rand::random::<u64>(),
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        dir.join(name)
+    }
+
+    #[test]
+    fn log_discards_torn_record() {
+        let path = temp_path("wal.jsonl");
+        let (mut log, records) = Log::<Vec<u32>>::open(&path).unwrap();
+        assert!(records.is_empty());
+        log.append(&vec![1]).unwrap();
+        log.append(&vec![2, 3]).unwrap();
+        drop(log);
+        // Simulate a crash in the middle of appending a record.
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"[4,").unwrap();
+        drop(file);
+        let (mut log, records) = Log::<Vec<u32>>::open(&path).unwrap();
+        assert_eq!(records, vec![vec![1], vec![2, 3]]);
+        // A record appended after recovery is not merged with the torn one.
+        log.append(&vec![5]).unwrap();
+        drop(log);
+        let (_, records) = Log::<Vec<u32>>::open(&path).unwrap();
+        assert_eq!(records, vec![vec![1], vec![2, 3], vec![5]]);
+    }
+
+    #[test]
+    fn log_stops_at_undecodable_record() {
+        let path = temp_path("wal.jsonl");
+        fs::write(&path, b"[1]\nnot json\n[2]\n").unwrap();
+        let (log, records) = Log::<Vec<u32>>::open(&path).unwrap();
+        assert_eq!(records, vec![vec![1]]);
+        drop(log);
+        assert_eq!(fs::read(&path).unwrap(), b"[1]\n");
+    }
+
+    #[test]
+    fn log_clear_removes_records() {
+        let path = temp_path("wal.jsonl");
+        let (mut log, _) = Log::<u32>::open(&path).unwrap();
+        log.append(&1).unwrap();
+        log.clear().unwrap();
+        log.append(&2).unwrap();
+        drop(log);
+        let (_, records) = Log::<u32>::open(&path).unwrap();
+        assert_eq!(records, vec![2]);
+    }
+
+    #[test]
+    fn save_replaces_value() {
+        let path = temp_path("value.json");
+        assert_eq!(load::<u32>(&path).unwrap(), None);
+        save(&path, &1u32).unwrap();
+        save(&path, &2u32).unwrap();
+        assert_eq!(load::<u32>(&path).unwrap(), Some(2));
+    }
+}
@@ -0,0 +1,301 @@
+use ed25519_dalek::{Signer as _, Signature, SigningKey};
+use serde::{Serialize, Deserialize};
+use std::{collections::HashMap, sync::Mutex};
+use crate::{
+    checkpoint::{format_public_key, parse_public_key, PublicKey},
+    condition::Attributes,
+    constant::MAX_CLOCK_SKEW_MILLIS,
+    error::Error,
+    permission::Permission,
+    util::now_millis,
+};
+
+/// Proof that an action is requested by the subject a Permission is bound
+/// to.
+///
+/// The subject signs the audience, the Permission, the action, the request
+/// attributes, the time and a random value with its Ed25519 key. Proofs are
+/// only accepted by checkers of their audience, within
+/// `MAX_CLOCK_SKEW_MILLIS` of being made and only once, so a captured proof
+/// cannot be replayed, presented to another deployment or tenant, or used
+/// with other attributes.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Proof {
+
+    /// When the proof was made, in milliseconds since the Unix epoch.
+    pub timestamp: u64,
+
+    /// A hex encoded random value that makes every proof unique.
+    pub nonce: String,
+
+    /// The hex encoded Ed25519 signature of the challenge.
+    pub signature: String,
+}
+
+/// The message a subject signs to prove that it requests an action.
+#[derive(Serialize)]
+struct Challenge<'a> {
+    audience: &'a str,
+    perm: &'a Permission,
+    action: &'a str,
+    attributes: &'a Attributes,
+    timestamp: u64,
+    nonce: &'a str,
+}
+
+impl Challenge<'_> {
+
+    /// The bytes covered by the signature.
+    fn to_bytes(&self) -> Vec<u8> {
+        velocypack::to_bytes(self).unwrap()
+    }
+}
+
+/// The key a subject proves its identity with.
+pub struct SubjectKey {
+    key: SigningKey,
+}
+
+impl SubjectKey {
+
+    /// Generate a random key.
+    pub fn generate() -> Self {
+        SubjectKey {
+            key: SigningKey::generate(&mut rand::rngs::OsRng),
+        }
+    }
+
+    /// Restore a key from its hex encoded secret.
+    pub fn from_secret(secret: &str) -> Result<Self, Error> {
+        let bytes: [u8; 32] = match hex::decode(secret) {
+            Ok(bytes) => match bytes.try_into() {
+                Ok(bytes) => bytes,
+                Err(_) => {
+                    return Err(Error::InvalidSubject);
+                },
+            },
+            Err(_) => {
+                return Err(Error::InvalidSubject);
+            },
+        };
+        Ok(SubjectKey {
+            key: SigningKey::from_bytes(&bytes),
+        })
+    }
+
+    /// Return the hex encoded secret.
+    pub fn secret(&self) -> String {
+        hex::encode(self.key.to_bytes())
+    }
+
+    /// Return the hex encoded public key, which Permissions are bound to.
+    pub fn subject(&self) -> String {
+        format_public_key(&self.key.verifying_key())
+    }
+
+    /// Prove that the subject requests an action with a Permission and
+    /// request attributes.
+    ///
+    /// The audience is the hex encoded signing key of the Authority the
+    /// proof is presented to, as served by the Synchronizer at
+    /// `GET /signing-key`.
+    pub fn prove(
+        &self,
+        audience: &str,
+        perm: &Permission,
+        action: &str,
+        attributes: &Attributes,
+    ) -> Proof {
+        let timestamp = now_millis();
+        let nonce = hex::encode(rand::random::<[u8; 16]>());
+        let challenge = Challenge {
+            audience,
+            perm,
+            action,
+            attributes,
+            timestamp,
+            nonce: &nonce,
+        };
+        let signature = self.key.sign(&challenge.to_bytes());
+        Proof {
+            timestamp,
+            nonce,
+            signature: hex::encode(signature.to_bytes()),
+        }
+    }
+}
+
+/// Ensure a subject is a hex encoded Ed25519 public key.
+pub fn validate(subject: &str) -> Result<(), Error> {
+    match parse_public_key(subject) {
+        Ok(_) => Ok(()),
+        Err(_) => Err(Error::InvalidSubject),
+    }
+}
+
+/// Checks that actions with Permissions bound to a subject are requested by
+/// that subject.
+pub struct ProofChecker {
+
+    /// The audience proofs must be made for.
+    audience: String,
+
+    /// The signatures accepted recently, with the time at which they expire.
+    seen: Mutex<HashMap<String, u64>>,
+}
+
+impl ProofChecker {
+
+    /// Create a ProofChecker for the Authority whose signing key is given,
+    /// which is the audience proofs must be made for.
+    pub fn new(signing_key: &PublicKey) -> Self {
+        ProofChecker {
+            audience: format_public_key(signing_key),
+            seen: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Check the proof of the subject requesting an action with request
+    /// attributes.
+    ///
+    /// Permissions that are not bound to a subject need no proof.
+    pub fn check(
+        &self,
+        perm: &Permission,
+        action: &str,
+        attributes: &Attributes,
+        proof: Option<&Proof>,
+    ) -> Result<(), Error> {
+        let subject = match &perm.subject {
+            Some(subject) => subject,
+            None => {
+                return Ok(());
+            },
+        };
+        let proof = match proof {
+            Some(proof) => proof,
+            None => {
+                return Err(Error::SubjectProof);
+            },
+        };
+        // Ensure the proof was made recently.
+        let now = now_millis();
+        if proof.timestamp.abs_diff(now) > MAX_CLOCK_SKEW_MILLIS {
+            return Err(Error::SubjectProof);
+        }
+        // Verify the signature with the subject's key.
+        let key = match parse_public_key(subject) {
+            Ok(key) => key,
+            Err(_) => {
+                return Err(Error::SubjectProof);
+            },
+        };
+        let signature = match hex::decode(&proof.signature) {
+            Ok(bytes) => match Signature::from_slice(&bytes) {
+                Ok(signature) => signature,
+                Err(_) => {
+                    return Err(Error::SubjectProof);
+                },
+            },
+            Err(_) => {
+                return Err(Error::SubjectProof);
+            },
+        };
+        let challenge = Challenge {
+            audience: &self.audience,
+            perm,
+            action,
+            attributes,
+            timestamp: proof.timestamp,
+            nonce: &proof.nonce,
+        };
+        if key.verify_strict(&challenge.to_bytes(), &signature).is_err() {
+            return Err(Error::SubjectProof);
+        }
+        // Reject replays, forgetting proofs that would now be rejected for
+        // their timestamp anyway. The decoded signature is remembered, so
+        // that it cannot be replayed with another hex encoding.
+        let mut seen = self.seen.lock().unwrap();
+        seen.retain(|_, expires| *expires >= now);
+        let expires = proof.timestamp + MAX_CLOCK_SKEW_MILLIS;
+        let signature = hex::encode(signature.to_bytes());
+        if seen.insert(signature, expires).is_some() {
+            return Err(Error::SubjectProof);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checkpoint::Signer;
+
+    /// Return a Permission bound to a subject.
+    fn bound(key: &SubjectKey) -> Permission {
+        serde_json::from_value(serde_json::json!({
+            "nonce": 1,
+            "actions": ["send-message"],
+            "version": 0,
+            "subject": key.subject(),
+        })).unwrap()
+    }
+
+    fn attributes(channel: &str) -> Attributes {
+        Attributes::from([("channel".to_owned(), channel.to_owned())])
+    }
+
+    #[test]
+    fn accepts_proof_once() {
+        let authority = Signer::generate().public_key();
+        let audience = format_public_key(&authority);
+        let checker = ProofChecker::new(&authority);
+        let key = SubjectKey::generate();
+        let perm = bound(&key);
+        let attrs = attributes("general");
+        let proof = key.prove(&audience, &perm, "send-message", &attrs);
+        let check = |proof: &Proof| {
+            checker.check(&perm, "send-message", &attrs, Some(proof))
+        };
+        assert!(check(&proof).is_ok());
+        assert!(matches!(check(&proof), Err(Error::SubjectProof)));
+        // The same signature in upper case hex is the same signature.
+        let upper = Proof {
+            signature: proof.signature.to_uppercase(),
+            ..proof.clone()
+        };
+        assert!(matches!(check(&upper), Err(Error::SubjectProof)));
+    }
+
+    #[test]
+    fn rejects_proof_for_other_request() {
+        let authority = Signer::generate().public_key();
+        let audience = format_public_key(&authority);
+        let checker = ProofChecker::new(&authority);
+        let key = SubjectKey::generate();
+        let perm = bound(&key);
+        let attrs = attributes("general");
+        let check = |proof: Proof, action: &str, attrs: &Attributes| {
+            checker.check(&perm, action, attrs, Some(&proof))
+        };
+        // Another action or other attributes.
+        let proof = key.prove(&audience, &perm, "send-message", &attrs);
+        let res = check(proof, "delete-message", &attrs);
+        assert!(matches!(res, Err(Error::SubjectProof)));
+        let proof = key.prove(&audience, &perm, "send-message", &attrs);
+        let res = check(proof, "send-message", &attributes("random"));
+        assert!(matches!(res, Err(Error::SubjectProof)));
+        // Another audience.
+        let other = format_public_key(&Signer::generate().public_key());
+        let proof = key.prove(&other, &perm, "send-message", &attrs);
+        let res = check(proof, "send-message", &attrs);
+        assert!(matches!(res, Err(Error::SubjectProof)));
+        // Another subject, or no proof at all.
+        let proof = SubjectKey::generate()
+            .prove(&audience, &perm, "send-message", &attrs);
+        let res = check(proof, "send-message", &attrs);
+        assert!(matches!(res, Err(Error::SubjectProof)));
+        let res = checker.check(&perm, "send-message", &attrs, None);
+        assert!(matches!(res, Err(Error::SubjectProof)));
+    }
+}
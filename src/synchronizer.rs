@@ -1,13 +1,46 @@
 use clacc::Witness;
 use gmp::mpz::Mpz;
 use hyper::body::to_bytes;
-use std::sync::atomic::AtomicPtr;
-use tokio::{sync::Mutex, task::JoinHandle, time::{interval, Duration}};
+use serde::{Serialize, Deserialize};
+use std::{collections::HashSet, sync::Arc};
+use tokio::{
+    sync::{Mutex, RwLock, RwLockReadGuard},
+    task::JoinHandle,
+    time::{interval, sleep, Duration, Instant},
+};
 use crate::{
-    constant::{AUTHORITY_ADDR, WORKER_ADDR, UPDATE_WINDOW_MILLIS},
+    checkpoint::SignedCheckpoint,
+    condition::Attributes,
+    constant::ROTATION_POLL_MILLIS,
+    delegation,
+    error::Error,
+    config::Config,
+    peer::Credentials,
     permission::{Action, Nonce, Permission},
-    request::{ActionRequest, UpdateRequest, UpdateResponse},
-    util::{from_bytes, Client},
+    request::{
+        ActionRequest,
+        ActionsRequest,
+        BatchResult,
+        BatchUpdateResponse,
+        CreateRequest,
+        Decision,
+        DelegateRequest,
+        DelegatedPermission,
+        DeleteRequest,
+        DeleteResponse,
+        KeyRequest,
+        RoleRequest,
+        UpdateActionsRequest,
+        UpdateRequest,
+        UpdateResponse,
+        WitnessedActions,
+        WitnessedDelegation,
+    },
+    role::Role,
+    subject::Proof,
+    tenant,
+    util::{from_bytes, now_millis, Client},
+    worker::RotationStatus,
 };
 
 /// A Synchronizer manages the Witness update window by synchronizing
@@ -18,52 +51,154 @@ use crate::{
 pub struct Synchronizer {
     auth_client: Client,
     worker_client: Client,
-    guard_acc: Mutex<()>,
+    window: Duration,
+
+    /// Lock held while the Accumulators are changed or their windows are
+    /// switched. Actions only need to see the Authority and the Worker in
+    /// agreement, so they share it.
+    guard_acc: RwLock<()>,
+
     guard_update: Mutex<()>,
+
+    /// Lock held exclusively while the Accumulator's key is rotated, so
+    /// that Permissions cannot be changed in the meantime. Actions do not
+    /// take it and keep being served.
+    guard_rotate: RwLock<()>,
+
+    status: Mutex<WindowStatus>,
+
+    /// The hex encoded public key the Authority signs Checkpoints with.
+    signing_key: String,
+
+    /// The Authority's latest Checkpoint.
+    published: Mutex<Option<SignedCheckpoint>>,
 }
 
 impl Synchronizer {
 
     /// Create a new Synchronizer.
-    pub async fn new() -> Result<Self, &'static str> {
+    pub async fn new(config: &Config) -> Result<Self, Error> {
+        Self::for_tenant(config, None).await
+    }
+
+    /// Create a Synchronizer for a tenant, or for the default tenant if
+    /// `tenant` is `None`.
+    ///
+    /// Requests to the Authority and the Worker are prefixed with the
+    /// tenant, so that they are served by the tenant's instances.
+    pub async fn for_tenant(
+        config: &Config,
+        tenant: Option<&str>,
+    ) -> Result<Self, Error> {
+        let prefix = tenant.map(tenant::prefix).unwrap_or_default();
         Synchronizer {
-            auth_client: Client::new(AUTHORITY_ADDR),
-            worker_client: Client::new(WORKER_ADDR),
-            guard_acc: Mutex::new(()),
+            auth_client: config.authority_client()?
+                .with_prefix(&prefix)
+                .with_credentials(Credentials::new(&config.authority_secret)?),
+            worker_client: config.worker_client()?
+                .with_prefix(&prefix)
+                .with_credentials(Credentials::new(&config.worker_secret)?),
+            window: Duration::from_millis(config.update_window_millis),
+            guard_acc: RwLock::new(()),
             guard_update: Mutex::new(()),
+            guard_rotate: RwLock::new(()),
+            status: Mutex::new(WindowStatus {
+                window_millis: config.update_window_millis,
+                closing: false,
+                closed: 0,
+                last_closed_at: None,
+                next_close_at: None,
+                last_error: None,
+            }),
+            signing_key: String::new(),
+            published: Mutex::new(None),
         }.key_worker().await
     }
 
     /// Set the Worker's public key by requesting it from the Authority.
-    async fn key_worker(mut self) -> Result<Self, &'static str> {
-        // Request the public key from the Authority.
-        let resp = self.auth_client.get("/key").await?;
-        // Deserialize the response to a Mpz.
+    async fn key_worker(mut self) -> Result<Self, Error> {
+        // Request the signing key from the Authority.
+        let resp = self.auth_client.get("/signing-key").await?;
         let bytes = to_bytes(resp.into_body()).await;
-        let key: Mpz = match from_bytes(&bytes) {
+        self.signing_key = match from_bytes(&bytes) {
             Some(res) => res,
             None => {
-                return Err("response error");
+                return Err(Error::Decode);
             },
         };
-        // Submit the public key to the Worker.
-        self.worker_client.post("/key", key).await?;
+        // Publish the latest Checkpoint, which carries the public key.
+        let checkpoint = self.publish().await?;
+        // Submit it to the Worker, which verifies it with the signing key
+        // pinned in its configuration.
+        let req = KeyRequest { checkpoint };
+        self.worker_client.post("/key", req).await?;
         // Return self on success.
         Ok(self)
     }
 
+    /// Internal helper to publish the Authority's latest Checkpoint.
+    ///
+    /// This is called whenever the verifying value may have changed while
+    /// the accumulator lock is held, so that the published Checkpoint
+    /// always agrees with the Witnesses handed out by `witness`.
+    async fn publish(&self) -> Result<SignedCheckpoint, Error> {
+        let resp = self.auth_client.get("/checkpoint").await?;
+        let bytes = to_bytes(resp.into_body()).await;
+        let checkpoint: SignedCheckpoint = match from_bytes(&bytes) {
+            Some(res) => res,
+            None => {
+                return Err(Error::Decode);
+            },
+        };
+        *self.published.lock().await = Some(checkpoint.clone());
+        Ok(checkpoint)
+    }
+
+    /// Return the hex encoded public key the Authority signs Checkpoints
+    /// with.
+    pub fn signing_key(&self) -> &str {
+        &self.signing_key
+    }
+
+    /// Return the published Checkpoint.
+    pub async fn checkpoint(&self) -> SignedCheckpoint {
+        // The Checkpoint is published before the Synchronizer is returned
+        // by `new`.
+        self.published.lock().await.clone().unwrap()
+    }
+
+    /// Return the Authority's recent Checkpoints, oldest first.
+    pub async fn checkpoints(&self) -> Result<Vec<SignedCheckpoint>, Error> {
+        let resp = self.auth_client.get("/checkpoints").await?;
+        let bytes = to_bytes(resp.into_body()).await;
+        match from_bytes(&bytes) {
+            Some(res) => Ok(res),
+            None => Err(Error::Decode),
+        }
+    }
+
     /// Add a permission to the system.
     pub async fn add_permission(
-        &mut self,
-        actions: Vec<Action>,
-    ) -> Result<Permission, &'static str> {
-        // Lock the Mutex.
-        let _guard = self.guard_acc.lock().await;
-        // Create a Permission that includes the requested actions.
+        &self,
+        req: CreateRequest,
+    ) -> Result<Permission, Error> {
+        // Refuse changes while the key is being rotated.
+        let _guard_rotate = self.changes()?;
+        // Take the accumulator lock exclusively.
+        let _guard = self.guard_acc.write().await;
+        // Create a Permission that includes the requested actions, validity
+        // period, subject, conditions and the current definitions of the
+        // requested roles.
         let mut perm = Permission {
             nonce: 0.into(),
-            actions: actions,
+            actions: req.actions,
             version: 0,
+            not_before: req.not_before,
+            expires_at: req.expires_at,
+            roles: self.resolve_roles(&req.roles).await?,
+            subject: req.subject,
+            conditions: req.conditions,
+            parent: None,
         };
         // Submit the permission to the Authority and read back the response
         // that includes populated Nonce.
@@ -72,7 +207,7 @@ impl Synchronizer {
         perm = match from_bytes(&bytes) {
             Some(res) => res,
             None => {
-                return Err("response error");
+                return Err(Error::Decode);
             },
         };
         // Submit the finalized Permission to the Worker.
@@ -81,14 +216,202 @@ impl Synchronizer {
         Ok(perm)
     }
 
+    /// Add a batch of Permissions to the system, returning the outcome of
+    /// each in order.
+    ///
+    /// The Authority and the Worker each apply the whole batch in a single
+    /// request, so a batch takes about as long as a single Permission. A
+    /// Permission that cannot be added does not prevent the others from
+    /// being added.
+    pub async fn add_permissions(
+        &self,
+        reqs: Vec<CreateRequest>,
+    ) -> Result<Vec<Result<Permission, Error>>, Error> {
+        // Refuse changes while the key is being rotated.
+        let _guard_rotate = self.changes()?;
+        // Take the accumulator lock exclusively.
+        let _guard = self.guard_acc.write().await;
+        // Create the Permissions like `add_permission` does, looking up the
+        // roles only once.
+        let roles = self.roles().await?;
+        let mut results = Vec::with_capacity(reqs.len());
+        let mut perms = Vec::new();
+        let mut slots = Vec::new();
+        for req in reqs {
+            match Self::resolve(&roles, &req.roles) {
+                Ok(roles) => {
+                    // The slot is filled with the Authority's outcome.
+                    slots.push(results.len());
+                    results.push(Err(Error::BadRequest));
+                    perms.push(Permission {
+                        nonce: 0.into(),
+                        actions: req.actions,
+                        version: 0,
+                        not_before: req.not_before,
+                        expires_at: req.expires_at,
+                        roles,
+                        subject: req.subject,
+                        conditions: req.conditions,
+                        parent: None,
+                    });
+                },
+                Err(err) => results.push(Err(err)),
+            }
+        }
+        if perms.is_empty() {
+            return Ok(results);
+        }
+        // Submit the Permissions to the Authority and read back the outcome
+        // of each, which includes the populated Nonce on success.
+        let resp = self.auth_client.post("/permissions", perms).await?;
+        let bytes = to_bytes(resp.into_body()).await;
+        let added: Vec<BatchResult> = match from_bytes(&bytes) {
+            Some(res) => res,
+            None => {
+                return Err(Error::Decode);
+            },
+        };
+        let added: Vec<Result<Permission, Error>> = added.into_iter()
+            .map(BatchResult::into_result)
+            .collect();
+        if added.len() != slots.len() {
+            return Err(Error::Decode);
+        }
+        // Submit the finalized Permissions to the Worker.
+        let absorbed: Vec<Permission> = added.iter()
+            .filter_map(|res| res.as_ref().ok().cloned())
+            .collect();
+        if !absorbed.is_empty() {
+            self.worker_client.post("/permissions", absorbed).await?;
+        }
+        for (slot, res) in slots.into_iter().zip(added) {
+            results[slot] = res;
+        }
+        Ok(results)
+    }
+
+    /// Return the current definitions of the roles.
+    pub async fn roles(&self) -> Result<Vec<Role>, Error> {
+        let resp = self.auth_client.get("/roles").await?;
+        let bytes = to_bytes(resp.into_body()).await;
+        match from_bytes(&bytes) {
+            Some(res) => Ok(res),
+            None => Err(Error::Decode),
+        }
+    }
+
+    /// Define a role, or redefine it with the next version.
+    ///
+    /// Permissions granted an earlier version of the role are updated to
+    /// the new version by the synchronization task before the next window
+    /// is closed.
+    pub async fn define_role(&self, req: RoleRequest) -> Result<Role, Error> {
+        let resp = self.auth_client.put("/role", req).await?;
+        let bytes = to_bytes(resp.into_body()).await;
+        match from_bytes(&bytes) {
+            Some(res) => Ok(res),
+            None => Err(Error::Decode),
+        }
+    }
+
+    /// Internal helper to look up the current definitions of roles by name.
+    async fn resolve_roles(
+        &self,
+        names: &[String],
+    ) -> Result<Vec<Role>, Error> {
+        if names.is_empty() {
+            return Ok(Vec::new());
+        }
+        Self::resolve(&self.roles().await?, names)
+    }
+
+    /// Internal helper to look up roles by name among the given
+    /// definitions.
+    fn resolve(roles: &[Role], names: &[String]) -> Result<Vec<Role>, Error> {
+        names.iter()
+            .map(|name| match roles.iter().find(|role| &role.name == name) {
+                Some(role) => Ok(role.clone()),
+                None => Err(Error::InvalidRole(name.clone())),
+            })
+            .collect()
+    }
+
+    /// Update the Permissions that were granted an earlier definition of a
+    /// role to its current definition.
+    ///
+    /// This is called by the synchronization task before each window is
+    /// closed, so that Permissions pick up redefined roles along with the
+    /// window. Returns the number of Permissions updated.
+    pub async fn refresh_roles(&self) -> Result<usize, Error> {
+        let roles = self.roles().await?;
+        if roles.is_empty() {
+            return Ok(0);
+        }
+        let resp = self.worker_client.post("/outdated", roles).await?;
+        let bytes = to_bytes(resp.into_body()).await;
+        let outdated: Vec<Permission> = match from_bytes(&bytes) {
+            Some(res) => res,
+            None => {
+                return Err(Error::Decode);
+            },
+        };
+        let mut updated = 0;
+        for perm in outdated {
+            let nonce = perm.nonce;
+            let req = UpdateActionsRequest {
+                actions: perm.actions.clone(),
+                perm,
+                roles: None,
+                conditions: None,
+            };
+            match self.update_permission(req).await {
+                Ok(_) => updated += 1,
+                Err(err) => {
+                    log::warn!(
+                        "could not update permission {}: {}",
+                        nonce,
+                        err,
+                    );
+                },
+            }
+        }
+        Ok(updated)
+    }
+
+    /// Get the current version of the Permission with a given Nonce.
+    ///
+    /// Permissions change without their owner's involvement when their
+    /// roles are redefined, so owners look up the current version here.
+    pub async fn permission(&self, nonce: Nonce) -> Result<Permission, Error> {
+        let path = format!("/permission/{}", nonce);
+        let resp = self.worker_client.get(&path).await?;
+        let bytes = to_bytes(resp.into_body()).await;
+        match from_bytes(&bytes) {
+            Some(res) => Ok(res),
+            None => Err(Error::Decode),
+        }
+    }
+
+    /// Internal helper to allow Permissions to be changed for as long as the
+    /// returned guard is held, unless a key rotation is in progress.
+    fn changes(&self) -> Result<RwLockReadGuard<'_, ()>, Error> {
+        match self.guard_rotate.try_read() {
+            Ok(guard) => Ok(guard),
+            Err(_) => Err(Error::Rotation(
+                "permissions cannot be changed during a key rotation"
+                    .to_owned(),
+            )),
+        }
+    }
+
     /// Internal helper to get the witness for a Permission.
     ///
     /// This code is reused by `update_permission` and `action` so that a
     /// current witness can be attached to the request to the Authority.
     async fn get_witness(
-        worker_client: &mut Client,
+        worker_client: &Client,
         nonce: Nonce,
-    ) -> Result<Witness<Mpz>, &'static str> {
+    ) -> Result<Witness<Mpz>, Error> {
         // Build the request path in the form of "/witness/{nonce}".
         let mut path = "/witness/".to_owned();
         path.push_str(&nonce.to_string());
@@ -97,34 +420,179 @@ impl Synchronizer {
         let bytes = to_bytes(resp.into_body()).await;
         match from_bytes::<Witness<Mpz>, _>(&bytes) {
             Some(res) => Ok(res),
-            None => Err("response error"),
+            None => Err(Error::Decode),
+        }
+    }
+
+    /// Internal helper to get the Witnesses for a list of Nonces in a
+    /// single request, or `None` for Nonces without one.
+    async fn get_witnesses(
+        &self,
+        nonces: Vec<Nonce>,
+    ) -> Result<Vec<Option<Witness<Mpz>>>, Error> {
+        let len = nonces.len();
+        let resp = self.worker_client.post("/witnesses", nonces).await?;
+        let bytes = to_bytes(resp.into_body()).await;
+        match from_bytes::<Vec<Option<Witness<Mpz>>>, _>(&bytes) {
+            Some(res) if res.len() == len => Ok(res),
+            _ => Err(Error::Decode),
         }
     }
 
     /// Update a permission.
+    ///
+    /// The Permission's roles are kept unless the request replaces them, and
+    /// are granted with their current definitions either way.
     pub async fn update_permission(
-        &mut self,
-        perm: Permission,
-        actions: Vec<Action>
-    ) -> Result<Permission, &'static str> {
-        // Lock the Mutex.
-        let _guard = self.guard_acc.lock().await;
-        // Get the Permission's current Witness.
-        let witness = Self::get_witness(
-            &mut self.worker_client,
-            perm.nonce
-        ).await?;
+        &self,
+        req: UpdateActionsRequest,
+    ) -> Result<Permission, Error> {
+        let names = Self::role_names(&req);
+        let roles = self.resolve_roles(&names).await?;
+        let (perm, update) = Self::next_version(req, &roles)?;
+        self.replace_permission(perm, update).await
+    }
+
+    /// Update a batch of permissions, returning the outcome of each in
+    /// order.
+    ///
+    /// Each update is made like with `update_permission`, but the Witnesses
+    /// are looked up and the updates are applied by the Authority and the
+    /// Worker in a single request each. A Permission may only appear once in
+    /// a batch. An update that cannot be made does not prevent the others
+    /// from being made.
+    pub async fn update_permissions(
+        &self,
+        reqs: Vec<UpdateActionsRequest>,
+    ) -> Result<Vec<Result<Permission, Error>>, Error> {
+        // Refuse changes while the key is being rotated.
+        let _guard_rotate = self.changes()?;
+        // Take the accumulator lock exclusively.
+        let _guard = self.guard_acc.write().await;
+        // Create the next version of each Permission, looking up the roles
+        // only once.
+        let roles = self.roles().await?;
+        let mut seen = HashSet::new();
+        let mut results = Vec::with_capacity(reqs.len());
+        let mut pairs = Vec::new();
+        let mut slots = Vec::new();
+        for req in reqs {
+            if !seen.insert(req.perm.nonce) {
+                results.push(Err(Error::BadRequest));
+                continue;
+            }
+            match Self::next_version(req, &roles) {
+                Ok((perm, update)) => {
+                    slots.push(results.len());
+                    results.push(Ok(update.clone()));
+                    pairs.push((perm, update));
+                },
+                Err(err) => results.push(Err(err)),
+            }
+        }
+        if pairs.is_empty() {
+            return Ok(results);
+        }
+        // Get the Permissions' current Witnesses.
+        let nonces = pairs.iter().map(|(perm, _)| perm.nonce).collect();
+        let witnesses = self.get_witnesses(nonces).await?;
+        let mut batch = Vec::new();
+        let mut batch_slots = Vec::new();
+        let items = pairs.into_iter().zip(witnesses).zip(slots);
+        for (((perm, update), witness), slot) in items {
+            match witness {
+                Some(witness) => {
+                    batch.push(UpdateRequest { perm, witness, update });
+                    batch_slots.push(slot);
+                },
+                None => results[slot] = Err(Error::UnknownPermission),
+            }
+        }
+        if batch.is_empty() {
+            return Ok(results);
+        }
+        // Submit the batch to the Authority and deserialize the response.
+        let resp = self.auth_client.put("/permissions", batch).await?;
+        let bytes = to_bytes(resp.into_body()).await;
+        let response: BatchUpdateResponse = match from_bytes(&bytes) {
+            Some(res) => res,
+            None => {
+                return Err(Error::Decode);
+            },
+        };
+        if response.results.len() != batch_slots.len() {
+            return Err(Error::Decode);
+        }
+        for (slot, res) in batch_slots.into_iter().zip(&response.results) {
+            if let Err(err) = res.clone().into_result() {
+                results[slot] = Err(err);
+            }
+        }
+        // Submit the response to the Worker so that it has the most current
+        // accumulation value. The Checkpoint only advances if an update was
+        // applied.
+        if !response.reqs.is_empty() {
+            self.worker_client.put("/permissions", response).await?;
+        }
+        Ok(results)
+    }
+
+    /// Internal helper to list the names of the roles an update grants.
+    fn role_names(req: &UpdateActionsRequest) -> Vec<String> {
+        match &req.roles {
+            Some(names) => names.clone(),
+            None => req.perm.roles.iter()
+                .map(|role| role.name.clone())
+                .collect(),
+        }
+    }
+
+    /// Internal helper to create the next version of a Permission, granting
+    /// its roles with their definitions among the given ones.
+    fn next_version(
+        req: UpdateActionsRequest,
+        roles: &[Role],
+    ) -> Result<(Permission, Permission), Error> {
+        let roles = Self::resolve(roles, &Self::role_names(&req))?;
+        let UpdateActionsRequest { perm, actions, conditions, .. } = req;
         // Create Permission with new actions and an incremented version.
+        // The validity period, subject and parent are carried over, as are
+        // the conditions unless they are replaced.
+        let conditions = conditions.unwrap_or_else(|| perm.conditions.clone());
         let update = Permission {
             nonce: perm.nonce,
-            actions: actions,
+            actions,
             version: perm.version + 1,
+            not_before: perm.not_before,
+            expires_at: perm.expires_at,
+            roles,
+            subject: perm.subject.clone(),
+            conditions,
+            parent: perm.parent,
         };
+        Ok((perm, update))
+    }
+
+    /// Internal helper to replace a Permission with its next version.
+    async fn replace_permission(
+        &self,
+        perm: Permission,
+        update: Permission,
+    ) -> Result<Permission, Error> {
+        // Refuse changes while the key is being rotated.
+        let _guard_rotate = self.changes()?;
+        // Take the accumulator lock exclusively.
+        let _guard = self.guard_acc.write().await;
+        // Get the Permission's current Witness.
+        let witness = Self::get_witness(
+            &self.worker_client,
+            perm.nonce
+        ).await?;
         // Create the UpdateRequest struct containing the Witness as well as
         // the old and new Permissions.
         let req = UpdateRequest {
-            perm: perm,
-            witness: witness,
+            perm,
+            witness,
             update: update.clone(),
         };
         // Submit the request to the Authority and deserialize the response.
@@ -133,7 +601,7 @@ impl Synchronizer {
         let response: UpdateResponse = match from_bytes(&bytes) {
             Some(res) => res,
             None => {
-                return Err("response error");
+                return Err(Error::Decode);
             },
         };
         // Submit the response to the Worker so that it has the most current
@@ -143,24 +611,167 @@ impl Synchronizer {
         Ok(update)
     }
 
-    /// Perform an action.
+    /// Delegate a subset of a Permission's actions to a new child
+    /// Permission.
+    ///
+    /// The parent's Witness is looked up and verified by the Authority
+    /// before it adds the child, which may be used once the current update
+    /// window has closed. Later changes to the parent cascade to the child
+    /// through `cascade`.
+    pub async fn delegate(
+        &self,
+        req: DelegateRequest,
+    ) -> Result<Permission, Error> {
+        // Refuse changes while the key is being rotated.
+        let _guard_rotate = self.changes()?;
+        // Take the accumulator lock exclusively.
+        let _guard = self.guard_acc.write().await;
+        // Get the parent's current Witness.
+        let witness = Self::get_witness(
+            &self.worker_client,
+            req.perm.nonce
+        ).await?;
+        // Submit the request to the Authority and read back the child.
+        let req = WitnessedDelegation {
+            req,
+            witness,
+        };
+        let resp = self.auth_client.post("/delegate", req).await?;
+        let bytes = to_bytes(resp.into_body()).await;
+        let child: Permission = match from_bytes(&bytes) {
+            Some(res) => res,
+            None => {
+                return Err(Error::Decode);
+            },
+        };
+        // Submit the child to the Worker.
+        self.worker_client.post("/permission", child.clone()).await?;
+        // Return the child on success.
+        Ok(child)
+    }
+
+    /// Revoke the delegated Permissions whose parent has been revoked, and
+    /// derive the others again from the current version of their parent.
+    ///
+    /// Changes reach Permissions delegated from other delegated Permissions
+    /// in the same pass, as planned by `delegation::cascade`.
+    ///
+    /// This is called by the synchronization task before each window is
+    /// closed, after roles are refreshed, so that changes to a parent take
+    /// effect on its children along with the window. Returns the number of
+    /// Permissions updated or revoked.
+    pub async fn cascade(&self) -> Result<usize, Error> {
+        let resp = self.worker_client.get("/delegated").await?;
+        let bytes = to_bytes(resp.into_body()).await;
+        let delegated: Vec<DelegatedPermission> = match from_bytes(&bytes) {
+            Some(res) => res,
+            None => {
+                return Err(Error::Decode);
+            },
+        };
+        let mut changed = 0;
+        let mut failed = HashSet::new();
+        for (perm, update) in delegation::cascade(&delegated) {
+            let nonce = perm.nonce;
+            // Leave the children of a Permission that could not be changed
+            // for the next window, when they are derived from it again.
+            if perm.parent.is_some_and(|p| failed.contains(&p.nonce)) {
+                failed.insert(nonce);
+                continue;
+            }
+            let res = match update {
+                Some(update) => {
+                    self.replace_permission(perm, update).await.map(|_| ())
+                },
+                None => self.delete_permission(perm).await,
+            };
+            match res {
+                Ok(_) => changed += 1,
+                Err(err) => {
+                    failed.insert(nonce);
+                    log::warn!(
+                        "could not cascade to permission {}: {}",
+                        nonce,
+                        err,
+                    );
+                },
+            }
+        }
+        Ok(changed)
+    }
+
+    /// Delete a permission.
+    pub async fn delete_permission(
+        &self,
+        perm: Permission,
+    ) -> Result<(), Error> {
+        // Refuse changes while the key is being rotated.
+        let _guard_rotate = self.changes()?;
+        // Take the accumulator lock exclusively.
+        let _guard = self.guard_acc.write().await;
+        // Get the Permission's current Witness.
+        let witness = Self::get_witness(
+            &self.worker_client,
+            perm.nonce
+        ).await?;
+        // Create the DeleteRequest struct containing the Witness.
+        let req = DeleteRequest {
+            perm,
+            witness,
+        };
+        // Submit the request to the Authority and deserialize the response.
+        let resp = self.auth_client.delete("/permission", req).await?;
+        let bytes = to_bytes(resp.into_body()).await;
+        let response: DeleteResponse = match from_bytes(&bytes) {
+            Some(res) => res,
+            None => {
+                return Err(Error::Decode);
+            },
+        };
+        // Submit the response to the Worker so that it has the most current
+        // accumulation value.
+        self.worker_client.delete("/permission", response).await?;
+        // Return success.
+        Ok(())
+    }
+
+    /// Get the current Witness of the Permission with a given Nonce.
+    ///
+    /// The Witness proves that the Permission is a member of the published
+    /// accumulation value, so the owner of a Permission may present both
+    /// to a party that verifies actions offline.
+    pub async fn witness(
+        &self,
+        nonce: Nonce,
+    ) -> Result<Witness<Mpz>, Error> {
+        // Share the accumulator lock with actions.
+        let _guard = self.guard_acc.read().await;
+        Self::get_witness(&self.worker_client, nonce).await
+    }
+
+    /// Perform an action with the given request attributes, with proof that
+    /// it is requested by the Permission's subject if it is bound to one.
     pub async fn action(
-        &mut self,
+        &self,
         perm: Permission,
         action: Action,
-    ) -> Result<(), &'static str> {
-        // Lock the Mutex.
-        let _guard = self.guard_acc.lock().await;
+        attributes: Attributes,
+        proof: Option<Proof>,
+    ) -> Result<(), Error> {
+        // Share the accumulator lock with other actions.
+        let _guard = self.guard_acc.read().await;
         // Get the Permission's current Witness.
         let witness = Self::get_witness(
-            &mut self.worker_client,
+            &self.worker_client,
             perm.nonce
         ).await?;
         // Create the ActionRequest struct.
         let req = ActionRequest {
-            perm: perm,
-            witness: witness,
-            action: action,
+            perm,
+            witness,
+            action,
+            attributes,
+            proof,
         };
         // Submit the request.
         self.auth_client.post("/action", req).await?;
@@ -168,54 +779,282 @@ impl Synchronizer {
         Ok(())
     }
 
+    /// Decide every action of a list for every Permission of a list.
+    ///
+    /// The Witnesses are looked up and the decisions are made by the
+    /// Authority in a single request each. The decisions are returned by
+    /// Permission and then by action.
+    pub async fn actions(
+        &self,
+        req: ActionsRequest,
+    ) -> Result<Vec<Vec<Result<(), Error>>>, Error> {
+        // Share the accumulator lock with other actions.
+        let _guard = self.guard_acc.read().await;
+        // Get the Permissions' current Witnesses.
+        let nonces = req.perms.iter().map(|perm| perm.nonce).collect();
+        let witnesses = self.get_witnesses(nonces).await?;
+        // Submit the request and deserialize the decisions.
+        let req = WitnessedActions { req, witnesses };
+        let resp = self.auth_client.post("/actions", req).await?;
+        let bytes = to_bytes(resp.into_body()).await;
+        let decisions: Vec<Vec<Decision>> = match from_bytes(&bytes) {
+            Some(res) => res,
+            None => {
+                return Err(Error::Decode);
+            },
+        };
+        Ok(decisions.into_iter()
+            .map(|row| row.into_iter().map(Decision::into_result).collect())
+            .collect())
+    }
+
+    /// Internal helper to move the Authority and the Worker on to the next
+    /// update window.
+    async fn window(&self) -> Result<(), Error> {
+        // Create a future for the update task, but only take the
+        // accumulator lock while the Authority and Worker states are
+        // mutated.
+        {
+            // Take the accumulator lock.
+            let _guard_acc = self.guard_acc.write().await;
+            // Tell the Authority to switch over its staging accumulation.
+            self.auth_client.get("/update").await?;
+            // Tell the Worker to start updating Witnesses.
+            self.worker_client.get("/update")
+            // The lock gets released here, even though the Worker update
+            // result will be awaited for.
+        }.await?;
+        // Take the accumulator lock.
+        let _guard_acc = self.guard_acc.write().await;
+        // Tell the Authority to switch over its updating accumulation.
+        self.auth_client.get("/sync").await?;
+        // Tell the Worker to switch over its permissions map.
+        self.worker_client.get("/sync").await?;
+        // Publish the new verifying value.
+        self.publish().await?;
+        Ok(())
+    }
+
+    /// Revoke the Permissions that have expired.
+    ///
+    /// This is called by the synchronization task before each window is
+    /// closed, so that expired Permissions leave the accumulation along with
+    /// the window. Returns the number of Permissions revoked.
+    pub async fn sweep(&self) -> Result<usize, Error> {
+        let resp = self.worker_client.get("/expired").await?;
+        let bytes = to_bytes(resp.into_body()).await;
+        let expired: Vec<Permission> = match from_bytes(&bytes) {
+            Some(res) => res,
+            None => {
+                return Err(Error::Decode);
+            },
+        };
+        let mut revoked = 0;
+        for perm in expired {
+            let nonce = perm.nonce;
+            match self.delete_permission(perm).await {
+                Ok(_) => revoked += 1,
+                Err(err) => {
+                    log::warn!(
+                        "could not revoke permission {}: {}",
+                        nonce,
+                        err,
+                    );
+                },
+            }
+        }
+        Ok(revoked)
+    }
+
+    /// Close the current update window.
+    ///
+    /// This is called periodically by the synchronization task, but may
+    /// also be called to close a window early. Only one window is closed at
+    /// a time.
+    pub async fn close_window(&self) -> Result<(), Error> {
+        // Lock the update Mutex.
+        let _guard_update = self.guard_update.lock().await;
+        self.status.lock().await.closing = true;
+        let res = self.window().await;
+        // Record the outcome.
+        let mut status = self.status.lock().await;
+        status.closing = false;
+        match &res {
+            Ok(_) => {
+                status.closed += 1;
+                status.last_closed_at = Some(now_millis());
+                status.last_error = None;
+            },
+            Err(err) => {
+                status.last_error = Some(err.to_string());
+            },
+        }
+        res
+    }
+
+    /// Rotate the Accumulator's key.
+    ///
+    /// Permissions cannot be changed for the duration of the rotation, but
+    /// actions keep being served. The current window is closed first so
+    /// that every change is synchronized. The Authority then generates a
+    /// new key and adds every Permission to a new Accumulator, and the
+    /// Worker recomputes every Witness against it in the background. Once
+    /// the Witnesses are ready, both switch over to the new key at the
+    /// boundary of an update window and the new Checkpoint is published.
+    ///
+    /// If any step before the switch fails, the rotation is abandoned and
+    /// the current key stays in use. The switch itself is not atomic: if
+    /// the Worker fails to switch after the Authority has, the Worker keeps
+    /// the recomputed Witnesses but must be switched over by hand.
+    pub async fn rotate(&self) -> Result<SignedCheckpoint, Error> {
+        // Block changes, waiting for those in flight to complete.
+        let _guard_rotate = self.guard_rotate.write().await;
+        // Synchronize every change made so far.
+        self.close_window().await?;
+        // Keep the synchronization task from closing another window.
+        let _guard_update = self.guard_update.lock().await;
+        let prepared = match self.prepare_rotation().await {
+            Ok(_) => {
+                // Take the accumulator lock and switch the Authority over.
+                let guard_acc = self.guard_acc.write().await;
+                match self.auth_client.post("/rotate/commit", ()).await {
+                    Ok(_) => Ok(guard_acc),
+                    Err(err) => Err(err),
+                }
+            },
+            Err(err) => Err(err),
+        };
+        let _guard_acc = match prepared {
+            Ok(guard_acc) => guard_acc,
+            Err(err) => {
+                // Abandon the rotation. Failures are ignored since neither
+                // service switches over without being told to.
+                let _ = self.auth_client.delete("/rotate", ()).await;
+                let _ = self.worker_client.delete("/rotate", ()).await;
+                return Err(err);
+            },
+        };
+        // Switch the Worker over and publish the Checkpoint of the new key.
+        self.worker_client.post("/rotate/commit", ()).await?;
+        self.publish().await
+    }
+
+    /// Internal helper to prepare both services for a key rotation.
+    async fn prepare_rotation(&self) -> Result<(), Error> {
+        // Request every synchronized Permission from the Worker.
+        let resp = self.worker_client.get("/permissions").await?;
+        let bytes = to_bytes(resp.into_body()).await;
+        let perms: Vec<Permission> = match from_bytes(&bytes) {
+            Some(res) => res,
+            None => {
+                return Err(Error::Decode);
+            },
+        };
+        // Have the Authority add them to an Accumulator with a new key.
+        let resp = self.auth_client.post("/rotate", perms).await?;
+        let bytes = to_bytes(resp.into_body()).await;
+        let checkpoint: SignedCheckpoint = match from_bytes(&bytes) {
+            Some(res) => res,
+            None => {
+                return Err(Error::Decode);
+            },
+        };
+        // Have the Worker recompute the Witnesses, and wait for it.
+        self.worker_client.post("/rotate", checkpoint).await?;
+        loop {
+            sleep(Duration::from_millis(ROTATION_POLL_MILLIS)).await;
+            let resp = self.worker_client.get("/rotate").await?;
+            let bytes = to_bytes(resp.into_body()).await;
+            let status: RotationStatus = match from_bytes(&bytes) {
+                Some(res) => res,
+                None => {
+                    return Err(Error::Decode);
+                },
+            };
+            if let Some(err) = status.error {
+                return Err(Error::Rotation(err));
+            }
+            if !status.rotating {
+                return Err(Error::Rotation(
+                    "the worker abandoned the rotation".to_owned(),
+                ));
+            }
+            if status.ready {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Return the state of the update windows.
+    pub async fn window_status(&self) -> WindowStatus {
+        self.status.lock().await.clone()
+    }
+
     /// Start the synchronization task.
     ///
-    /// The synchronization task executes in a continuous loop until a
-    /// communication error occurs with the Authority or the Worker.
-    /// The owner of a Synchronizer instance must await the returned future
-    /// before the instance may be freed safely.
-    pub fn sync(&mut self) -> JoinHandle<Result<(), &'static str>> {
-        // Create an AtomicPtr so that a reference to the instance may be moved
-        // into the task.
-        let mut ptr = AtomicPtr::new(self);
+    /// The synchronization task executes in a continuous loop. If a
+    /// communication error occurs with the Authority or the Worker, the
+    /// window is abandoned and its updates are carried into the next one,
+    /// which allows either service to be restarted.
+    /// The task holds a reference to the Synchronizer for as long as it
+    /// runs.
+    pub fn sync(self: Arc<Self>) -> JoinHandle<()> {
         tokio::spawn(async move {
-            // Dereference the pointer to get the refenence.
-            let sync = unsafe {
-                ptr.get_mut().as_mut().unwrap()
-            };
-            // Lock the update Mutex to prevent additional sync tasks from
-            // executing.
-            let _guard_update = sync.guard_update.lock().await;
             // Define the update window.
-            let dur = Duration::from_millis(UPDATE_WINDOW_MILLIS);
-            let mut window = interval(dur);
+            let mut window = interval(self.window);
             // The first tick completes immediately. Get it out of the way.
-            window.tick().await;
+            let mut tick = window.tick().await;
             // Start looping.
             loop {
+                // Publish when the next tick is due.
+                let due = (tick + self.window).duration_since(Instant::now());
+                let due = now_millis() + due.as_millis() as u64;
+                self.status.lock().await.next_close_at = Some(due);
                 // Wait for the next interval tick.
-                window.tick().await;
-                // Create a future for the update task, but only lock the
-                // Accumulator Mutex while the Authority and Worker states
-                // are mutated.
-                {
-                    // Lock the accumulator Mutex.
-                    let _guard_acc = sync.guard_acc.lock().await;
-                    // Tell the Authority to switch over its staging
-                    // accumulation.
-                    sync.auth_client.get("/update").await?;
-                    // Tell the Worker to start updating Witnesses.
-                    sync.worker_client.get("/update")
-                    // Mutex gets released here, even though the Worker update
-                    // result will be awaited for.
-                }.await?;
-                // Lock the accumulator Mutex.
-                let _guard_acc = sync.guard_acc.lock().await;
-                // Tell the Authority to switch over its updating accumulation.
-                sync.auth_client.get("/sync").await?;
-                // Tell the Worker to switch over its permissions map.
-                sync.worker_client.get("/sync").await?;
+                tick = window.tick().await;
+                // Update Permissions to redefined roles, cascade changes to
+                // delegated ones and revoke expired ones so that the changes
+                // take effect with this window.
+                if let Err(err) = self.refresh_roles().await {
+                    log::error!("role refresh failed: {}", err);
+                }
+                if let Err(err) = self.cascade().await {
+                    log::error!("delegation cascade failed: {}", err);
+                }
+                if let Err(err) = self.sweep().await {
+                    log::error!("expiry sweep failed: {}", err);
+                }
+                // Close the window.
+                if let Err(err) = self.close_window().await {
+                    log::error!("update window failed: {}", err);
+                }
             }
         })
     }
 }
+
+/// The state of the update windows.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct WindowStatus {
+
+    /// The length of the update window in milliseconds.
+    pub window_millis: u64,
+
+    /// Whether a window is being closed.
+    pub closing: bool,
+
+    /// The number of windows that have been closed.
+    pub closed: u64,
+
+    /// When the last window was closed, in milliseconds since the Unix
+    /// epoch.
+    pub last_closed_at: Option<u64>,
+
+    /// When the synchronization task will next close a window, in
+    /// milliseconds since the Unix epoch.
+    pub next_close_at: Option<u64>,
+
+    /// The error that caused the last attempt to close a window to fail, if
+    /// it did.
+    pub last_error: Option<String>,
+}
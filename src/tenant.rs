@@ -0,0 +1,168 @@
+use hyper::{Request, Uri};
+use std::{collections::HashMap, sync::Arc};
+use crate::error::Error;
+
+/// The prefix of the paths of requests to a tenant, which is followed by
+/// the tenant's ID and the path of the request, e.g.
+/// `/tenant/billing/permission`. Requests without it go to the default
+/// tenant.
+pub const PATH_PREFIX: &str = "/tenant/";
+
+/// The longest tenant ID that is accepted.
+pub const MAX_ID_LEN: usize = 64;
+
+/// Ensure a tenant ID is made of lowercase letters, digits, `-` and `_`,
+/// so that it can be used in paths and directory names.
+pub fn validate(tenant: &str) -> Result<(), Error> {
+    let valid = !tenant.is_empty()
+        && tenant.len() <= MAX_ID_LEN
+        && tenant.bytes().all(|c| {
+            c.is_ascii_lowercase()
+                || c.is_ascii_digit()
+                || c == b'-'
+                || c == b'_'
+        });
+    match valid {
+        true => Ok(()),
+        false => Err(Error::Config(format!("invalid tenant ID {}", tenant))),
+    }
+}
+
+/// The path prefix of requests to a tenant.
+pub fn prefix(tenant: &str) -> String {
+    format!("{}{}", PATH_PREFIX, tenant)
+}
+
+/// Split a path into a tenant ID and the path of the request to it, or
+/// return `None` if the path is not prefixed with a tenant.
+pub fn split(path: &str) -> Option<(&str, &str)> {
+    let rest = path.strip_prefix(PATH_PREFIX)?;
+    match rest.find('/') {
+        Some(i) => Some((&rest[..i], &rest[i..])),
+        None => Some((rest, "/")),
+    }
+}
+
+/// The instances of a service, such as the Authority or the Worker, that
+/// serve each tenant of a deployment.
+///
+/// Every tenant has its own instance, so its accumulation, Nonces,
+/// Witnesses and update window are separate from those of every other
+/// tenant.
+pub struct Tenants<T> {
+
+    /// The instance serving requests without a tenant prefix.
+    default: Arc<T>,
+
+    /// The instances serving each tenant by ID.
+    tenants: HashMap<String, Arc<T>>,
+}
+
+impl<T> Tenants<T> {
+
+    /// Create a set of instances with only the default tenant.
+    pub fn new(default: T) -> Self {
+        Tenants {
+            default: Arc::new(default),
+            tenants: HashMap::new(),
+        }
+    }
+
+    /// Add the instance serving a tenant.
+    pub fn insert(&mut self, tenant: &str, instance: T) {
+        self.tenants.insert(tenant.to_owned(), Arc::new(instance));
+    }
+
+    /// Return the instance serving requests without a tenant prefix.
+    pub fn default_tenant(&self) -> Arc<T> {
+        Arc::clone(&self.default)
+    }
+
+    /// Iterate over the instances of the tenants other than the default one.
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Arc<T>)> {
+        self.tenants.iter()
+    }
+
+    /// Route a request to the instance serving its tenant.
+    ///
+    /// The tenant prefix is stripped from the request's path, so that the
+    /// instance serves it like a request without one. Requests should be
+    /// authenticated before they are routed, since peers sign the path with
+    /// the prefix.
+    pub fn route<B>(
+        &self,
+        req: Request<B>,
+    ) -> Result<(Arc<T>, Request<B>), Error> {
+        let (tenant, path) = match split(req.uri().path()) {
+            Some((tenant, path)) => (tenant.to_owned(), path.to_owned()),
+            None => {
+                return Ok((self.default_tenant(), req));
+            },
+        };
+        let instance = match self.tenants.get(&tenant) {
+            Some(instance) => Arc::clone(instance),
+            None => {
+                return Err(Error::UnknownTenant(tenant));
+            },
+        };
+        let path = match req.uri().query() {
+            Some(query) => format!("{}?{}", path, query),
+            None => path,
+        };
+        let (mut parts, body) = req.into_parts();
+        parts.uri = match Uri::builder().path_and_query(path).build() {
+            Ok(uri) => uri,
+            Err(_) => {
+                return Err(Error::BadRequest);
+            },
+        };
+        Ok((instance, Request::from_parts(parts, body)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tenants() -> Tenants<&'static str> {
+        let mut tenants = Tenants::new("default");
+        tenants.insert("billing", "billing");
+        tenants
+    }
+
+    fn route(path: &str) -> Result<(&'static str, String), Error> {
+        let req = Request::builder().uri(path).body(()).unwrap();
+        let (instance, req) = tenants().route(req)?;
+        Ok((*instance, req.uri().to_string()))
+    }
+
+    #[test]
+    fn route_strips_tenant_prefix() {
+        assert_eq!(
+            route("/tenant/billing/witness/7?x=1"),
+            Ok(("billing", "/witness/7?x=1".to_owned())),
+        );
+        assert_eq!(route("/tenant/billing"), Ok(("billing", "/".to_owned())));
+        assert_eq!(
+            route("/witness/7"),
+            Ok(("default", "/witness/7".to_owned())),
+        );
+    }
+
+    #[test]
+    fn route_refuses_unknown_tenants() {
+        assert_eq!(
+            route("/tenant/support/witness/7"),
+            Err(Error::UnknownTenant("support".to_owned())),
+        );
+    }
+
+    #[test]
+    fn validate_refuses_unsafe_ids() {
+        assert!(validate("billing-2_eu").is_ok());
+        for id in ["", "Billing", "../billing", "bill/ing", "bill ing"] {
+            assert!(validate(id).is_err());
+        }
+        assert!(validate(&"a".repeat(MAX_ID_LEN + 1)).is_err());
+    }
+}
@@ -0,0 +1,238 @@
+use futures::Future;
+use hyper::{
+    Body, Error as HyperError, Request, Response, Server,
+    server::conn::Http,
+    service::{make_service_fn, service_fn},
+};
+use rustls::{
+    Certificate,
+    ClientConfig,
+    PrivateKey,
+    RootCertStore,
+    ServerConfig,
+    ServerName,
+    client::{ServerCertVerified, ServerCertVerifier, WebPkiVerifier},
+};
+use sha2::{Digest, Sha256};
+use std::{
+    convert::Infallible,
+    fs::File,
+    io::BufReader,
+    net::SocketAddr,
+    sync::Arc,
+    time::SystemTime,
+};
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
+use crate::error::Error;
+
+/// The settings a client needs to connect to services over TLS.
+#[derive(Clone)]
+pub struct ClientTls {
+    config: Arc<ClientConfig>,
+}
+
+impl ClientTls {
+
+    /// Trust the certificate authorities in a PEM file.
+    ///
+    /// If `pin` is given, it is the hex encoded SHA-256 fingerprint of the
+    /// DER encoded certificate the service must present, in addition to
+    /// that certificate being issued by one of the authorities.
+    pub fn new(ca_path: &str, pin: Option<&str>) -> Result<Self, Error> {
+        let mut roots = RootCertStore::empty();
+        for cert in read_certs(ca_path)? {
+            if roots.add(&cert).is_err() {
+                return Err(invalid(format!(
+                    "{}: invalid CA certificate",
+                    ca_path,
+                )));
+            }
+        }
+        let verifier = Pinned {
+            inner: WebPkiVerifier::new(roots, None),
+            pin: match pin {
+                Some(pin) => Some(parse_fingerprint(pin)?),
+                None => None,
+            },
+        };
+        let config = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_custom_certificate_verifier(Arc::new(verifier))
+            .with_no_client_auth();
+        Ok(ClientTls {
+            config: Arc::new(config),
+        })
+    }
+
+    /// Return the rustls configuration.
+    pub fn config(&self) -> ClientConfig {
+        (*self.config).clone()
+    }
+}
+
+/// A certificate verifier that optionally pins the server's certificate.
+struct Pinned {
+    inner: WebPkiVerifier,
+    pin: Option<[u8; 32]>,
+}
+
+impl ServerCertVerifier for Pinned {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        // Verify the certificate chain against the trusted authorities.
+        let verified = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            scts,
+            ocsp_response,
+            now,
+        )?;
+        // Ensure the certificate is the pinned one.
+        match self.pin {
+            Some(pin) if Sha256::digest(&end_entity.0)[..] != pin => {
+                Err(rustls::Error::General(
+                    "certificate does not match the pinned fingerprint"
+                        .to_owned(),
+                ))
+            },
+            _ => Ok(verified),
+        }
+    }
+}
+
+/// Create the TLS acceptor for a service's listener from the PEM files
+/// holding its certificate chain and private key.
+pub fn acceptor(cert_path: &str, key_path: &str) -> Result<TlsAcceptor, Error> {
+    let certs = read_certs(cert_path)?;
+    let key = read_key(key_path)?;
+    let config = match ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, key) {
+        Ok(config) => config,
+        Err(err) => {
+            return Err(invalid(format!("{}: {}", cert_path, err)));
+        },
+    };
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// Serve HTTP requests on an address, over TLS if an acceptor is given.
+///
+/// Connections whose TLS handshake fails are dropped.
+pub async fn serve<F, R>(
+    addr: SocketAddr,
+    tls: Option<TlsAcceptor>,
+    handle: F,
+) -> Result<(), Error>
+where
+    F: Fn(Request<Body>) -> R + Clone + Send + 'static,
+    R: Future<Output = Result<Response<Body>, HyperError>> + Send + 'static,
+{
+    let acceptor = match tls {
+        Some(acceptor) => acceptor,
+        None => {
+            let make_service = make_service_fn(move |_| {
+                let handle = handle.clone();
+                async move {
+                    Ok::<_, Infallible>(service_fn(handle))
+                }
+            });
+            return match Server::bind(&addr).serve(make_service).await {
+                Ok(_) => Ok(()),
+                Err(err) => Err(Error::Transport(err.to_string())),
+            };
+        },
+    };
+    let listener = match TcpListener::bind(addr).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            return Err(Error::Transport(err.to_string()));
+        },
+    };
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(_) => continue,
+        };
+        let acceptor = acceptor.clone();
+        let handle = handle.clone();
+        tokio::spawn(async move {
+            let stream = match acceptor.accept(stream).await {
+                Ok(stream) => stream,
+                Err(_) => return,
+            };
+            let _ = Http::new()
+                .serve_connection(stream, service_fn(handle))
+                .await;
+        });
+    }
+}
+
+/// Internal helper to read the certificates in a PEM file.
+fn read_certs(path: &str) -> Result<Vec<Certificate>, Error> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) => {
+            return Err(invalid(format!("{}: {}", path, err)));
+        },
+    };
+    match rustls_pemfile::certs(&mut BufReader::new(file)) {
+        Ok(certs) if !certs.is_empty() => {
+            Ok(certs.into_iter().map(Certificate).collect())
+        },
+        _ => Err(invalid(format!("{}: no certificates found", path))),
+    }
+}
+
+/// Internal helper to read the first private key in a PEM file.
+fn read_key(path: &str) -> Result<PrivateKey, Error> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) => {
+            return Err(invalid(format!("{}: {}", path, err)));
+        },
+    };
+    let items = match rustls_pemfile::read_all(&mut BufReader::new(file)) {
+        Ok(items) => items,
+        Err(err) => {
+            return Err(invalid(format!("{}: {}", path, err)));
+        },
+    };
+    for item in items {
+        match item {
+            rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::ECKey(key) => {
+                return Ok(PrivateKey(key));
+            },
+            _ => {},
+        }
+    }
+    Err(invalid(format!("{}: no private key found", path)))
+}
+
+/// Parse a hex encoded SHA-256 fingerprint, ignoring colons.
+pub fn parse_fingerprint(pin: &str) -> Result<[u8; 32], Error> {
+    match hex::decode(pin.replace(':', "")) {
+        Ok(bytes) => match bytes.try_into() {
+            Ok(pin) => Ok(pin),
+            Err(_) => Err(invalid("certificate pins must be SHA-256")),
+        },
+        Err(_) => Err(invalid("certificate pins must be hex encoded")),
+    }
+}
+
+/// Describe an invalid TLS setting.
+fn invalid<S: Into<String>>(msg: S) -> Error {
+    Error::Config(msg.into())
+}
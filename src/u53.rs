@@ -13,7 +13,7 @@ use std::hash::Hash;
 /// it is the largest sized integer possible in Javascript without losing
 /// precision.
 #[allow(non_camel_case_types)]
-#[derive(Copy, Clone, Hash)]
+#[derive(Copy, Clone, Hash, PartialEq, Eq)]
 pub struct u53(u64);
 
 impl From<u64> for u53 {
@@ -42,7 +42,7 @@ impl From<u8> for u53 {
 
 impl From<i64> for u53 {
     fn from(v: i64) -> Self {
-        u53((v as u64).into())
+        u53(v as u64)
     }
 }
 
@@ -75,7 +75,7 @@ impl Serialize for u53 {
         &self,
         serializer: S
     ) -> Result<S::Ok, S::Error> where S: Serializer {
-        serializer.serialize_u64(self.clone().into())
+        serializer.serialize_u64((*self).into())
     }
 }
 
@@ -143,14 +143,6 @@ impl<'de> Deserialize<'de> for u53 {
     }
 }
 
-impl Eq for u53 {}
-
-impl PartialEq for u53 {
-    fn eq(&self, other: &u53) -> bool {
-        self.0 == other.0
-    }
-}
-
 impl std::fmt::Display for u53 {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.0)
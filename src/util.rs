@@ -5,20 +5,31 @@ use hyper::{
     Response,
     Request,
     StatusCode,
-    body::Bytes,
+    body::{Bytes, to_bytes},
     client::connect::HttpConnector,
 };
-use serde::{Serialize, Deserialize};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use rustls::{ClientConfig, RootCertStore};
+use serde::{Serialize, Deserialize, de::DeserializeOwned};
+use serde_json::Value;
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::{
+    error::{Error, ErrorBody},
+    peer::Credentials,
+    tls::ClientTls,
+};
 
-pub fn from_bytes<'a, T: Deserialize<'a>, E>(
-    bytes: &'a Result<Bytes, E>,
+/// Deserialize a velocypack body.
+///
+/// The body is decoded into a JSON value first, since velocypack cannot
+/// deserialize options, tuples or enums directly.
+pub fn from_bytes<T: DeserializeOwned, E>(
+    bytes: &Result<Bytes, E>,
 ) -> Option<T> {
     match bytes {
         Ok(bytes) => {
-            match velocypack::from_bytes(&bytes[..]) {
-                Ok(res) => Some(res),
-                Err(_) => None,
-            }
+            let value = velocypack::from_bytes::<Value>(&bytes[..]).ok()?;
+            serde_json::from_value(value).ok()
         }
         Err(_) => None,
     }
@@ -29,102 +40,178 @@ pub fn from_json<'a, T: Deserialize<'a>, E>(
 ) -> Option<T> {
     match bytes {
         Ok(bytes) => match std::str::from_utf8(&bytes[..]) {
-            Ok(json) => match serde_json::from_str(json) {
-                Ok(res) => Some(res),
-                Err(_) => None
-            },
+            Ok(json) => serde_json::from_str(json).ok(),
             Err(_) => None,
         }
         Err(_) => None,
     }
 }
 
+/// The current time in milliseconds since the Unix epoch.
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+/// Build the response for an error.
+pub fn error_response(err: Error) -> Response<Body> {
+    let status = err.status();
+    let body = serde_json::to_string(&ErrorBody::from(err)).unwrap();
+    let mut resp = Response::new(body.into());
+    *resp.status_mut() = status;
+    resp
+}
+
+#[derive(Clone)]
 pub struct Client {
-    client: HyperClient<HttpConnector, Body>,
+    client: HyperClient<HttpsConnector<HttpConnector>, Body>,
     base: String,
+    credentials: Option<Credentials>,
 }
 
 impl Client {
 
     pub fn new(host: &str) -> Client {
+        // Plain HTTP does not use the TLS configuration, so it trusts
+        // nothing.
+        let config = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(RootCertStore::empty())
+            .with_no_client_auth();
+        Self::build("http://", host, config)
+    }
+
+    /// Create a client that connects over TLS.
+    pub fn with_tls(host: &str, tls: &ClientTls) -> Client {
+        Self::build("https://", host, tls.config())
+    }
+
+    /// Internal helper to create a client for a scheme.
+    fn build(scheme: &str, host: &str, config: ClientConfig) -> Client {
+        let connector = HttpsConnectorBuilder::new()
+            .with_tls_config(config)
+            .https_or_http()
+            .enable_http1()
+            .build();
         let mut base = String::new();
-        base.push_str("http://");
+        base.push_str(scheme);
         base.push_str(host);
         Client {
-            client: HyperClient::new(), 
-            base: base,
+            client: HyperClient::builder().build(connector),
+            base,
+            credentials: None,
         }
     }
 
-    pub async fn get(
-        &mut self,
-        path: &str
-    ) -> Result<Response<Body>, &'static str> {
+    /// Prefix the path of every request, e.g. with `tenant::prefix` to
+    /// send requests to a tenant.
+    pub fn with_prefix(self, prefix: &str) -> Client {
+        let mut base = self.base;
+        base.push_str(prefix);
+        Client {
+            base,
+            ..self
+        }
+    }
+
+    /// Sign requests with a service's credentials, as the Authority and the
+    /// Worker require.
+    pub fn with_credentials(self, credentials: Credentials) -> Client {
+        Client {
+            credentials: Some(credentials),
+            ..self
+        }
+    }
+
+    /// Internal helper to send a request.
+    ///
+    /// Responses other than 200 OK are turned into the error described by
+    /// their body, or into `Error::Upstream` if the body does not describe
+    /// one.
+    async fn send(
+        &self,
+        method: Method,
+        path: &str,
+        body: Vec<u8>,
+    ) -> Result<Response<Body>, Error> {
         let mut uri = self.base.clone();
         uri.push_str(path);
-        let req = Request::builder()
-            .method(Method::GET)
-            .uri(uri)
-            .body(Body::empty())
-            .unwrap();
-        match self.client.request(req).await {
-            Ok(resp) => {
-                match resp.status() {
-                    StatusCode::OK => Ok(resp),
-                    x => Err(x.canonical_reason().unwrap()),
+        let mut req = Request::builder()
+            .method(method)
+            .uri(uri);
+        if let Some(credentials) = &self.credentials {
+            req = credentials.sign(req, &body);
+        }
+        let req = req.body(Body::from(body)).unwrap();
+        let resp = match self.client.request(req).await {
+            Ok(resp) => resp,
+            Err(err) => {
+                return Err(Error::Transport(err.to_string()));
+            },
+        };
+        match resp.status() {
+            StatusCode::OK => Ok(resp),
+            x => {
+                let bytes = to_bytes(resp.into_body()).await;
+                match from_json::<ErrorBody, _>(&bytes) {
+                    Some(body) => Err(body.error),
+                    None => Err(Error::Upstream(x.as_u16())),
                 }
             },
-            _ => Err("request error"),
         }
     }
 
+    pub async fn get(
+        &self,
+        path: &str
+    ) -> Result<Response<Body>, Error> {
+        self.send(Method::GET, path, Vec::new()).await
+    }
+
     pub async fn post<T>(
-        &mut self,
+        &self,
         path: &str,
         body: T,
-    ) -> Result<Response<Body>, &'static str>
+    ) -> Result<Response<Body>, Error>
     where T: Serialize {
-        let mut uri = self.base.clone();
-        uri.push_str(path);
         let data = velocypack::to_bytes(&body).unwrap();
-        let req = Request::builder()
-            .method(Method::POST)
-            .uri(uri)
-            .body(Body::from(data))
-            .unwrap();
-        match self.client.request(req).await {
-            Ok(resp) => {
-                match resp.status() {
-                    StatusCode::OK => Ok(resp),
-                    x => Err(x.canonical_reason().unwrap()),
-                }
-            },
-            _ => Err("request error"),
-        }
+        self.send(Method::POST, path, data).await
     }
 
     pub async fn put<T>(
-        &mut self,
+        &self,
         path: &str,
         body: T,
-    ) -> Result<Response<Body>, &'static str>
+    ) -> Result<Response<Body>, Error>
     where T: Serialize {
-        let mut uri = self.base.clone();
-        uri.push_str(path);
         let data = velocypack::to_bytes(&body).unwrap();
-        let req = Request::builder()
-            .method(Method::PUT)
-            .uri(uri)
-            .body(Body::from(data))
-            .unwrap();
-        match self.client.request(req).await {
-            Ok(resp) => {
-                match resp.status() {
-                    StatusCode::OK => Ok(resp),
-                    x => Err(x.canonical_reason().unwrap()),
-                }
-            },
-            _ => Err("request error"),
-        }
+        self.send(Method::PUT, path, data).await
+    }
+
+    pub async fn delete<T>(
+        &self,
+        path: &str,
+        body: T,
+    ) -> Result<Response<Body>, Error>
+    where T: Serialize {
+        let data = velocypack::to_bytes(&body).unwrap();
+        self.send(Method::DELETE, path, data).await
+    }
+
+    /// Send a request with a JSON body.
+    ///
+    /// The services exchange velocypack with each other, but the
+    /// Synchronizer's public API speaks JSON.
+    pub async fn send_json<T>(
+        &self,
+        method: Method,
+        path: &str,
+        body: T,
+    ) -> Result<Response<Body>, Error>
+    where T: Serialize {
+        let data = serde_json::to_vec(&body).unwrap();
+        self.send(method, path, data).await
     }
 }
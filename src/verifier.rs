@@ -0,0 +1,242 @@
+use clacc::{
+    Accumulator,
+    BigInt,
+    Map as _,
+    Witness,
+    sha3::Shake128 as Map,
+};
+use gmp::mpz::Mpz;
+use hyper::body::to_bytes;
+use std::sync::{Arc, RwLock};
+use tokio::{task::JoinHandle, time::{interval, Duration}};
+use crate::{
+    checkpoint::{Checkpoint, PublicKey, SignedCheckpoint},
+    condition::{self, Attributes},
+    error::Error,
+    matcher,
+    permission::Permission,
+    subject::{Proof, ProofChecker},
+    tls::ClientTls,
+    util::{from_json, now_millis, Client},
+};
+
+/// Ensure a Witness's nonce is the canonical one for a Permission.
+///
+/// The Witness's nonce, unlike the Permission's Nonce, is not assigned by
+/// the Authority: it is the distance from the Permission's hash to the next
+/// prime, which the Accumulator adds to the hash to map the Permission to a
+/// prime. The Accumulator does not check that the nonce is this distance,
+/// and without this check a holder of one valid Witness could choose the
+/// nonce given with a forged Permission so that both map to the same prime.
+pub fn verify_nonce(
+    perm: &Permission,
+    witness: &Witness<Mpz>,
+) -> Result<(), Error> {
+    let x = Mpz::from_bytes_be(&<Map>::map(perm.clone()));
+    if witness.nonce != x.next_prime() - x {
+        return Err(Error::WitnessVerification);
+    }
+    Ok(())
+}
+
+/// Verify that a Permission is a member of the accumulation of `acc`.
+///
+/// The Witness must prove the Permission's membership, and its nonce must
+/// be the canonical one for the Permission, as checked by `verify_nonce`.
+pub fn verify_membership(
+    acc: &Accumulator<Mpz, Map>,
+    perm: &Permission,
+    witness: &Witness<Mpz>,
+) -> Result<(), Error> {
+    verify_nonce(perm, witness)?;
+    // Verify the Permission is part of the accumulation.
+    if acc.verify(perm.clone(), witness.clone()).is_err() {
+        return Err(Error::WitnessVerification);
+    }
+    Ok(())
+}
+
+/// Verify that a Permission allows an action.
+///
+/// The Permission must be a member of the accumulation of `acc`, as checked
+/// by `verify_membership`. It must also be within its validity period, if
+/// it has one, and the request's attributes must meet the Permission's
+/// conditions on the action.
+pub fn verify(
+    acc: &Accumulator<Mpz, Map>,
+    perm: &Permission,
+    witness: &Witness<Mpz>,
+    action: &str,
+    attributes: &Attributes,
+) -> Result<(), Error> {
+    verify_membership(acc, perm, witness)?;
+    check(perm, action, attributes)
+}
+
+/// Check that a Permission whose membership has been verified allows an
+/// action.
+///
+/// This is the part of `verify` that does not depend on the Witness, so
+/// that several actions can be checked against one verified Permission.
+pub fn check(
+    perm: &Permission,
+    action: &str,
+    attributes: &Attributes,
+) -> Result<(), Error> {
+    // Ensure the Permission is within its validity period.
+    perm.check_validity(now_millis())?;
+    // Ensure the requested action is allowed by the actions list and the
+    // Permission's roles.
+    matcher::authorize(perm.all_actions(), action)?;
+    // Ensure the request meets the conditions on the action.
+    condition::evaluate(&perm.conditions, action, attributes)
+}
+
+/// A Verifier checks actions locally against the accumulation value
+/// published by the Synchronizer.
+///
+/// Relying parties that embed a Verifier take the Authority off the request
+/// path: the owner of a Permission fetches its Witness from the Synchronizer
+/// and presents both with each request, and the Verifier checks them
+/// without any network access. The published value is refreshed by
+/// polling, so a Permission that was updated or revoked in the last update
+/// window keeps verifying until the next refresh, and a new one only starts
+/// verifying after it.
+///
+/// The value is taken from the Checkpoint published by the Synchronizer,
+/// which must be signed by the Authority. Checkpoints for an earlier window
+/// than the current one are rejected, as are Checkpoints for another
+/// Accumulator unless they are for a later window, as after a key rotation.
+///
+/// Permissions bound to a subject are only verified with a proof signed by
+/// the subject, and each proof is only accepted once by a Verifier.
+pub struct Verifier {
+    client: Client,
+    signing_key: PublicKey,
+    current: RwLock<Current>,
+    proofs: ProofChecker,
+}
+
+/// The Checkpoint a Verifier currently verifies against.
+struct Current {
+    window: u64,
+    acc: Arc<Accumulator<Mpz, Map>>,
+}
+
+impl Verifier {
+
+    /// Create a Verifier from the Checkpoint published by the Synchronizer
+    /// at an address such as "127.0.0.1:3000".
+    ///
+    /// The Authority's signing key should be obtained out of band, e.g.
+    /// from the operator's configuration, rather than from the Synchronizer.
+    pub async fn new(
+        addr: &str,
+        signing_key: PublicKey,
+    ) -> Result<Self, Error> {
+        Self::with_client(Client::new(addr), signing_key).await
+    }
+
+    /// Create a Verifier like `new`, but from a Synchronizer that serves
+    /// TLS.
+    pub async fn with_tls(
+        addr: &str,
+        tls: &ClientTls,
+        signing_key: PublicKey,
+    ) -> Result<Self, Error> {
+        Self::with_client(Client::with_tls(addr, tls), signing_key).await
+    }
+
+    /// Create a Verifier that fetches Checkpoints with a client, e.g. one
+    /// whose requests are prefixed with `tenant::prefix` to verify the
+    /// Permissions of a tenant.
+    pub async fn with_client(
+        client: Client,
+        signing_key: PublicKey,
+    ) -> Result<Self, Error> {
+        let checkpoint = Self::fetch(&client, &signing_key).await?;
+        let mut acc = Accumulator::<Mpz, Map>::with_public_key(checkpoint.key);
+        acc.set_value(checkpoint.verifying);
+        Ok(Verifier {
+            client,
+            proofs: ProofChecker::new(&signing_key),
+            signing_key,
+            current: RwLock::new(Current {
+                window: checkpoint.window,
+                acc: Arc::new(acc),
+            }),
+        })
+    }
+
+    /// Internal helper to fetch and verify the published Checkpoint.
+    async fn fetch(
+        client: &Client,
+        signing_key: &PublicKey,
+    ) -> Result<Checkpoint, Error> {
+        let resp = client.get("/checkpoint").await?;
+        let bytes = to_bytes(resp.into_body()).await;
+        let checkpoint: SignedCheckpoint = match from_json(&bytes) {
+            Some(res) => res,
+            None => {
+                return Err(Error::Decode);
+            },
+        };
+        checkpoint.verify(signing_key).cloned()
+    }
+
+    /// Refresh the published value.
+    pub async fn refresh(&self) -> Result<(), Error> {
+        let checkpoint = Self::fetch(&self.client, &self.signing_key).await?;
+        let mut current = self.current.write().unwrap();
+        // Ensure the Checkpoint does not roll the value back to an earlier
+        // window. A key rotation takes effect at the start of a window, so
+        // the key may only change along with it.
+        if checkpoint.window < current.window
+            || (checkpoint.key != current.acc.get_public_key()
+                && checkpoint.window == current.window) {
+            return Err(Error::InvalidCheckpoint);
+        }
+        let mut acc = Accumulator::<Mpz, Map>::with_public_key(checkpoint.key);
+        acc.set_value(checkpoint.verifying);
+        *current = Current {
+            window: checkpoint.window,
+            acc: Arc::new(acc),
+        };
+        Ok(())
+    }
+
+    /// Start a task that refreshes the published value periodically.
+    ///
+    /// The period should be no longer than the update window so that
+    /// changes are picked up within a window of being made. Failed
+    /// refreshes are reported and retried at the next tick.
+    pub fn watch(self: Arc<Self>, period: Duration) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut ticks = interval(period);
+            // The first tick completes immediately. Get it out of the way.
+            ticks.tick().await;
+            loop {
+                ticks.tick().await;
+                if let Err(err) = self.refresh().await {
+                    log::error!("verifier refresh failed: {}", err);
+                }
+            }
+        })
+    }
+
+    /// Verify that a Permission allows an action with the given request
+    /// attributes, along with the proof of its subject if it is bound to
+    /// one.
+    pub fn verify(
+        &self,
+        perm: &Permission,
+        witness: &Witness<Mpz>,
+        action: &str,
+        attributes: &Attributes,
+        proof: Option<&Proof>,
+    ) -> Result<(), Error> {
+        let acc = Arc::clone(&self.current.read().unwrap().acc);
+        verify(&acc, perm, witness, action, attributes)?;
+        self.proofs.check(perm, action, attributes, proof)
+    }
+}
@@ -7,22 +7,143 @@ use clacc::{
 use gmp::mpz::Mpz;
 use crossbeam::thread;
 use num_cpus;
+use serde::{Serialize, Deserialize};
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
+    fs,
+    path::PathBuf,
     sync::{Arc, Mutex as StdMutex},
 };
 use tokio::sync::Mutex;
 use crate::{
+    checkpoint::{
+        PublicKey,
+        SignedCheckpoint,
+        format_public_key,
+        parse_public_key,
+    },
+    error::Error,
     permission::{Nonce, Permission},
-    request::UpdateResponse,
+    request::{
+        BatchUpdateResponse,
+        DelegatedPermission,
+        DeleteResponse,
+        KeyRequest,
+        UpdateResponse,
+    },
+    role::Role,
+    store::{self, Log},
+    verifier,
 };
 
 /// Type for a map where Nonces map to Permission-Witness pairs.
 type PermissionMap = HashMap<Nonce, (Permission, Witness<Mpz>)>;
 
-/// A Worker that absorbs new and update Permissions during a window and can
-/// perform a batched Update on a set of Witnesses.
-pub struct Worker {
+/// An update absorbed into the update window.
+#[derive(Serialize, Deserialize, Clone)]
+enum Operation {
+    Add(Permission),
+    Update(UpdateResponse),
+    Delete(DeleteResponse),
+    AddBatch(Vec<Permission>),
+    UpdateBatch(BatchUpdateResponse),
+}
+
+impl Operation {
+
+    /// The sequence number of the Checkpoint the Operation carries, if any.
+    fn checkpoint_seq(&self) -> Option<u64> {
+        match self {
+            Operation::Add(_) => None,
+            Operation::Update(res) => Some(res.checkpoint.checkpoint.seq),
+            Operation::Delete(res) => Some(res.checkpoint.checkpoint.seq),
+            Operation::AddBatch(_) => None,
+            Operation::UpdateBatch(res) => {
+                Some(res.checkpoint.checkpoint.seq)
+            },
+        }
+    }
+}
+
+/// An Operation recorded in the write-ahead log.
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    seq: u64,
+    op: Operation,
+}
+
+/// The Worker's state at the time of the latest `sync`.
+#[derive(Serialize, Deserialize)]
+struct Snapshot {
+
+    /// The sequence number of the last log entry reflected in the snapshot.
+    seq: u64,
+
+    /// The accumulation value that the Witnesses in `perms` are valid for.
+    value: Mpz,
+
+    /// The synchronized Permission-Witness pairs.
+    perms: Vec<(Permission, Witness<Mpz>)>,
+
+    /// The Operations absorbed since the update process that produced
+    /// `perms` was started.
+    pending: Vec<Operation>,
+
+    /// The sequence number of the last Checkpoint that was accepted.
+    #[serde(default)]
+    checkpoint_seq: u64,
+}
+
+/// Durable storage for a Worker's state.
+///
+/// Absorbed Operations are appended to a write-ahead log before they are
+/// applied. Every `sync` snapshots the synchronized Permissions map along
+/// with the Operations that have not been applied to it yet, after which
+/// the log is cleared. If the process dies before the next `sync`, the
+/// Operations are absorbed again on recovery and the interrupted update is
+/// simply performed as part of the next one.
+struct Storage {
+    dir: PathBuf,
+    log: Log<Entry>,
+    seq: u64,
+
+    /// The Operations absorbed since the current update process started.
+    pending: Vec<Operation>,
+}
+
+impl Storage {
+
+    /// Durably record an Operation.
+    fn append(&mut self, op: Operation) -> Result<(), Error> {
+        self.log.append(&Entry { seq: self.seq + 1, op: op.clone() })?;
+        self.seq += 1;
+        self.pending.push(op);
+        Ok(())
+    }
+
+    /// Durably record the synchronized state and clear the log.
+    fn snapshot(
+        &mut self,
+        value: &Mpz,
+        perms: &PermissionMap,
+        checkpoint_seq: u64,
+    ) -> Result<(), Error> {
+        store::save(&self.dir.join("snapshot.json"), &Snapshot {
+            seq: self.seq,
+            value: value.clone(),
+            perms: perms.values().cloned().collect(),
+            pending: self.pending.clone(),
+            checkpoint_seq,
+        })?;
+        // Entries up to `seq` are skipped on recovery, so a crash before the
+        // log is cleared is harmless.
+        self.log.clear()
+    }
+}
+
+/// The state of a Worker that is used to absorb updates during the current
+/// update window.
+struct Absorbing {
 
     /// The value of the Accumulator before any updates absorbed during the
     /// current window have been applied.
@@ -42,67 +163,386 @@ pub struct Worker {
     /// update window.
     additions: PermissionMap,
 
+    /// The Nonces of Permissions that will be deleted during the current
+    /// update window.
+    deletions: HashSet<Nonce>,
+
     /// The current map of Permission-Witness pairs.
     perms: PermissionMap,
 
+    /// The public key the Authority signs Checkpoints with. Accumulation
+    /// values are only accepted if they are covered by a Checkpoint signed
+    /// with it.
+    signing_key: Option<PublicKey>,
+
+    /// The sequence number of the last Checkpoint that was accepted. Older
+    /// Checkpoints are rejected so that they cannot be replayed.
+    checkpoint_seq: u64,
+
+    /// The durable storage, if the Worker was opened from a directory.
+    storage: Option<Storage>,
+}
+
+/// The state of a Worker that is used by the update process.
+struct Updating {
+
     /// The additions that are having their initial witnesses calculated
     /// during the update process.
-    updating_additions: PermissionMap,
+    additions: PermissionMap,
 
     /// The permissions that are having their witnesses updated during the
     /// update process.
-    updating_perms: PermissionMap,
+    perms: PermissionMap,
+
+    /// Whether Witnesses have been updated without being synchronized.
+    updated: bool,
+}
+
+/// A key rotation that the Worker is recomputing Witnesses for.
+struct Rotation {
+
+    /// The Authority's Checkpoint of the new Accumulator.
+    checkpoint: SignedCheckpoint,
+
+    /// The recomputed Permission-Witness pairs, or the reason they could
+    /// not be recomputed, once the recomputation has finished.
+    result: Option<Result<PermissionMap, String>>,
+}
+
+/// The progress of recomputing Witnesses for a key rotation.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RotationStatus {
+
+    /// Whether a rotation has been started and not committed or aborted.
+    pub rotating: bool,
 
-    /// Mutex locked during updates to the Accumulator.
-    guard_acc: Mutex<()>,
+    /// Whether every Witness has been recomputed against the new key.
+    pub ready: bool,
 
-    /// Mutex locked while the Worker is in the process of updating Witnesses.
-    guard_update: Mutex<()>,
+    /// The reason the Witnesses could not be recomputed, if they could not.
+    pub error: Option<String>,
+}
+
+/// A Worker that absorbs new and update Permissions during a window and can
+/// perform a batched Update on a set of Witnesses.
+///
+/// All methods take `&self`, so a Worker may be shared between tasks with an
+/// `Arc`.
+pub struct Worker {
+
+    /// The state used to absorb updates, locked during updates to the
+    /// Accumulator.
+    absorbing: Mutex<Absorbing>,
+
+    /// The state used by the update process, locked while the Worker is in
+    /// the process of updating Witnesses.
+    updating: Mutex<Updating>,
+
+    /// The key rotation in progress, if any.
+    rotation: Mutex<Option<Rotation>>,
+}
+
+impl Default for Worker {
+    fn default() -> Self {
+        Self::new(None)
+    }
 }
 
 impl Worker {
 
-    /// Create a new Worker using the public key returned from the Authority.
-    pub fn new() -> Self {
+    /// Create a new Worker.
+    ///
+    /// The Worker only accepts Checkpoints signed with the Authority's
+    /// signing key. Without one it cannot be keyed.
+    pub fn new(signing_key: Option<PublicKey>) -> Self {
         // Allocate Worker.
         Worker {
-            value: 0.into(),
-            acc: None,
-            update: Update::new(),
-            additions: HashMap::new(),
-            perms: HashMap::new(),
-            updating_additions: HashMap::new(),
-            updating_perms: HashMap::new(),
-            guard_acc: Mutex::new(()),
-            guard_update: Mutex::new(()),
+            absorbing: Mutex::new(Absorbing {
+                value: 0.into(),
+                acc: None,
+                update: Update::new(),
+                additions: HashMap::new(),
+                deletions: HashSet::new(),
+                perms: HashMap::new(),
+                signing_key,
+                checkpoint_seq: 0,
+                storage: None,
+            }),
+            updating: Mutex::new(Updating {
+                additions: HashMap::new(),
+                perms: HashMap::new(),
+                updated: false,
+            }),
+            rotation: Mutex::new(None),
         }
     }
 
+    /// Open a Worker whose state is persisted in a directory.
+    ///
+    /// If the directory holds a public key, the Accumulator is allocated
+    /// from it and the Permissions map is restored from the latest snapshot.
+    /// Operations logged since are absorbed into the current update window
+    /// again, including those of an update process that was interrupted.
+    ///
+    /// A signing key that was pinned when the directory was first keyed
+    /// must match the one given.
+    pub fn open(
+        dir: &str,
+        signing_key: Option<PublicKey>,
+    ) -> Result<Self, Error> {
+        let dir = PathBuf::from(dir);
+        if fs::create_dir_all(&dir).is_err() {
+            return Err(Error::Storage(
+                "could not create data directory".to_owned(),
+            ));
+        }
+        let worker = Self::new(signing_key);
+        let (log, entries) = Log::<Entry>::open(&dir.join("wal.jsonl"))?;
+        let mut storage = Storage {
+            dir: dir.clone(),
+            log,
+            seq: 0,
+            pending: Vec::new(),
+        };
+        // The Worker has not been shared yet, so its state may be accessed
+        // directly.
+        let mut absorbing = worker.absorbing.into_inner();
+        let mut updating = worker.updating.into_inner();
+        // Ensure the signing key that was pinned when the Worker was keyed
+        // is still the one given. A stored key is never trusted on its own.
+        let stored: Option<String> = store::load(
+            &dir.join("signing_key.json"),
+        )?;
+        if let Some(stored) = stored {
+            let stored = parse_public_key(&stored)?;
+            if absorbing.signing_key != Some(stored) {
+                return Err(Error::Storage(
+                    "signing key does not match the configured key".to_owned(),
+                ));
+            }
+        }
+        // Without a public key nothing can have been absorbed yet.
+        let key: Mpz = match store::load(&dir.join("key.json"))? {
+            Some(key) => key,
+            None => {
+                absorbing.storage = Some(storage);
+                return Ok(Worker {
+                    absorbing: Mutex::new(absorbing),
+                    updating: Mutex::new(updating),
+                    rotation: Mutex::new(None),
+                });
+            },
+        };
+        let mut acc = Accumulator::<Mpz, Map>::with_public_key(key);
+        // Restore the synchronized state from the latest snapshot.
+        let mut ops = Vec::new();
+        let snapshot: Option<Snapshot> = store::load(
+            &dir.join("snapshot.json"),
+        )?;
+        if let Some(snapshot) = snapshot {
+            acc.set_value(snapshot.value);
+            for pair in snapshot.perms {
+                updating.perms.insert(pair.0.nonce, pair);
+            }
+            absorbing.perms = updating.perms.clone();
+            storage.seq = snapshot.seq;
+            absorbing.checkpoint_seq = snapshot.checkpoint_seq;
+            ops = snapshot.pending;
+        }
+        absorbing.value = acc.get_value();
+        // Collect the Operations that were logged after the snapshot.
+        for entry in entries {
+            if entry.seq <= storage.seq {
+                continue;
+            }
+            ops.push(entry.op);
+            storage.seq = entry.seq;
+        }
+        // Absorb them into the current update window. Their Checkpoints were
+        // verified before they were logged.
+        for op in ops.iter() {
+            if let Some(seq) = op.checkpoint_seq() {
+                absorbing.checkpoint_seq = seq;
+            }
+            Self::absorb(
+                op.clone(),
+                &absorbing.value,
+                &mut acc,
+                &mut absorbing.update,
+                &mut absorbing.additions,
+                &mut absorbing.deletions,
+            );
+        }
+        storage.pending = ops;
+        absorbing.acc = Some(acc);
+        absorbing.storage = Some(storage);
+        Ok(Worker {
+            absorbing: Mutex::new(absorbing),
+            updating: Mutex::new(updating),
+            rotation: Mutex::new(None),
+        })
+    }
+
     /// Submit the Authority's public key.
     ///
     /// This allocates the Worker's Accumulator and allows the other methods
-    /// to be called successfully. If there is already an Accumulator
-    /// allocated, this method returns an error.
+    /// to be called successfully. The key is taken from a Checkpoint, which
+    /// must be signed with the Authority's signing key the Worker was
+    /// created with. If no signing key was given, or there is already an
+    /// Accumulator allocated for a different key, this method returns an
+    /// error.
     pub async fn set_key(
-        &mut self,
-        key: Mpz,
-    ) -> Result<(), &'static str> {
+        &self,
+        req: KeyRequest,
+    ) -> Result<(), Error> {
         // Lock the Accumulator Mutex.
-        let _guard_acc = self.guard_acc.lock().await;
-        // Error out if there is already an Accumulator allocated.
-        match self.acc {
-            Some(_) => Err("already have public key"),
+        let mut guard = self.absorbing.lock().await;
+        let absorbing = &mut *guard;
+        // Error out unless a signing key is pinned. A key submitted along
+        // with the Checkpoint could be anyone's.
+        let signing_key = match absorbing.signing_key {
+            Some(key) => key,
             None => {
+                return Err(Error::MissingSigningKey);
+            },
+        };
+        // Take the public key from the Checkpoint.
+        let key = req.checkpoint.verify(&signing_key)?.key.clone();
+        // Error out if there is already an Accumulator allocated for a
+        // different key. Submitting the same key again is allowed so that a
+        // restarted Synchronizer can key a Worker that restored its state.
+        match &absorbing.acc {
+            Some(acc) if acc.get_public_key() == key => Ok(()),
+            Some(_) => Err(Error::KeyAlreadySet),
+            None => {
+                // Persist the keys.
+                if let Some(storage) = &absorbing.storage {
+                    store::save(
+                        &storage.dir.join("signing_key.json"),
+                        &format_public_key(&signing_key),
+                    )?;
+                    store::save(&storage.dir.join("key.json"), &key)?;
+                }
                 // Allocate new Accumulator initialized from the Authority's
                 // public key.
                 let acc = Accumulator::<Mpz, Map>::with_public_key(key);
-                self.value = acc.get_value().clone();
-                self.acc = Some(acc);
+                absorbing.value = acc.get_value().clone();
+                absorbing.acc = Some(acc);
                 Ok(())
             }
         }
     }
 
+    /// Internal helper to accept the Checkpoint of an updated or deleted
+    /// Permission.
+    ///
+    /// The Checkpoint must be signed with the trusted signing key, be for
+    /// the Worker's Accumulator and be newer than any Checkpoint accepted
+    /// before.
+    fn accept(
+        absorbing: &mut Absorbing,
+        checkpoint: &SignedCheckpoint,
+    ) -> Result<(), Error> {
+        let signing_key = match &absorbing.signing_key {
+            Some(key) => key,
+            None => {
+                return Err(Error::MissingPublicKey);
+            },
+        };
+        let checkpoint = checkpoint.verify(signing_key)?;
+        let key = match &absorbing.acc {
+            Some(acc) => acc.get_public_key(),
+            None => {
+                return Err(Error::MissingPublicKey);
+            },
+        };
+        if checkpoint.key != key || checkpoint.seq <= absorbing.checkpoint_seq {
+            return Err(Error::InvalidCheckpoint);
+        }
+        absorbing.checkpoint_seq = checkpoint.seq;
+        Ok(())
+    }
+
+    /// Internal helper to absorb an Operation into the update window.
+    ///
+    /// This code is reused by the methods that absorb updates and by `open`
+    /// when recovering the update window. It is assumed that the caller has
+    /// locked a Mutex so that operations on the Accumulator are thread safe.
+    fn absorb(
+        op: Operation,
+        value: &Mpz,
+        acc: &mut Accumulator<Mpz, Map>,
+        update: &mut Update<Mpz, Map>,
+        additions: &mut PermissionMap,
+        deletions: &mut HashSet<Nonce>,
+    ) {
+        match op {
+            Operation::Add(perm) => {
+                // Use the helper to add the Permission.
+                Self::add_permission_internal(
+                    perm,
+                    value,
+                    acc,
+                    update,
+                    additions,
+                );
+            },
+            Operation::Update(res) => {
+                // Absorb the deletion into the batched Update.
+                update.del(res.req.perm.clone(), res.req.witness.clone());
+                // Use the helper to add the Permission.
+                Self::add_permission_internal(
+                    res.req.update,
+                    value,
+                    acc,
+                    update,
+                    additions,
+                );
+                // Synchronize the Worker's accumulation with the
+                // Authority's. Note that the Worker can't call
+                // Accumulator.del because it does not have the private key.
+                acc.set_value(res.checkpoint.checkpoint.staging);
+            },
+            Operation::Delete(res) => {
+                // Absorb the deletion into the batched Update.
+                update.del(res.req.perm.clone(), res.req.witness);
+                // Remember the Nonce so that its pair is not carried into the
+                // next Permissions map.
+                deletions.insert(res.req.perm.nonce);
+                // Synchronize the Worker's accumulation with the
+                // Authority's.
+                acc.set_value(res.checkpoint.checkpoint.staging);
+            },
+            Operation::AddBatch(perms) => {
+                // Use the helper to add each Permission.
+                for perm in perms {
+                    Self::add_permission_internal(
+                        perm,
+                        value,
+                        acc,
+                        update,
+                        additions,
+                    );
+                }
+            },
+            Operation::UpdateBatch(res) => {
+                // Absorb each update like a single one, then synchronize
+                // the accumulation once with the value after all of them.
+                for req in res.reqs {
+                    update.del(req.perm, req.witness);
+                    Self::add_permission_internal(
+                        req.update,
+                        value,
+                        acc,
+                        update,
+                        additions,
+                    );
+                }
+                acc.set_value(res.checkpoint.checkpoint.staging);
+            },
+        }
+    }
+
     /// Internal helper to add a new permission.
     ///
     /// This code is reused by `add_permission` and `update_permission`.
@@ -127,25 +567,64 @@ impl Worker {
 
     /// Absorb a new Permission into the update window.
     pub async fn add_permission(
-        &mut self,
+        &self,
         perm: Permission,
-    ) -> Result<(), &'static str> {
+    ) -> Result<(), Error> {
+        // Lock the Accumulator Mutex.
+        let mut guard = self.absorbing.lock().await;
+        let absorbing = &mut *guard;
+        // Error out if there is no Accumulator allocated.
+        let acc = match &mut absorbing.acc {
+            Some(acc) => acc,
+            None => {
+                return Err(Error::MissingPublicKey);
+            },
+        };
+        // Log the Operation and absorb it.
+        let op = Operation::Add(perm);
+        if let Some(storage) = &mut absorbing.storage {
+            storage.append(op.clone())?;
+        }
+        Self::absorb(
+            op,
+            &absorbing.value,
+            acc,
+            &mut absorbing.update,
+            &mut absorbing.additions,
+            &mut absorbing.deletions,
+        );
+        Ok(())
+    }
+
+    /// Absorb a batch of new Permissions into the update window.
+    ///
+    /// The batch is logged as a single Operation.
+    pub async fn add_permissions(
+        &self,
+        perms: Vec<Permission>,
+    ) -> Result<(), Error> {
         // Lock the Accumulator Mutex.
-        let _guard_acc = self.guard_acc.lock().await;
+        let mut guard = self.absorbing.lock().await;
+        let absorbing = &mut *guard;
         // Error out if there is no Accumulator allocated.
-        let acc = match &mut self.acc {
+        let acc = match &mut absorbing.acc {
             Some(acc) => acc,
             None => {
-                return Err("need public key");
+                return Err(Error::MissingPublicKey);
             },
         };
-        // Use the helper to add the Permission.
-        Self::add_permission_internal(
-            perm,
-            &self.value,
+        // Log the Operation and absorb it.
+        let op = Operation::AddBatch(perms);
+        if let Some(storage) = &mut absorbing.storage {
+            storage.append(op.clone())?;
+        }
+        Self::absorb(
+            op,
+            &absorbing.value,
             acc,
-            &mut self.update,
-            &mut self.additions,
+            &mut absorbing.update,
+            &mut absorbing.additions,
+            &mut absorbing.deletions,
         );
         Ok(())
     }
@@ -155,32 +634,117 @@ impl Worker {
     /// This is simply a deletion of the old version and an addition of the
     /// new version.
     pub async fn update_permission(
-        &mut self,
+        &self,
         res: UpdateResponse,
-    ) -> Result<(), &'static str> {
+    ) -> Result<(), Error> {
+        // Ensure the old Permission's Witness carries its canonical nonce
+        // before absorbing its deletion.
+        verifier::verify_nonce(&res.req.perm, &res.req.witness)?;
         // Lock the Accumulator Mutex.
-        let _guard_acc = self.guard_acc.lock().await;
-        // Error out if there is no Accumulator allocated.
-        let acc = match &mut self.acc {
+        let mut guard = self.absorbing.lock().await;
+        let absorbing = &mut *guard;
+        // Error out unless the accumulation value is covered by a valid
+        // Checkpoint.
+        Self::accept(absorbing, &res.checkpoint)?;
+        let acc = match &mut absorbing.acc {
             Some(acc) => acc,
             None => {
-                return Err("need public key");
+                return Err(Error::MissingPublicKey);
             },
         };
-        // Absorb the deletion into the batched Update.
-        self.update.del(res.req.perm.clone(), res.req.witness.clone());
-        // Use the helper to add the Permission.
-        Self::add_permission_internal(
-            res.req.update,
-            &self.value,
+        // Log the Operation and absorb it.
+        let op = Operation::Update(res);
+        if let Some(storage) = &mut absorbing.storage {
+            storage.append(op.clone())?;
+        }
+        Self::absorb(
+            op,
+            &absorbing.value,
             acc,
-            &mut self.update,
-            &mut self.additions
+            &mut absorbing.update,
+            &mut absorbing.additions,
+            &mut absorbing.deletions,
+        );
+        Ok(())
+    }
+
+    /// Absorb a batch of updated Permissions into the update window.
+    ///
+    /// Only the updates the Authority applied are absorbed, under the single
+    /// Checkpoint it signed after applying them.
+    pub async fn update_permissions(
+        &self,
+        res: BatchUpdateResponse,
+    ) -> Result<(), Error> {
+        // Ensure the old Permissions' Witnesses carry their canonical nonces
+        // before absorbing their deletions.
+        for req in &res.reqs {
+            verifier::verify_nonce(&req.perm, &req.witness)?;
+        }
+        // Lock the Accumulator Mutex.
+        let mut guard = self.absorbing.lock().await;
+        let absorbing = &mut *guard;
+        // Error out unless the accumulation value is covered by a valid
+        // Checkpoint.
+        Self::accept(absorbing, &res.checkpoint)?;
+        let acc = match &mut absorbing.acc {
+            Some(acc) => acc,
+            None => {
+                return Err(Error::MissingPublicKey);
+            },
+        };
+        // Log the Operation and absorb it.
+        let op = Operation::UpdateBatch(res);
+        if let Some(storage) = &mut absorbing.storage {
+            storage.append(op.clone())?;
+        }
+        Self::absorb(
+            op,
+            &absorbing.value,
+            acc,
+            &mut absorbing.update,
+            &mut absorbing.additions,
+            &mut absorbing.deletions,
+        );
+        Ok(())
+    }
+
+    /// Absorb a deleted Permission into the update window.
+    ///
+    /// The Permission's Nonce is dropped from the Permissions map when the
+    /// update window is synchronized.
+    pub async fn delete_permission(
+        &self,
+        res: DeleteResponse,
+    ) -> Result<(), Error> {
+        // Ensure the Permission's Witness carries its canonical nonce before
+        // absorbing its deletion.
+        verifier::verify_nonce(&res.req.perm, &res.req.witness)?;
+        // Lock the Accumulator Mutex.
+        let mut guard = self.absorbing.lock().await;
+        let absorbing = &mut *guard;
+        // Error out unless the accumulation value is covered by a valid
+        // Checkpoint.
+        Self::accept(absorbing, &res.checkpoint)?;
+        let acc = match &mut absorbing.acc {
+            Some(acc) => acc,
+            None => {
+                return Err(Error::MissingPublicKey);
+            },
+        };
+        // Log the Operation and absorb it.
+        let op = Operation::Delete(res);
+        if let Some(storage) = &mut absorbing.storage {
+            storage.append(op.clone())?;
+        }
+        Self::absorb(
+            op,
+            &absorbing.value,
+            acc,
+            &mut absorbing.update,
+            &mut absorbing.additions,
+            &mut absorbing.deletions,
         );
-        // Synchronize the Worker's accumulation with the Authority's.
-        // Note that the Worker can't call Accumulator.del because it does not
-        // have the private key.
-        acc.set_value(res.value);
         Ok(())
     }
 
@@ -188,72 +752,225 @@ impl Worker {
     pub async fn witness(
         &self,
         nonce: Nonce,
-    ) -> Result<Option<Witness<Mpz>>, &'static str> {
+    ) -> Result<Option<Witness<Mpz>>, Error> {
         // Lock the Accumulator Mutex to ensure latest Permissions collection
         // is available if called during the update process.
-        let _guard_acc = self.guard_acc.lock().await;
+        let absorbing = self.absorbing.lock().await;
         // Error out if there is no Accumulator allocated.
-        match &self.acc {
+        match &absorbing.acc {
             Some(_) => {},
             None => {
-                return Err("need public key");
+                return Err(Error::MissingPublicKey);
             },
         }
         // Return the Witness stored for the Nonce.
-        match self.perms.get(&nonce) {
+        match absorbing.perms.get(&nonce) {
             Some(pair) => Ok(Some(pair.1.clone())),
             None => Ok(None),
         }
     }
 
+    /// Retrieve the current Witnesses for a list of Nonces, in order.
+    pub async fn witnesses(
+        &self,
+        nonces: Vec<Nonce>,
+    ) -> Result<Vec<Option<Witness<Mpz>>>, Error> {
+        let absorbing = self.absorbing.lock().await;
+        // Error out if there is no Accumulator allocated.
+        if absorbing.acc.is_none() {
+            return Err(Error::MissingPublicKey);
+        }
+        Ok(nonces.iter()
+            .map(|nonce| absorbing.perms.get(nonce).map(|pair| pair.1.clone()))
+            .collect())
+    }
+
+    /// Retrieve the current version of the Permission with a given Nonce.
+    pub async fn permission(
+        &self,
+        nonce: Nonce,
+    ) -> Result<Option<Permission>, Error> {
+        let absorbing = self.absorbing.lock().await;
+        // Error out if there is no Accumulator allocated.
+        if absorbing.acc.is_none() {
+            return Err(Error::MissingPublicKey);
+        }
+        Ok(absorbing.perms.get(&nonce).map(|pair| pair.0.clone()))
+    }
+
+    /// Return the synchronized Permissions that carry an earlier definition
+    /// of one of the given roles.
+    ///
+    /// Like with `expired`, Permissions that have been updated or deleted
+    /// during the current update window are left out.
+    pub async fn outdated(
+        &self,
+        roles: Vec<Role>,
+    ) -> Result<Vec<Permission>, Error> {
+        let absorbing = self.absorbing.lock().await;
+        // Error out if there is no Accumulator allocated.
+        if absorbing.acc.is_none() {
+            return Err(Error::MissingPublicKey);
+        }
+        let versions: HashMap<&str, usize> = roles.iter()
+            .map(|role| (role.name.as_str(), role.version))
+            .collect();
+        let is_outdated = |perm: &Permission| perm.roles.iter().any(|role| {
+            versions.get(role.name.as_str())
+                .is_some_and(|&version| version != role.version)
+        });
+        Ok(absorbing.perms.values()
+            .map(|pair| &pair.0)
+            .filter(|perm| is_outdated(perm))
+            .filter(|perm| !absorbing.additions.contains_key(&perm.nonce))
+            .filter(|perm| !absorbing.deletions.contains(&perm.nonce))
+            .cloned()
+            .collect())
+    }
+
+    /// Return the synchronized Permissions that have expired at a time
+    /// given in milliseconds since the Unix epoch.
+    ///
+    /// Permissions that have been updated or deleted during the current
+    /// update window are left out, since their synchronized Witnesses no
+    /// longer prove their membership of the staging accumulation.
+    pub async fn expired(&self, now: u64) -> Result<Vec<Permission>, Error> {
+        let absorbing = self.absorbing.lock().await;
+        // Error out if there is no Accumulator allocated.
+        if absorbing.acc.is_none() {
+            return Err(Error::MissingPublicKey);
+        }
+        Ok(absorbing.perms.values()
+            .map(|pair| &pair.0)
+            .filter(|perm| perm.is_expired(now))
+            .filter(|perm| !absorbing.additions.contains_key(&perm.nonce))
+            .filter(|perm| !absorbing.deletions.contains(&perm.nonce))
+            .cloned()
+            .collect())
+    }
+
+    /// Return the synchronized Permissions that were delegated, along with
+    /// the current version of their parents.
+    ///
+    /// A parent updated during the current update window is given at its new
+    /// version, and one deleted during it is given as `None`, so that changes
+    /// to a parent cascade to its children in the same window. Like with
+    /// `expired`, children that have themselves been updated or deleted
+    /// during the current update window are left out.
+    pub async fn delegated(&self) -> Result<Vec<DelegatedPermission>, Error> {
+        let absorbing = self.absorbing.lock().await;
+        // Error out if there is no Accumulator allocated.
+        if absorbing.acc.is_none() {
+            return Err(Error::MissingPublicKey);
+        }
+        let current = |nonce: &Nonce| {
+            if absorbing.deletions.contains(nonce) {
+                return None;
+            }
+            absorbing.additions.get(nonce)
+                .or_else(|| absorbing.perms.get(nonce))
+                .map(|pair| pair.0.clone())
+        };
+        Ok(absorbing.perms.values()
+            .map(|pair| &pair.0)
+            .filter(|perm| !absorbing.additions.contains_key(&perm.nonce))
+            .filter(|perm| !absorbing.deletions.contains(&perm.nonce))
+            .filter_map(|perm| {
+                let parent = perm.parent?;
+                Some(DelegatedPermission {
+                    perm: perm.clone(),
+                    parent: current(&parent.nonce),
+                })
+            })
+            .collect())
+    }
+
     /// Perform Witness updates.
     ///
     /// This will block the current thread during the process, however, other
     /// threads may call `add_permission` and `update_permission` to absorb
     /// updates for the next window without adversely affecting the current
     /// update process.
-    pub async fn update(&mut self) -> Result<(), &'static str> {
+    pub async fn update(&self) -> Result<(), Error> {
         // Lock the update Mutex.
-        let _guard_update = self.guard_update.lock().await;
-        // Error out if there is no Accumulator allocated.
-        match self.acc {
-            Some(_) => {},
-            None => {
-                return Err("need public key");
-            },
+        let mut guard = self.updating.lock().await;
+        let updating = &mut *guard;
+        // If the previous update was never synchronized, carry its
+        // additions into the Permissions being updated so that they are not
+        // lost.
+        if updating.updated {
+            for pair in updating.additions.values() {
+                updating.perms.insert(pair.0.nonce, pair.clone());
+            }
         }
         // Cache volatile values that are needed for the update process.
         let acc;
         let update;
+        let deletions;
         {
             // Lock the Accumulator Mutex so that other threads cannot call
             // `add_permission` or `update_permission` while the instance
             // values are copied to the local cache.
-            let _guard_acc = self.guard_acc.lock().await;
-            // Store a copy of the current Accumulator.
-            acc = self.acc.as_ref().unwrap().clone();
+            let mut guard = self.absorbing.lock().await;
+            let absorbing = &mut *guard;
+            // Error out if there is no Accumulator allocated.
+            acc = match &absorbing.acc {
+                Some(acc) => acc.clone(),
+                None => {
+                    return Err(Error::MissingPublicKey);
+                },
+            };
             // Store a copy of the updates absorbed during this update window.
-            update = self.update.clone();
+            update = absorbing.update.clone();
             // Copy the elements added during this update window.
-            self.updating_additions = self.additions.clone();
+            updating.additions = absorbing.additions.clone();
+            // Take the Nonces deleted during this update window.
+            deletions = std::mem::take(&mut absorbing.deletions);
             // Reset the batched Update and clear the additions collection
             // for subsequent calls to `add_permission` and
             // `update_permission`.
-            self.update = Update::new();
-            self.additions.clear();
+            absorbing.update = Update::new();
+            absorbing.additions.clear();
             // Set the accumulation value for the additions in the next
             // update.
-            self.value = acc.get_value().clone();
+            absorbing.value = acc.get_value().clone();
+            // Operations absorbed from here on are pending until the next
+            // update process.
+            if let Some(storage) = &mut absorbing.storage {
+                storage.pending.clear();
+            }
             // The Accumulator Mutex gets unlocked here, allowing other
             // threads to call `add_permission` or `update_permission`.
         }
+        // Drop the deleted Permissions so that their Witnesses are neither
+        // updated nor carried into the next Permissions map.
+        for nonce in deletions.iter() {
+            updating.perms.remove(nonce);
+        }
         // Update witnesses.
-        let additions = Arc::new(StdMutex::new(
-            self.updating_additions.values_mut()
-        ));
-        let staticels = Arc::new(StdMutex::new(
-            self.updating_perms.values_mut()
-        ));
+        Self::update_witnesses(
+            &acc,
+            &update,
+            &mut updating.additions,
+            &mut updating.perms,
+        );
+        updating.updated = true;
+        Ok(())
+    }
+
+    /// Internal helper to apply a batched Update to sets of Witnesses using
+    /// every CPU.
+    ///
+    /// This code is reused by `update` and by the recomputation of Witnesses
+    /// for a key rotation.
+    fn update_witnesses(
+        acc: &Accumulator<Mpz, Map>,
+        update: &Update<Mpz, Map>,
+        additions: &mut PermissionMap,
+        perms: &mut PermissionMap,
+    ) {
+        let additions = Arc::new(StdMutex::new(additions.values_mut()));
+        let staticels = Arc::new(StdMutex::new(perms.values_mut()));
         thread::scope(|scope| {
             for _ in 0..num_cpus::get() {
                 let acc = acc.clone();
@@ -266,24 +983,334 @@ impl Worker {
                     staticels,
                 ));
             }
-            Ok(())
-        }).unwrap()
+        }).unwrap();
     }
 
     /// Finalize the update process.
-    pub async fn sync(&mut self) {
+    pub async fn sync(&self) -> Result<(), Error> {
         // Lock the update Mutex.
-        let _guard_update = self.guard_update.lock().await;
+        let mut updating = self.updating.lock().await;
+        // Nothing to do if there is no update to synchronize.
+        if !updating.updated {
+            return Ok(());
+        }
         // Insert the Permissions that were added during this update window
         // into the updated Permissions map.
-        for pair in self.updating_additions.values() {
-            self.updating_perms.insert(pair.0.nonce, pair.clone());
+        let additions: Vec<_> = updating.additions.values().cloned().collect();
+        for pair in additions {
+            updating.perms.insert(pair.0.nonce, pair);
         }
         // Lock the Accumulator Mutex so that other threads may not call
         // `add_permission` or `update_permission` while the updated
         // Permissions map is copied back into the `perms` field.
-        let _guard_acc = self.guard_acc.lock().await;
+        let mut guard = self.absorbing.lock().await;
+        let absorbing = &mut *guard;
+        // Persist the updated Permissions map along with the Operations
+        // absorbed since the update process started.
+        if let Some(storage) = &mut absorbing.storage {
+            storage.snapshot(
+                &absorbing.value,
+                &updating.perms,
+                absorbing.checkpoint_seq,
+            )?;
+        }
         // Copy the updated Permissions map into the `perms` field.
-        self.perms = self.updating_perms.clone();
+        absorbing.perms = updating.perms.clone();
+        updating.updated = false;
+        Ok(())
+    }
+
+    /// Internal helper to ensure that nothing has been absorbed or updated
+    /// since the last `sync`, so that the synchronized Permissions are all
+    /// the Permissions in the verifying accumulation.
+    fn synchronized(
+        updating: &Updating,
+        absorbing: &Absorbing,
+    ) -> Result<(), Error> {
+        if absorbing.acc.is_none() {
+            return Err(Error::MissingPublicKey);
+        }
+        if updating.updated
+            || !absorbing.additions.is_empty()
+            || !absorbing.deletions.is_empty() {
+            return Err(Error::Rotation(
+                "changes have not been synchronized".to_owned(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Return the synchronized Permissions.
+    ///
+    /// Returns an error if changes have been absorbed or Witnesses updated
+    /// since the last `sync`.
+    pub async fn permissions(&self) -> Result<Vec<Permission>, Error> {
+        let updating = self.updating.lock().await;
+        let absorbing = self.absorbing.lock().await;
+        Self::synchronized(&updating, &absorbing)?;
+        Ok(absorbing.perms.values().map(|pair| pair.0.clone()).collect())
+    }
+
+    /// Start recomputing every Witness against the new key of a rotation.
+    ///
+    /// The Checkpoint must be signed with the trusted signing key and carry
+    /// the new public key and the accumulation value of every synchronized
+    /// Permission. The Witnesses are recomputed in the background while the
+    /// current ones keep being served; poll `rotation_status` until they are
+    /// ready, then call `commit_rotation`. Starting a rotation abandons any
+    /// rotation in progress.
+    pub async fn begin_rotation(
+        self: Arc<Self>,
+        checkpoint: SignedCheckpoint,
+    ) -> Result<(), Error> {
+        let perms = {
+            let updating = self.updating.lock().await;
+            let absorbing = self.absorbing.lock().await;
+            Self::synchronized(&updating, &absorbing)?;
+            let signing_key = match &absorbing.signing_key {
+                Some(key) => key,
+                None => {
+                    return Err(Error::MissingPublicKey);
+                },
+            };
+            checkpoint.verify(signing_key)?;
+            absorbing.perms.clone()
+        };
+        *self.rotation.lock().await = Some(Rotation {
+            checkpoint: checkpoint.clone(),
+            result: None,
+        });
+        // Recompute the Witnesses on a blocking thread so that the current
+        // ones keep being served.
+        tokio::spawn(async move {
+            let task_checkpoint = checkpoint.clone();
+            let result = tokio::task::spawn_blocking(move || {
+                Self::recompute(&task_checkpoint, perms)
+            }).await;
+            let result = match result {
+                Ok(result) => result,
+                Err(err) => Err(err.to_string()),
+            };
+            // Only record the result if the rotation has not been abandoned
+            // in the meantime.
+            let mut rotation = self.rotation.lock().await;
+            if let Some(rotation) = &mut *rotation {
+                if rotation.checkpoint.signature == checkpoint.signature {
+                    rotation.result = Some(result);
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Internal helper to compute the Witnesses of a set of Permissions in
+    /// the Accumulator of a rotation.
+    ///
+    /// Every Permission is added to an empty Accumulator with the new key
+    /// as if it was absorbed during a single update window, and the
+    /// resulting value must match the one in the Authority's Checkpoint.
+    fn recompute(
+        checkpoint: &SignedCheckpoint,
+        perms: PermissionMap,
+    ) -> Result<PermissionMap, String> {
+        let checkpoint = &checkpoint.checkpoint;
+        let mut acc = Accumulator::<Mpz, Map>::with_public_key(
+            checkpoint.key.clone(),
+        );
+        let value = acc.get_value();
+        let mut update = Update::new();
+        let mut additions = HashMap::new();
+        for (perm, _) in perms.into_values() {
+            Self::add_permission_internal(
+                perm,
+                &value,
+                &mut acc,
+                &mut update,
+                &mut additions,
+            );
+        }
+        if acc.get_value() != checkpoint.verifying {
+            return Err(
+                "permissions do not match the new accumulation".to_owned(),
+            );
+        }
+        Self::update_witnesses(
+            &acc,
+            &update,
+            &mut additions,
+            &mut HashMap::new(),
+        );
+        Ok(additions)
+    }
+
+    /// Return the progress of recomputing Witnesses for a key rotation.
+    pub async fn rotation_status(&self) -> RotationStatus {
+        match &*self.rotation.lock().await {
+            Some(Rotation { result: Some(Err(err)), .. }) => RotationStatus {
+                rotating: true,
+                ready: false,
+                error: Some(err.clone()),
+            },
+            Some(rotation) => RotationStatus {
+                rotating: true,
+                ready: rotation.result.is_some(),
+                error: None,
+            },
+            None => RotationStatus {
+                rotating: false,
+                ready: false,
+                error: None,
+            },
+        }
+    }
+
+    /// Switch over to the new key and the recomputed Witnesses.
+    ///
+    /// The new key and Witnesses are persisted and replace the current
+    /// ones. The switch is not atomic with the Authority's, so the Worker
+    /// must not be stopped between the two.
+    pub async fn commit_rotation(&self) -> Result<(), Error> {
+        let mut rotation = self.rotation.lock().await;
+        let perms = match rotation.as_ref().map(|r| &r.result) {
+            Some(Some(Ok(perms))) => perms.clone(),
+            Some(_) => {
+                return Err(Error::Rotation(
+                    "witnesses have not been recomputed".to_owned(),
+                ));
+            },
+            None => {
+                return Err(Error::Rotation(
+                    "no rotation in progress".to_owned(),
+                ));
+            },
+        };
+        let signed = &rotation.as_ref().unwrap().checkpoint;
+        let checkpoint = signed.checkpoint.clone();
+        let mut acc = Accumulator::<Mpz, Map>::with_public_key(
+            checkpoint.key.clone(),
+        );
+        acc.set_value(checkpoint.verifying);
+        // Lock both Mutexes in the same order as `sync`.
+        let mut updating = self.updating.lock().await;
+        let mut guard = self.absorbing.lock().await;
+        let absorbing = &mut *guard;
+        // Changes absorbed since the rotation began would be lost.
+        Self::synchronized(&updating, absorbing)?;
+        if let Some(storage) = &mut absorbing.storage {
+            store::save(&storage.dir.join("key.json"), &checkpoint.key)?;
+            storage.pending.clear();
+            storage.snapshot(
+                &acc.get_value(),
+                &perms,
+                absorbing.checkpoint_seq,
+            )?;
+        }
+        absorbing.value = acc.get_value();
+        absorbing.acc = Some(acc);
+        absorbing.update = Update::new();
+        absorbing.additions.clear();
+        absorbing.deletions.clear();
+        absorbing.perms = perms.clone();
+        updating.additions.clear();
+        updating.perms = perms;
+        updating.updated = false;
+        *rotation = None;
+        Ok(())
+    }
+
+    /// Abandon the rotation in progress, if any.
+    pub async fn abort_rotation(&self) {
+        *self.rotation.lock().await = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{authority::Authority, request::UpdateRequest};
+
+    /// Return a fresh temporary data directory.
+    fn temp_dir() -> String {
+        std::env::temp_dir()
+            .join(format!(
+                "compauth-worker-{}-{}",
+                std::process::id(),
+                rand::random::<u64>(),
+            ))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    fn perm(action: &str) -> Permission {
+        serde_json::from_value(serde_json::json!({
+            "nonce": 0,
+            "actions": [action],
+            "version": 0,
+        })).unwrap()
+    }
+
+    /// Add a batch of Permissions to the Authority and the Worker.
+    async fn add(
+        auth: &Authority,
+        worker: &Worker,
+        actions: &[&str],
+    ) -> Vec<Permission> {
+        let perms = actions.iter().map(|action| perm(action)).collect();
+        let perms: Vec<Permission> = auth.add_permissions(perms).await
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        worker.add_permissions(perms.clone()).await.unwrap();
+        perms
+    }
+
+    /// Close an update window like the Synchronizer does.
+    async fn window(auth: &Authority, worker: &Worker) {
+        auth.update().await.unwrap();
+        worker.update().await.unwrap();
+        auth.sync().await.unwrap();
+        worker.sync().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn open_replays_batches() {
+        let dir = temp_dir();
+        let auth = Authority::new();
+        let signing_key = Some(auth.get_signing_key());
+        let worker = Worker::open(&dir, signing_key).unwrap();
+        let checkpoint = auth.checkpoint().await;
+        worker.set_key(KeyRequest { checkpoint }).await.unwrap();
+        let perms = add(&auth, &worker, &["tick", "tock"]).await;
+        window(&auth, &worker).await;
+        // Log a batch of additions and a batch of updates after the
+        // snapshot taken by `sync`.
+        let mut current = add(&auth, &worker, &["tack"]).await;
+        let mut reqs = Vec::new();
+        for perm in perms {
+            let update = Permission {
+                version: perm.version + 1,
+                ..perm.clone()
+            };
+            let witness = worker.witness(perm.nonce).await.unwrap().unwrap();
+            reqs.push(UpdateRequest { perm, witness, update });
+        }
+        let res = auth.update_permissions(reqs).await;
+        for res in res.results.iter() {
+            current.push(res.clone().into_result().unwrap());
+        }
+        worker.update_permissions(res).await.unwrap();
+        drop(worker);
+        // The batches are absorbed again, so that the next window computes
+        // every Witness against the Authority's accumulation.
+        let worker = Worker::open(&dir, signing_key).unwrap();
+        window(&auth, &worker).await;
+        let mut acc = Accumulator::<Mpz, Map>::with_public_key(auth.get_key());
+        acc.set_value(auth.values().await.verifying);
+        for perm in current {
+            let stored = worker.permission(perm.nonce).await.unwrap();
+            assert!(stored.as_ref() == Some(&perm));
+            let witness = worker.witness(perm.nonce).await.unwrap().unwrap();
+            verifier::verify_membership(&acc, &perm, &witness).unwrap();
+        }
     }
 }
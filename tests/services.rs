@@ -0,0 +1,370 @@
+//! Tests that run the Authority and the Worker as separate processes, as in
+//! a deployment, and drive them with a Synchronizer.
+
+use compauth::{
+    client::Client as SyncClient,
+    checkpoint::parse_public_key,
+    config::Config,
+    error::Error,
+    peer::Credentials,
+    permission::Permission,
+    request::{
+        ActionRequest,
+        ActionsRequest,
+        CreateRequest,
+        UpdateActionsRequest,
+    },
+    synchronizer::Synchronizer,
+    tenant,
+    util::{from_bytes, now_millis, Client},
+};
+use hyper::body::to_bytes;
+use std::{
+    fs,
+    net::{TcpListener, TcpStream},
+    path::PathBuf,
+    process::{Child, Command},
+    thread::sleep,
+    time::Duration,
+};
+
+const AUTHORITY_SECRET: &str =
+    "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+const WORKER_SECRET: &str =
+    "202122232425262728292a2b2c2d2e2f303132333435363738393a3b3c3d3e3f";
+const KEY_SECRET: &str =
+    "404142434445464748494a4b4c4d4e4f505152535455565758595a5b5c5d5e5f";
+
+/// The Authority and the Worker running as child processes, which are
+/// killed when dropped.
+struct Services {
+    config: Config,
+    dir: PathBuf,
+    children: Vec<Child>,
+}
+
+impl Services {
+
+    /// Start the Authority and the Worker with fresh data directories.
+    async fn start() -> Self {
+        Self::with_tenants(&[]).await
+    }
+
+    /// Start the services like `start`, serving tenants besides the
+    /// default one.
+    async fn with_tenants(tenants: &[&str]) -> Self {
+        let dir = std::env::temp_dir().join(format!(
+            "compauth-services-{}-{}",
+            std::process::id(),
+            rand::random::<u64>(),
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        let path = |name: &str| dir.join(name).to_string_lossy().into_owned();
+        let config = Config {
+            synchronizer_addr: free_addr(),
+            authority_addr: free_addr(),
+            worker_addr: free_addr(),
+            authority_data_dir: path("authority"),
+            worker_data_dir: path("worker"),
+            authority_secret: AUTHORITY_SECRET.to_owned(),
+            worker_secret: WORKER_SECRET.to_owned(),
+            authority_key_secret: KEY_SECRET.to_owned(),
+            tenants: tenants.join(","),
+            ..Config::default()
+        };
+        let mut services = Services {
+            config,
+            dir,
+            children: Vec::new(),
+        };
+        services.spawn("authority", &services.config.authority_addr.clone());
+        // The Worker only accepts Checkpoints signed with the key the
+        // Authority generated.
+        let key = services.signing_key(None).await;
+        services.config.authority_signing_key = key;
+        let mut keys = Vec::new();
+        for tenant in tenants {
+            let key = services.signing_key(Some(tenant)).await;
+            keys.push(format!("{}={}", tenant, key));
+        }
+        services.config.tenant_signing_keys = keys.join(",");
+        services.spawn("worker", &services.config.worker_addr.clone());
+        services
+    }
+
+    /// Run a service with the current configuration and wait until it
+    /// accepts connections.
+    fn spawn(&mut self, name: &str, addr: &str) {
+        let path = self.dir.join(format!("{}.toml", name));
+        fs::write(&path, toml::to_string(&self.config).unwrap()).unwrap();
+        let exe = match name {
+            "authority" => env!("CARGO_BIN_EXE_authority"),
+            "worker" => env!("CARGO_BIN_EXE_worker"),
+            _ => env!("CARGO_BIN_EXE_synchronizer"),
+        };
+        let child = Command::new(exe)
+            .env("COMPAUTH_CONFIG", &path)
+            .spawn()
+            .unwrap();
+        self.children.push(child);
+        // Generating the private keys of several tenants takes a while.
+        for _ in 0..2400 {
+            if TcpStream::connect(addr).is_ok() {
+                return;
+            }
+            sleep(Duration::from_millis(50));
+        }
+        panic!("{} did not start", name);
+    }
+
+    /// Return a client of the Authority of a tenant, or of the default
+    /// tenant, that signs its requests.
+    fn authority(&self, tenant: Option<&str>) -> Client {
+        let prefix = tenant.map(tenant::prefix).unwrap_or_default();
+        self.config.authority_client().unwrap()
+            .with_prefix(&prefix)
+            .with_credentials(Credentials::new(AUTHORITY_SECRET).unwrap())
+    }
+
+    /// Return the hex encoded key the Authority of a tenant signs
+    /// Checkpoints with.
+    async fn signing_key(&self, tenant: Option<&str>) -> String {
+        let auth = self.authority(tenant);
+        let resp = auth.get("/signing-key").await.unwrap();
+        from_bytes(&to_bytes(resp.into_body()).await).unwrap()
+    }
+
+    /// Run the Synchronizer's service and return a client of its public
+    /// API.
+    fn serve(&mut self) -> SyncClient {
+        self.spawn("synchronizer", &self.config.synchronizer_addr.clone());
+        SyncClient::new(&self.config.synchronizer_addr)
+    }
+
+    /// Create a Synchronizer of the services, which keys the Worker.
+    async fn synchronizer(&self) -> Synchronizer {
+        Synchronizer::new(&self.config).await.unwrap()
+    }
+}
+
+impl Drop for Services {
+    fn drop(&mut self) {
+        for child in self.children.iter_mut() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Return a local address that is free to listen on.
+fn free_addr() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().to_string()
+}
+
+/// Perform an action with a Permission through a Synchronizer.
+async fn act(
+    sync: &Synchronizer,
+    perm: Permission,
+    action: &str,
+) -> Result<(), Error> {
+    sync.action(perm, action.to_owned(), Default::default(), None).await
+}
+
+/// Return a request to create a Permission for an action.
+fn create(action: &str) -> CreateRequest {
+    vec![action.to_owned()].into()
+}
+
+#[tokio::test]
+async fn sweep_revokes_expired_permissions_in_one_window() {
+    let services = Services::start().await;
+    let sync = services.synchronizer().await;
+    let expires_at = Some(now_millis() + 2000);
+    let mut expired = Vec::new();
+    for action in ["tick", "tock"] {
+        let req = CreateRequest { expires_at, ..create(action) };
+        expired.push(sync.add_permission(req).await.unwrap());
+    }
+    sync.close_window().await.unwrap();
+    tokio::time::sleep(Duration::from_millis(2000)).await;
+    // An addition earlier in the window does not keep the others from
+    // being revoked.
+    let kept = sync.add_permission(create("tack")).await.unwrap();
+    assert_eq!(sync.sweep().await.unwrap(), 2);
+    sync.close_window().await.unwrap();
+    for perm in expired {
+        let res = sync.witness(perm.nonce).await;
+        assert_eq!(res.err(), Some(Error::UnknownPermission));
+    }
+    act(&sync, kept, "tack").await.unwrap();
+    assert_eq!(sync.sweep().await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn batches_reach_the_worker() {
+    let services = Services::start().await;
+    let sync = services.synchronizer().await;
+    // A Permission the Authority refuses does not affect the others.
+    let invalid = CreateRequest {
+        not_before: Some(2),
+        expires_at: Some(1),
+        ..create("tuck")
+    };
+    let reqs = vec![create("tick"), invalid, create("tock")];
+    let added = sync.add_permissions(reqs).await.unwrap();
+    assert_eq!(added[1].as_ref().err(), Some(&Error::InvalidValidity));
+    let perms: Vec<Permission> = added.into_iter()
+        .filter_map(Result::ok)
+        .collect();
+    assert_eq!(perms.len(), 2);
+    sync.close_window().await.unwrap();
+    // Every update of a batch is applied, also after an addition in the
+    // same window.
+    sync.add_permission(create("tack")).await.unwrap();
+    let reqs = perms.iter()
+        .map(|perm| UpdateActionsRequest {
+            perm: perm.clone(),
+            actions: vec!["tuck".to_owned()],
+            roles: None,
+            conditions: None,
+        })
+        .collect();
+    let updated = sync.update_permissions(reqs).await.unwrap();
+    sync.close_window().await.unwrap();
+    for res in updated {
+        let perm = res.unwrap();
+        assert_eq!(sync.permission(perm.nonce).await.unwrap().version, 1);
+        act(&sync, perm, "tuck").await.unwrap();
+    }
+    // The previous versions are no longer members.
+    for perm in perms {
+        let res = act(&sync, perm, "tick").await;
+        assert_eq!(res.err(), Some(Error::WitnessVerification));
+    }
+}
+
+#[tokio::test]
+async fn actions_are_decided_by_the_authority() {
+    let services = Services::start().await;
+    let sync = services.synchronizer().await;
+    let tick = sync.add_permission(create("tick")).await.unwrap();
+    let tock = sync.add_permission(create("tock")).await.unwrap();
+    // A Permission that was never added has no Witness.
+    let unknown = Permission {
+        nonce: 1.into(),
+        ..tick.clone()
+    };
+    sync.close_window().await.unwrap();
+    let req = ActionsRequest {
+        perms: vec![tick, tock, unknown],
+        actions: vec!["tick".to_owned(), "tock".to_owned()],
+        attributes: Default::default(),
+        proofs: Vec::new(),
+    };
+    let decisions = sync.actions(req).await.unwrap();
+    assert_eq!(decisions, vec![
+        vec![Ok(()), Err(Error::NotGranted)],
+        vec![Err(Error::NotGranted), Ok(())],
+        vec![Err(Error::UnknownPermission), Err(Error::UnknownPermission)],
+    ]);
+}
+
+#[tokio::test]
+async fn defining_roles_requires_the_authority_secret() {
+    let mut services = Services::start().await;
+    let client = services.serve();
+    let actions = vec!["tickets:*".to_owned()];
+    // Unsigned requests and requests signed with another secret are
+    // refused.
+    let res = client.define_role("support", &actions).await;
+    assert_eq!(res.err(), Some(Error::Unauthenticated));
+    let credentials = Credentials::new(WORKER_SECRET).unwrap();
+    let res = client.clone()
+        .with_credentials(credentials)
+        .define_role("support", &actions)
+        .await;
+    assert_eq!(res.err(), Some(Error::Unauthenticated));
+    assert!(client.roles().await.unwrap().is_empty());
+    let credentials = Credentials::new(AUTHORITY_SECRET).unwrap();
+    let role = client.with_credentials(credentials)
+        .define_role("support", &actions)
+        .await
+        .unwrap();
+    assert_eq!(role.actions, actions);
+}
+
+#[tokio::test]
+async fn rotation_recomputes_witnesses() {
+    let services = Services::start().await;
+    let sync = services.synchronizer().await;
+    let tick = sync.add_permission(create("tick")).await.unwrap();
+    let tock = sync.add_permission(create("tock")).await.unwrap();
+    sync.close_window().await.unwrap();
+    let key = sync.checkpoint().await.checkpoint.key;
+    let witness = sync.witness(tick.nonce).await.unwrap();
+    let rotated = sync.rotate().await.unwrap().checkpoint.key;
+    assert!(rotated != key);
+    assert!(sync.checkpoint().await.checkpoint.key == rotated);
+    // The existing Permissions are verified with their new Witnesses.
+    assert!(sync.witness(tick.nonce).await.unwrap().u != witness.u);
+    act(&sync, tick.clone(), "tick").await.unwrap();
+    act(&sync, tock, "tock").await.unwrap();
+    // Permissions can be changed again under the new key.
+    let req = UpdateActionsRequest {
+        perm: tick,
+        actions: vec!["tuck".to_owned()],
+        roles: None,
+        conditions: None,
+    };
+    let tuck = sync.update_permission(req).await.unwrap();
+    sync.close_window().await.unwrap();
+    act(&sync, tuck, "tuck").await.unwrap();
+}
+
+#[tokio::test]
+async fn tenants_are_isolated() {
+    let services = Services::with_tenants(&["billing", "support"]).await;
+    let config = &services.config;
+    let billing = Synchronizer::for_tenant(config, Some("billing"));
+    let billing = billing.await.unwrap();
+    let support = Synchronizer::for_tenant(config, Some("support"));
+    let support = support.await.unwrap();
+    let perm = billing.add_permission(create("tick")).await.unwrap();
+    billing.close_window().await.unwrap();
+    support.close_window().await.unwrap();
+    act(&billing, perm.clone(), "tick").await.unwrap();
+    // The Permission and its Witness are unknown to the other tenant, and
+    // are not verified against its accumulator.
+    let res = support.witness(perm.nonce).await;
+    assert_eq!(res.err(), Some(Error::UnknownPermission));
+    let req = ActionRequest {
+        perm: perm.clone(),
+        witness: billing.witness(perm.nonce).await.unwrap(),
+        action: "tick".to_owned(),
+        attributes: Default::default(),
+        proof: None,
+    };
+    let res = services.authority(Some("support")).post("/action", req).await;
+    assert_eq!(res.err(), Some(Error::WitnessVerification));
+    // Each tenant signs its Checkpoints with its own key.
+    let key = services.signing_key(Some("support")).await;
+    let key = parse_public_key(&key).unwrap();
+    let checkpoint = billing.checkpoint().await;
+    assert_eq!(checkpoint.verify(&key).err(), Some(Error::InvalidCheckpoint));
+    assert!(billing.signing_key() != support.signing_key());
+    assert!(billing.checkpoint().await.checkpoint.key
+        != support.checkpoint().await.checkpoint.key);
+    // Each tenant keeps its state in its own directory.
+    for tenant in ["billing", "support"] {
+        let config = config.for_tenant(tenant);
+        let authority = PathBuf::from(&config.authority_data_dir);
+        let worker = PathBuf::from(&config.worker_data_dir);
+        assert!(authority.ends_with(format!("tenants/{}", tenant)));
+        assert!(authority.join("key.enc").exists());
+        assert!(authority.join("signing_key.json").exists());
+        assert!(worker.ends_with(format!("tenants/{}", tenant)));
+        assert!(worker.join("key.json").exists());
+    }
+}